@@ -0,0 +1,99 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Run with TARGET_URI, API_KEY and AUDIO_FILE set, after building the native
+// library with `make build-ffi`.
+
+using System.Text;
+using System.Text.Json;
+using Xunit;
+
+namespace AttestedOhttp.Tests;
+
+public sealed class OhttpClientFixture : IDisposable
+{
+    private const string KmsUrl = "https://accconfinferenceprod.confidential-ledger.azure.com";
+    private const string KmsCert = "/tmp/service_cert.pem";
+
+    public OhttpClientFixture()
+    {
+        using var http = new HttpClient();
+        var network = JsonDocument.Parse(http.GetStringAsync(KmsUrl + "/node/network").Result);
+        File.WriteAllText(KmsCert, network.RootElement.GetProperty("service_certificate").GetString());
+        Client = OhttpClient.Create(new OhttpClientOptions { KmsUrl = KmsUrl, KmsCert = KmsCert });
+    }
+
+    public OhttpClient Client { get; }
+
+    public void Dispose() => Client.Dispose();
+}
+
+public class OhttpClientTests : IClassFixture<OhttpClientFixture>
+{
+    private static readonly string TargetUri = Environment.GetEnvironmentVariable("TARGET_URI") ?? "";
+    private static readonly string ApiKey = Environment.GetEnvironmentVariable("API_KEY") ?? "";
+    private static readonly string AudioFile = Environment.GetEnvironmentVariable("AUDIO_FILE") ?? "";
+
+    private readonly OhttpClient _client;
+
+    public OhttpClientTests(OhttpClientFixture fixture)
+    {
+        _client = fixture.Client;
+    }
+
+    /// <summary>A multipart transcription request for the audio file.</summary>
+    private static OhttpRequest Transcription(string apiKey, bool attestationToken = false)
+    {
+        const string boundary = "ohttp-client-boundary";
+        using var body = new MemoryStream();
+        void Write(string text) => body.Write(Encoding.UTF8.GetBytes(text));
+        Write($"--{boundary}\r\nContent-Disposition: form-data; name=\"response_format\"\r\n\r\njson\r\n");
+        Write($"--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{Path.GetFileName(AudioFile)}\"\r\n");
+        Write("Content-Type: audio/mpeg\r\n\r\n");
+        body.Write(File.ReadAllBytes(AudioFile));
+        Write($"\r\n--{boundary}--\r\n");
+
+        var outerHeaders = new Dictionary<string, string> { ["api-key"] = apiKey };
+        if (attestationToken)
+        {
+            outerHeaders["x-attestation-token"] = "true";
+        }
+        return new OhttpRequest
+        {
+            Headers = new Dictionary<string, string> { ["content-type"] = $"multipart/form-data; boundary={boundary}" },
+            Body = body.ToArray(),
+            OuterHeaders = outerHeaders,
+        };
+    }
+
+    [Fact]
+    public async Task Post()
+    {
+        using var response = await _client.PostAsync(TargetUri, Transcription(ApiKey));
+        Assert.Equal(200, response.StatusCode);
+        var body = JsonDocument.Parse(await response.ReadAsStringAsync());
+        Assert.True(body.RootElement.TryGetProperty("text", out _));
+    }
+
+    [Fact]
+    public async Task Streaming()
+    {
+        using var response = await _client.PostAsync(TargetUri, Transcription(ApiKey));
+        using var reader = new StreamReader(response.GetBodyStream());
+        Assert.Contains("text", await reader.ReadToEndAsync());
+    }
+
+    [Fact]
+    public async Task AttestationToken()
+    {
+        using var response = await _client.PostAsync(TargetUri, Transcription(ApiKey, attestationToken: true));
+        Assert.Equal(200, response.StatusCode);
+        Assert.Equal(response.Headers["x-attestation-token"], response.AttestationToken);
+    }
+
+    [Fact]
+    public async Task InvalidApiKey()
+    {
+        await Assert.ThrowsAsync<RelayException>(() => _client.PostAsync(TargetUri, Transcription("invalid_key")));
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+using System.Runtime.InteropServices;
+
+namespace AttestedOhttp;
+
+/// <summary>Outcome of a native call, as in ohttp_client.h.</summary>
+public enum OhttpStatus
+{
+    Ok = 0,
+    InvalidArgument,
+    KmsError,
+    AttestationError,
+    RelayError,
+    EncapsulationError,
+    Error,
+}
+
+[StructLayout(LayoutKind.Sequential)]
+internal struct OhttpBuffer
+{
+    public IntPtr Data;
+    public nuint Len;
+}
+
+/// <summary>P/Invoke declarations of the C interface of ohttp-client-ffi.</summary>
+internal static class NativeMethods
+{
+    private const string Library = "ohttp_client";
+
+    [DllImport(Library)]
+    internal static extern IntPtr ohttp_last_error();
+
+    [DllImport(Library)]
+    internal static extern IntPtr ohttp_builder_new();
+
+    [DllImport(Library)]
+    internal static extern void ohttp_builder_free(IntPtr builder);
+
+    [DllImport(Library)]
+    internal static extern OhttpStatus ohttp_builder_kms_url(
+        IntPtr builder, [MarshalAs(UnmanagedType.LPUTF8Str)] string? kmsUrl);
+
+    [DllImport(Library)]
+    internal static extern OhttpStatus ohttp_builder_kms_cert(
+        IntPtr builder, [MarshalAs(UnmanagedType.LPUTF8Str)] string? kmsCert);
+
+    [DllImport(Library)]
+    internal static extern OhttpStatus ohttp_builder_config(
+        IntPtr builder, [MarshalAs(UnmanagedType.LPUTF8Str)] string? config);
+
+    [DllImport(Library)]
+    internal static extern OhttpStatus ohttp_builder_config_file(
+        IntPtr builder, [MarshalAs(UnmanagedType.LPUTF8Str)] string? configFile);
+
+    [DllImport(Library)]
+    internal static extern OhttpStatus ohttp_builder_discover_dns(
+        IntPtr builder, [MarshalAs(UnmanagedType.LPUTF8Str)] string? name);
+
+    [DllImport(Library)]
+    internal static extern OhttpStatus ohttp_builder_attestation(
+        IntPtr builder,
+        [MarshalAs(UnmanagedType.LPUTF8Str)] string issuer,
+        [MarshalAs(UnmanagedType.LPUTF8Str)] string? audience);
+
+    [DllImport(Library)]
+    internal static extern OhttpStatus ohttp_builder_build(IntPtr builder, out ClientHandle client);
+
+    [DllImport(Library)]
+    internal static extern void ohttp_client_free(IntPtr client);
+
+    [DllImport(Library)]
+    internal static extern OhttpStatus ohttp_client_post(
+        ClientHandle client,
+        [MarshalAs(UnmanagedType.LPUTF8Str)] string url,
+        [MarshalAs(UnmanagedType.LPUTF8Str)] string? method,
+        [MarshalAs(UnmanagedType.LPUTF8Str)] string? targetPath,
+        [MarshalAs(UnmanagedType.LPArray, ArraySubType = UnmanagedType.LPUTF8Str)] string[] headers,
+        nuint headersLen,
+        byte[]? body,
+        nuint bodyLen,
+        [MarshalAs(UnmanagedType.LPArray, ArraySubType = UnmanagedType.LPUTF8Str)] string[] outerHeaders,
+        nuint outerHeadersLen,
+        out ResponseHandle response);
+
+    [DllImport(Library)]
+    internal static extern OhttpStatus ohttp_client_post_raw(
+        ClientHandle client,
+        [MarshalAs(UnmanagedType.LPUTF8Str)] string url,
+        byte[] request,
+        nuint requestLen,
+        [MarshalAs(UnmanagedType.LPArray, ArraySubType = UnmanagedType.LPUTF8Str)] string[] outerHeaders,
+        nuint outerHeadersLen,
+        out ResponseHandle response);
+
+    [DllImport(Library)]
+    internal static extern ushort ohttp_response_status(ResponseHandle response);
+
+    [DllImport(Library)]
+    internal static extern IntPtr ohttp_response_headers(ResponseHandle response);
+
+    [DllImport(Library)]
+    internal static extern IntPtr ohttp_response_attestation_token(ResponseHandle response);
+
+    [DllImport(Library)]
+    internal static extern OhttpStatus ohttp_response_next_chunk(ResponseHandle response, out OhttpBuffer chunk);
+
+    [DllImport(Library)]
+    internal static extern void ohttp_response_free(IntPtr response);
+
+    [DllImport(Library)]
+    internal static extern void ohttp_buffer_free(OhttpBuffer buffer);
+
+    [DllImport(Library)]
+    internal static extern void ohttp_string_free(IntPtr value);
+
+    /// <summary>Throws the exception matching a failed call.</summary>
+    internal static void Check(OhttpStatus status)
+    {
+        if (status != OhttpStatus.Ok)
+        {
+            var message = Marshal.PtrToStringUTF8(ohttp_last_error()) ?? status.ToString();
+            throw OhttpException.FromStatus(status, message);
+        }
+    }
+
+    /// <summary>Takes ownership of a string returned by the library.</summary>
+    internal static string? TakeString(IntPtr value)
+    {
+        if (value == IntPtr.Zero)
+        {
+            return null;
+        }
+        try
+        {
+            return Marshal.PtrToStringUTF8(value);
+        }
+        finally
+        {
+            ohttp_string_free(value);
+        }
+    }
+}
+
+internal sealed class ClientHandle : SafeHandle
+{
+    public ClientHandle() : base(IntPtr.Zero, true) { }
+
+    public override bool IsInvalid => handle == IntPtr.Zero;
+
+    protected override bool ReleaseHandle()
+    {
+        NativeMethods.ohttp_client_free(handle);
+        return true;
+    }
+}
+
+internal sealed class ResponseHandle : SafeHandle
+{
+    public ResponseHandle() : base(IntPtr.Zero, true) { }
+
+    public override bool IsInvalid => handle == IntPtr.Zero;
+
+    protected override bool ReleaseHandle()
+    {
+        NativeMethods.ohttp_response_free(handle);
+        return true;
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+namespace AttestedOhttp;
+
+/// <summary>How a client obtains its key configuration.</summary>
+public sealed class OhttpClientOptions
+{
+    /// <summary>URL of the KMS to obtain the key configuration from.</summary>
+    public string? KmsUrl { get; init; }
+
+    /// <summary>Path of the certificate of the KMS, verified by its receipt.</summary>
+    public string? KmsCert { get; init; }
+
+    /// <summary>Key configuration as hex.</summary>
+    public string? Config { get; init; }
+
+    /// <summary>Path of a key configuration saved by the CLI with --save-config.</summary>
+    public string? ConfigFile { get; init; }
+
+    /// <summary>Gateway name whose HTTPS records advertise the key configuration.</summary>
+    public string? DiscoverDns { get; init; }
+
+    /// <summary>Issuer against which the attestation token of the gateway is verified.</summary>
+    public string? AttestationIssuer { get; init; }
+
+    /// <summary>Audience that the attestation token must be issued for.</summary>
+    public string? AttestationAudience { get; init; }
+}
+
+/// <summary>The inner request sent by <see cref="OhttpClient.PostAsync"/>.</summary>
+public sealed class OhttpRequest
+{
+    /// <summary>Path of the request at the gateway.</summary>
+    public string TargetPath { get; init; } = "/";
+
+    public string Method { get; init; } = "POST";
+
+    public IDictionary<string, string> Headers { get; init; } = new Dictionary<string, string>();
+
+    /// <summary>Body sent as is, with the content type given in <see cref="Headers"/>.</summary>
+    public byte[]? Body { get; init; }
+
+    /// <summary>Headers of the outer request to the relay, e.g. an API key.</summary>
+    public IDictionary<string, string> OuterHeaders { get; init; } = new Dictionary<string, string>();
+}
+
+/// <summary>
+/// A client sending requests through a relay to a gateway whose key
+/// configuration was verified when the client was created. Native calls
+/// block, so the asynchronous methods run them on the thread pool.
+/// </summary>
+public sealed class OhttpClient : IDisposable
+{
+    private readonly ClientHandle _handle;
+
+    private OhttpClient(ClientHandle handle)
+    {
+        _handle = handle;
+    }
+
+    /// <summary>Creates a client, obtaining and verifying its key configuration.</summary>
+    public static OhttpClient Create(OhttpClientOptions options)
+    {
+        var builder = NativeMethods.ohttp_builder_new();
+        try
+        {
+            NativeMethods.Check(NativeMethods.ohttp_builder_kms_url(builder, options.KmsUrl));
+            NativeMethods.Check(NativeMethods.ohttp_builder_kms_cert(builder, options.KmsCert));
+            NativeMethods.Check(NativeMethods.ohttp_builder_config(builder, options.Config));
+            NativeMethods.Check(NativeMethods.ohttp_builder_config_file(builder, options.ConfigFile));
+            NativeMethods.Check(NativeMethods.ohttp_builder_discover_dns(builder, options.DiscoverDns));
+            if (options.AttestationIssuer is not null)
+            {
+                NativeMethods.Check(NativeMethods.ohttp_builder_attestation(
+                    builder, options.AttestationIssuer, options.AttestationAudience));
+            }
+        }
+        catch
+        {
+            NativeMethods.ohttp_builder_free(builder);
+            throw;
+        }
+
+        // Building consumes the builder, whether it succeeds or not
+        var status = NativeMethods.ohttp_builder_build(builder, out var handle);
+        NativeMethods.Check(status);
+        return new OhttpClient(handle);
+    }
+
+    public static Task<OhttpClient> CreateAsync(OhttpClientOptions options, CancellationToken cancellationToken = default) =>
+        Task.Run(() => Create(options), cancellationToken);
+
+    /// <summary>Encapsulates and sends a request through the relay at <paramref name="url"/>.</summary>
+    public OhttpResponse Post(string url, OhttpRequest request)
+    {
+        var headers = HeaderLines(request.Headers);
+        var outerHeaders = HeaderLines(request.OuterHeaders);
+        var status = NativeMethods.ohttp_client_post(
+            _handle,
+            url,
+            request.Method,
+            request.TargetPath,
+            headers,
+            (nuint)headers.Length,
+            request.Body,
+            (nuint)(request.Body?.Length ?? 0),
+            outerHeaders,
+            (nuint)outerHeaders.Length,
+            out var response);
+        NativeMethods.Check(status);
+        return new OhttpResponse(response);
+    }
+
+    public Task<OhttpResponse> PostAsync(string url, OhttpRequest request, CancellationToken cancellationToken = default) =>
+        Task.Run(() => Post(url, request), cancellationToken);
+
+    /// <summary>Encapsulates and sends an HTTP/1.1 request message as is.</summary>
+    public OhttpResponse PostRaw(string url, byte[] httpRequest, IDictionary<string, string>? outerHeaders = null)
+    {
+        var lines = HeaderLines(outerHeaders ?? new Dictionary<string, string>());
+        var status = NativeMethods.ohttp_client_post_raw(
+            _handle, url, httpRequest, (nuint)httpRequest.Length, lines, (nuint)lines.Length, out var response);
+        NativeMethods.Check(status);
+        return new OhttpResponse(response);
+    }
+
+    public Task<OhttpResponse> PostRawAsync(
+        string url,
+        byte[] httpRequest,
+        IDictionary<string, string>? outerHeaders = null,
+        CancellationToken cancellationToken = default) =>
+        Task.Run(() => PostRaw(url, httpRequest, outerHeaders), cancellationToken);
+
+    public void Dispose() => _handle.Dispose();
+
+    private static string[] HeaderLines(IDictionary<string, string> headers) =>
+        headers.Select(header => $"{header.Key}: {header.Value}").ToArray();
+}
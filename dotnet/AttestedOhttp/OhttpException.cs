@@ -0,0 +1,55 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+namespace AttestedOhttp;
+
+/// <summary>An oblivious request failed.</summary>
+public class OhttpException : Exception
+{
+    public OhttpException(OhttpStatus status, string message) : base(message)
+    {
+        Status = status;
+    }
+
+    public OhttpStatus Status { get; }
+
+    internal static OhttpException FromStatus(OhttpStatus status, string message) => status switch
+    {
+        OhttpStatus.InvalidArgument => new OhttpArgumentException(message),
+        OhttpStatus.KmsError => new KmsException(message),
+        OhttpStatus.AttestationError => new AttestationException(message),
+        OhttpStatus.RelayError => new RelayException(message),
+        OhttpStatus.EncapsulationError => new EncapsulationException(message),
+        _ => new OhttpException(status, message),
+    };
+}
+
+/// <summary>An argument was rejected by the native library.</summary>
+public class OhttpArgumentException : OhttpException
+{
+    public OhttpArgumentException(string message) : base(OhttpStatus.InvalidArgument, message) { }
+}
+
+/// <summary>The key configuration could not be obtained or verified.</summary>
+public class KmsException : OhttpException
+{
+    public KmsException(string message) : base(OhttpStatus.KmsError, message) { }
+}
+
+/// <summary>The attestation token was missing, invalid or violated the policy.</summary>
+public class AttestationException : OhttpException
+{
+    public AttestationException(string message) : base(OhttpStatus.AttestationError, message) { }
+}
+
+/// <summary>The relay could not be reached or rejected the request.</summary>
+public class RelayException : OhttpException
+{
+    public RelayException(string message) : base(OhttpStatus.RelayError, message) { }
+}
+
+/// <summary>The request could not be encapsulated or the response decapsulated.</summary>
+public class EncapsulationException : OhttpException
+{
+    public EncapsulationException(string message) : base(OhttpStatus.EncapsulationError, message) { }
+}
@@ -0,0 +1,148 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+using System.Runtime.InteropServices;
+using System.Text;
+
+namespace AttestedOhttp;
+
+/// <summary>A decapsulated response, whose body is read in chunks as it arrives.</summary>
+public sealed class OhttpResponse : IDisposable
+{
+    private readonly ResponseHandle _handle;
+
+    internal OhttpResponse(ResponseHandle handle)
+    {
+        _handle = handle;
+        StatusCode = NativeMethods.ohttp_response_status(handle);
+        Headers = ParseHeaders(NativeMethods.TakeString(NativeMethods.ohttp_response_headers(handle)));
+        AttestationToken = NativeMethods.TakeString(NativeMethods.ohttp_response_attestation_token(handle));
+    }
+
+    public int StatusCode { get; }
+
+    public IReadOnlyDictionary<string, string> Headers { get; }
+
+    /// <summary>The attestation token returned by the gateway, if any.</summary>
+    public string? AttestationToken { get; }
+
+    /// <summary>Returns the next chunk of the body, or null at its end.</summary>
+    public byte[]? ReadChunk()
+    {
+        NativeMethods.Check(NativeMethods.ohttp_response_next_chunk(_handle, out var chunk));
+        if (chunk.Data == IntPtr.Zero)
+        {
+            return null;
+        }
+        try
+        {
+            var data = new byte[(int)chunk.Len];
+            Marshal.Copy(chunk.Data, data, 0, data.Length);
+            return data;
+        }
+        finally
+        {
+            NativeMethods.ohttp_buffer_free(chunk);
+        }
+    }
+
+    public Task<byte[]?> ReadChunkAsync(CancellationToken cancellationToken = default) =>
+        Task.Run(ReadChunk, cancellationToken);
+
+    /// <summary>The chunks of the body as they are decapsulated.</summary>
+    public async IAsyncEnumerable<byte[]> ReadChunksAsync(
+        [System.Runtime.CompilerServices.EnumeratorCancellation] CancellationToken cancellationToken = default)
+    {
+        while (await ReadChunkAsync(cancellationToken).ConfigureAwait(false) is { } chunk)
+        {
+            yield return chunk;
+        }
+    }
+
+    /// <summary>The remainder of the body as a read-only stream.</summary>
+    public Stream GetBodyStream() => new BodyStream(this);
+
+    /// <summary>Reads the remainder of the body.</summary>
+    public async Task<byte[]> ReadAsBytesAsync(CancellationToken cancellationToken = default)
+    {
+        using var body = new MemoryStream();
+        await foreach (var chunk in ReadChunksAsync(cancellationToken).ConfigureAwait(false))
+        {
+            body.Write(chunk);
+        }
+        return body.ToArray();
+    }
+
+    /// <summary>Reads the remainder of the body as UTF-8 text.</summary>
+    public async Task<string> ReadAsStringAsync(CancellationToken cancellationToken = default) =>
+        Encoding.UTF8.GetString(await ReadAsBytesAsync(cancellationToken).ConfigureAwait(false));
+
+    public void Dispose() => _handle.Dispose();
+
+    private static IReadOnlyDictionary<string, string> ParseHeaders(string? lines)
+    {
+        var headers = new Dictionary<string, string>(StringComparer.OrdinalIgnoreCase);
+        foreach (var line in (lines ?? "").Split("\r\n", StringSplitOptions.RemoveEmptyEntries))
+        {
+            var separator = line.IndexOf(':');
+            if (separator > 0)
+            {
+                var name = line[..separator];
+                var value = line[(separator + 1)..].Trim();
+                headers[name] = headers.TryGetValue(name, out var previous) ? $"{previous}, {value}" : value;
+            }
+        }
+        return headers;
+    }
+
+    /// <summary>Reads the body of a response chunk by chunk.</summary>
+    private sealed class BodyStream : Stream
+    {
+        private readonly OhttpResponse _response;
+        private byte[] _chunk = Array.Empty<byte>();
+        private int _offset;
+        private bool _ended;
+
+        public BodyStream(OhttpResponse response)
+        {
+            _response = response;
+        }
+
+        public override bool CanRead => true;
+        public override bool CanSeek => false;
+        public override bool CanWrite => false;
+        public override long Length => throw new NotSupportedException();
+
+        public override long Position
+        {
+            get => throw new NotSupportedException();
+            set => throw new NotSupportedException();
+        }
+
+        public override int Read(byte[] buffer, int offset, int count)
+        {
+            while (_offset == _chunk.Length)
+            {
+                if (_ended || _response.ReadChunk() is not { } chunk)
+                {
+                    _ended = true;
+                    return 0;
+                }
+                _chunk = chunk;
+                _offset = 0;
+            }
+            var read = Math.Min(count, _chunk.Length - _offset);
+            Array.Copy(_chunk, _offset, buffer, offset, read);
+            _offset += read;
+            return read;
+        }
+
+        public override Task<int> ReadAsync(byte[] buffer, int offset, int count, CancellationToken cancellationToken) =>
+            Task.Run(() => Read(buffer, offset, count), cancellationToken);
+
+        public override void Flush() { }
+        public override long Seek(long offset, SeekOrigin origin) => throw new NotSupportedException();
+        public override void SetLength(long value) => throw new NotSupportedException();
+        public override void Write(byte[] buffer, int offset, int count) => throw new NotSupportedException();
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::Res;
+use futures::{stream, StreamExt};
+use ohttp_client::{InnerRequestBuilder, OhttpClient, OhttpClientError};
+use reqwest::header::HeaderMap;
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+/// The request to send repeatedly.
+pub struct Target<'a> {
+    pub url: &'a String,
+    pub request: &'a InnerRequestBuilder,
+    pub outer_headers: &'a HeaderMap,
+}
+
+/// Outcome of one request of a benchmark.
+enum Outcome {
+    /// The response with this status was read completely after this time.
+    Response(u16, Duration),
+    /// The request failed before its response was read completely.
+    Failure(OhttpClientError),
+}
+
+/// The kind of a failure, by which failures are counted: the variant of the
+/// error and its status, without details that differ between requests such
+/// as correlation IDs.
+fn failure_kind(e: &OhttpClientError) -> String {
+    match e {
+        OhttpClientError::RelayHttp { status, .. } => format!("relay status {}", status.as_u16()),
+        OhttpClientError::GatewayProblem { status, .. } => {
+            format!("gateway status {}", status.as_u16())
+        }
+        OhttpClientError::InnerStatus { status, .. } => format!("inner status {}", status.as_u16()),
+        OhttpClientError::KmsError(_) => "kms".to_string(),
+        OhttpClientError::ReceiptVerification(_) => "receipt verification".to_string(),
+        OhttpClientError::KeyReceiptMismatch { .. } => "key receipt mismatch".to_string(),
+        OhttpClientError::Encapsulation(_) => "encapsulation".to_string(),
+        OhttpClientError::Bhttp(_) => "bhttp".to_string(),
+        OhttpClientError::Io(_) => "io".to_string(),
+        OhttpClientError::Http(_) => "http".to_string(),
+        OhttpClientError::Json(_) => "json".to_string(),
+        OhttpClientError::HexError(_) => "hex".to_string(),
+        OhttpClientError::Attestation(_) => "attestation".to_string(),
+        OhttpClientError::Discovery(_) => "discovery".to_string(),
+        OhttpClientError::Config(_) => "configuration".to_string(),
+        OhttpClientError::Timeout(_) => "timeout".to_string(),
+        OhttpClientError::CircuitOpen { .. } => "circuit open".to_string(),
+        OhttpClientError::Auth(_) => "authentication".to_string(),
+        OhttpClientError::InvalidRequest(_) => "invalid request".to_string(),
+        OhttpClientError::Chat(_) => "chat".to_string(),
+        OhttpClientError::Cancelled => "cancelled".to_string(),
+    }
+}
+
+/// Sends one request, encapsulated afresh, and reads its response.
+async fn send(client: &OhttpClient, target: &Target<'_>) -> Outcome {
+    let started = Instant::now();
+    let response = client
+        .post(target.url, target.request, target.outer_headers)
+        .await;
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => return Outcome::Failure(e),
+    };
+    let status = response.status().as_u16();
+    match response.bytes().await {
+        Ok(_) => Outcome::Response(status, started.elapsed()),
+        Err(e) => Outcome::Failure(e),
+    }
+}
+
+/// The latency below which `percent` percent of the sorted latencies fall,
+/// by the nearest-rank method.
+fn percentile(sorted: &[Duration], percent: f64) -> Option<Duration> {
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.max(1) - 1).copied()
+}
+
+/// Converts a duration to fractional milliseconds.
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Sends `requests` requests, `concurrency` at a time, and prints the
+/// latency percentiles and throughput of the responses and the number of
+/// responses per status and of failures per kind of error, as JSON with
+/// `json`. The first error of each kind is printed as an example.
+pub async fn run(
+    client: &OhttpClient,
+    target: &Target<'_>,
+    requests: usize,
+    concurrency: usize,
+    json: bool,
+) -> Res<()> {
+    let started = Instant::now();
+    let outcomes: Vec<Outcome> = stream::iter(0..requests)
+        .map(|_| send(client, target))
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    let elapsed = started.elapsed();
+
+    let mut latencies = Vec::new();
+    let mut statuses: BTreeMap<u16, usize> = BTreeMap::new();
+    let mut errors: BTreeMap<String, usize> = BTreeMap::new();
+    let mut examples: BTreeMap<String, String> = BTreeMap::new();
+    for outcome in outcomes {
+        match outcome {
+            Outcome::Response(status, latency) => {
+                latencies.push(latency);
+                *statuses.entry(status).or_default() += 1;
+            }
+            Outcome::Failure(e) => {
+                let kind = failure_kind(&e);
+                examples
+                    .entry(kind.clone())
+                    .or_insert_with(|| e.to_string());
+                *errors.entry(kind).or_default() += 1;
+            }
+        }
+    }
+    latencies.sort();
+    let throughput = latencies.len() as f64 / elapsed.as_secs_f64();
+    let p50 = percentile(&latencies, 50.0);
+    let p95 = percentile(&latencies, 95.0);
+    let p99 = percentile(&latencies, 99.0);
+    let max = latencies.last().copied();
+
+    if json {
+        let output = json!({
+            "requests": requests,
+            "concurrency": concurrency,
+            "duration_ms": millis(elapsed),
+            "throughput": throughput,
+            "latency_ms": {
+                "p50": p50.map(millis),
+                "p95": p95.map(millis),
+                "p99": p99.map(millis),
+                "max": max.map(millis),
+            },
+            "statuses": statuses,
+            "errors": errors,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    let failed: usize = errors.values().sum();
+    let latency = |duration: Option<Duration>| {
+        duration.map_or("-".to_string(), |duration| {
+            format!("{:.1} ms", millis(duration))
+        })
+    };
+    println!(
+        "requests:   {requests}, {} responses, {failed} failed, {concurrency} at a time",
+        latencies.len()
+    );
+    println!("duration:   {:.2} s", elapsed.as_secs_f64());
+    println!("throughput: {throughput:.2} responses/s");
+    println!(
+        "latency:    p50 {}, p95 {}, p99 {}, max {}",
+        latency(p50),
+        latency(p95),
+        latency(p99),
+        latency(max)
+    );
+    for (status, count) in &statuses {
+        println!("status {status}: {count}");
+    }
+    for (kind, count) in &errors {
+        println!("failed {count}x: {kind}, e.g. {}", examples[kind]);
+    }
+    Ok(())
+}
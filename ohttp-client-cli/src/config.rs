@@ -0,0 +1,204 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{ClientArgs, Res};
+use serde::Deserialize;
+use std::{collections::HashMap, fs, path::PathBuf};
+use tracing::info;
+
+/// Options of the client that can be set in the configuration file. Options
+/// given on the command line take precedence, except for outer headers, which
+/// are added to those of the configuration file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    url: Option<String>,
+    config: Option<String>,
+    kms_url: Vec<String>,
+    kms_token: Option<String>,
+    kms_cert: Option<PathBuf>,
+    allow_unverified_keys: Option<bool>,
+    proxy: Option<String>,
+    #[cfg(unix)]
+    unix_socket: Option<PathBuf>,
+    relay_cacert: Vec<PathBuf>,
+    relay_no_system_roots: Option<bool>,
+    relay_fallback: Vec<String>,
+    relay_round_robin: Option<bool>,
+    relay_pin: Vec<String>,
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    cert_password: Option<String>,
+    kms_client_cert: Option<bool>,
+    connect_timeout: Option<f64>,
+    max_time: Option<f64>,
+    retry: Option<u32>,
+    retry_non_idempotent: Option<bool>,
+    hedge_after: Option<f64>,
+    rate_limit: Option<f64>,
+    discover_dns: Option<bool>,
+    attestation_issuer: Option<String>,
+    attestation_audience: Option<String>,
+    attestation_policy: Option<PathBuf>,
+    outer_headers: Vec<String>,
+    allow_outer_header: Vec<String>,
+}
+
+/// Keeps the value given on the command line, if any.
+fn merge<T>(arg: &mut Option<T>, value: Option<T>) {
+    if arg.is_none() {
+        *arg = value;
+    }
+}
+
+/// Keeps the values given on the command line, if any.
+fn merge_all<T>(arg: &mut Vec<T>, values: Vec<T>) {
+    if arg.is_empty() {
+        *arg = values;
+    }
+}
+
+/// Returns `values` unless empty, `defaults` otherwise.
+fn or_all<T>(values: Vec<T>, defaults: Vec<T>) -> Vec<T> {
+    if values.is_empty() {
+        defaults
+    } else {
+        values
+    }
+}
+
+impl Profile {
+    /// Fills the unset options of this profile with those of `defaults`.
+    fn or(self, defaults: Profile) -> Profile {
+        Profile {
+            url: self.url.or(defaults.url),
+            config: self.config.or(defaults.config),
+            kms_url: or_all(self.kms_url, defaults.kms_url),
+            kms_token: self.kms_token.or(defaults.kms_token),
+            kms_cert: self.kms_cert.or(defaults.kms_cert),
+            allow_unverified_keys: self
+                .allow_unverified_keys
+                .or(defaults.allow_unverified_keys),
+            proxy: self.proxy.or(defaults.proxy),
+            #[cfg(unix)]
+            unix_socket: self.unix_socket.or(defaults.unix_socket),
+            relay_cacert: or_all(self.relay_cacert, defaults.relay_cacert),
+            relay_no_system_roots: self
+                .relay_no_system_roots
+                .or(defaults.relay_no_system_roots),
+            relay_fallback: or_all(self.relay_fallback, defaults.relay_fallback),
+            relay_round_robin: self.relay_round_robin.or(defaults.relay_round_robin),
+            relay_pin: or_all(self.relay_pin, defaults.relay_pin),
+            cert: self.cert.or(defaults.cert),
+            key: self.key.or(defaults.key),
+            cert_password: self.cert_password.or(defaults.cert_password),
+            kms_client_cert: self.kms_client_cert.or(defaults.kms_client_cert),
+            connect_timeout: self.connect_timeout.or(defaults.connect_timeout),
+            max_time: self.max_time.or(defaults.max_time),
+            retry: self.retry.or(defaults.retry),
+            retry_non_idempotent: self.retry_non_idempotent.or(defaults.retry_non_idempotent),
+            hedge_after: self.hedge_after.or(defaults.hedge_after),
+            rate_limit: self.rate_limit.or(defaults.rate_limit),
+            discover_dns: self.discover_dns.or(defaults.discover_dns),
+            attestation_issuer: self.attestation_issuer.or(defaults.attestation_issuer),
+            attestation_audience: self.attestation_audience.or(defaults.attestation_audience),
+            attestation_policy: self.attestation_policy.or(defaults.attestation_policy),
+            outer_headers: [defaults.outer_headers, self.outer_headers].concat(),
+            allow_outer_header: or_all(self.allow_outer_header, defaults.allow_outer_header),
+        }
+    }
+
+    /// Fills the options not given on the command line.
+    pub fn apply(self, args: &mut ClientArgs) {
+        merge(&mut args.url, self.url);
+        merge(&mut args.keys.config, self.config);
+        merge_all(&mut args.keys.kms_url, self.kms_url);
+        merge(&mut args.keys.kms_token, self.kms_token);
+        merge(&mut args.keys.kms_cert, self.kms_cert);
+        args.keys.allow_unverified_keys |= self.allow_unverified_keys.unwrap_or_default();
+        merge(&mut args.proxy, self.proxy);
+        #[cfg(unix)]
+        merge(&mut args.unix_socket, self.unix_socket);
+        merge_all(&mut args.relay_cacert, self.relay_cacert);
+        args.relay_no_system_roots |= self.relay_no_system_roots.unwrap_or_default();
+        merge_all(&mut args.relay_fallback, self.relay_fallback);
+        args.relay_round_robin |= self.relay_round_robin.unwrap_or_default();
+        merge_all(&mut args.relay_pin, self.relay_pin);
+        merge(&mut args.cert, self.cert);
+        merge(&mut args.key, self.key);
+        merge(&mut args.cert_password, self.cert_password);
+        args.kms_client_cert |= self.kms_client_cert.unwrap_or_default();
+        merge(&mut args.connect_timeout, self.connect_timeout);
+        merge(&mut args.max_time, self.max_time);
+        merge(&mut args.retry, self.retry);
+        args.retry_non_idempotent |= self.retry_non_idempotent.unwrap_or_default();
+        merge(&mut args.hedge_after, self.hedge_after);
+        merge(&mut args.rate_limit, self.rate_limit);
+        args.discover_dns |= self.discover_dns.unwrap_or_default();
+        merge(&mut args.attestation_issuer, self.attestation_issuer);
+        merge(&mut args.attestation_audience, self.attestation_audience);
+        merge(&mut args.attestation_policy, self.attestation_policy);
+        args.outer_headers = [self.outer_headers, std::mem::take(&mut args.outer_headers)].concat();
+        merge_all(&mut args.allow_outer_header, self.allow_outer_header);
+    }
+}
+
+/// Configuration file with default options and named profiles, e.g.
+///
+/// ```toml
+/// [default]
+/// kms_url = ["https://kms.example.com"]
+/// kms_cert = "/etc/attested-ohttp/service_cert.pem"
+///
+/// [profile.whisper]
+/// url = "https://relay.example.com/score"
+/// outer_headers = ["api-key: ..."]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    default: Profile,
+    profile: HashMap<String, Profile>,
+}
+
+/// Location of the configuration file when none is given,
+/// `~/.config/attested-ohttp/config.toml`.
+fn default_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("attested-ohttp").join("config.toml"))
+}
+
+/// Loads the options of `profile`, or the default options, from the
+/// configuration file at `path`, or from the default location if it exists.
+/// Files ending in `.json` are read as JSON, other files as TOML.
+pub fn load(path: &Option<PathBuf>, profile: &Option<String>) -> Res<Profile> {
+    let path = match path {
+        Some(path) => path.clone(),
+        None => match default_path() {
+            Some(path) if path.exists() => path,
+            _ if profile.is_some() => return Err("no configuration file found".into()),
+            _ => return Ok(Profile::default()),
+        },
+    };
+
+    info!("Reading configuration from {}", path.display());
+    let contents = fs::read_to_string(&path)?;
+    let mut file: ConfigFile = if path.extension().map_or(false, |ext| ext == "json") {
+        serde_json::from_str(&contents)?
+    } else {
+        toml::from_str(&contents)?
+    };
+
+    match profile {
+        Some(name) => {
+            let selected = file
+                .profile
+                .remove(name)
+                .ok_or(format!("no profile {name} in {}", path.display()))?;
+            Ok(selected.or(file.default))
+        }
+        None => Ok(file.default),
+    }
+}
@@ -0,0 +1,43 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use ohttp_client::OhttpClientError;
+use std::{error::Error, process::ExitCode};
+
+/// Exit codes, shown in the help.
+pub const EXIT_CODES: &str = "\
+Exit codes:
+  0   success
+  1   invalid arguments, input or response
+  2   invalid command line
+  3   the relay or the KMS could not be reached, timed out or returned an error
+  4   the key configuration could not be obtained or verified
+  5   the attestation token was missing, invalid or violated the policy
+  6   the gateway rejected the request with problem details
+  22  the inner response had an error status and --fail was given";
+
+/// Maps an error to one of the documented exit codes.
+pub fn exit_code(e: &(dyn Error + 'static)) -> ExitCode {
+    if e.downcast_ref::<verifier::Error>().is_some() {
+        return ExitCode::from(4);
+    }
+    match e.downcast_ref::<OhttpClientError>() {
+        Some(
+            OhttpClientError::Http(_)
+            | OhttpClientError::RelayHttp { .. }
+            | OhttpClientError::Timeout(_)
+            | OhttpClientError::CircuitOpen { .. }
+            | OhttpClientError::Auth(_),
+        ) => ExitCode::from(3),
+        Some(
+            OhttpClientError::KmsError(_)
+            | OhttpClientError::ReceiptVerification(_)
+            | OhttpClientError::KeyReceiptMismatch { .. }
+            | OhttpClientError::Discovery(_),
+        ) => ExitCode::from(4),
+        Some(OhttpClientError::Attestation(_)) => ExitCode::from(5),
+        Some(OhttpClientError::GatewayProblem { .. }) => ExitCode::from(6),
+        Some(OhttpClientError::InnerStatus { .. }) => ExitCode::from(22),
+        _ => ExitCode::FAILURE,
+    }
+}
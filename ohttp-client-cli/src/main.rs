@@ -1,99 +1,1298 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-use clap::Parser;
+use base64::{engine::general_purpose, Engine};
+use clap::{Args, Parser, Subcommand};
 use core::str;
-use ohttp_client::{HexArg, OhttpClientBuilder};
-use std::path::PathBuf;
-use tracing_subscriber::{EnvFilter, FmtSubscriber};
+use exit::{exit_code, EXIT_CODES};
+use indicatif::{ProgressBar, ProgressStyle};
+use ohttp_client::{
+    init_logging, AttestationConfig, AttestationPolicy, ClientIdentity, HedgePolicy, HexArg,
+    HttpVersion, InnerRequestBuilder, KeyConfigInfo, KmsAuth, LogFormat, OhttpClient,
+    OhttpClientBuilder, OhttpClientError, OhttpProtocol, OhttpResponse, OuterHeaderAction,
+    OuterHeaderFilter, RateLimit, RelayFailoverPolicy, RelayRetryPolicy, RequestStats, Timeouts,
+    ACCEPTED_ENCODINGS,
+};
+use percent_encoding::percent_decode_str;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_DISPOSITION},
+    Method,
+};
+use serde_json::{json, Map, Value};
+use std::{
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::info;
+use url::Url;
+
+mod bench;
+mod config;
+mod exit;
 
 type Res<T> = Result<T, Box<dyn std::error::Error>>;
 
 #[derive(Debug, Parser)]
-#[command(version = "0.1", about = "Make an oblivious HTTP request.")]
-struct Args {
-    /// The URL of an oblivious proxy resource.
-    /// If you use an oblivious request resource, this also works, though
-    /// you don't get any of the privacy guarantees.
-    url: String,
+#[command(
+    version = "0.1",
+    about = "Make oblivious HTTP requests.",
+    after_help = EXIT_CODES
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 
-    /// Target path of the oblivious resource
-    #[arg(long, short = 'p', default_value = "/")]
-    target_path: String,
+    /// Configuration file with default options, read from
+    /// ~/.config/attested-ohttp/config.toml if it exists when not given
+    #[arg(long, global = true)]
+    config_file: Option<PathBuf>,
+
+    /// Profile of the configuration file whose options are used
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// Format of the log events enabled with RUST_LOG: text or json
+    #[arg(long, global = true, default_value = "text")]
+    log_format: LogFormat,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Send an oblivious POST request with a raw, form or file body
+    Post {
+        #[command(flatten)]
+        client: ClientArgs,
+        #[command(flatten)]
+        inner: InnerArgs,
+        #[command(flatten)]
+        body: BodyArgs,
+    },
+    /// Send an oblivious GET request
+    Get {
+        #[command(flatten)]
+        client: ClientArgs,
+        #[command(flatten)]
+        inner: InnerArgs,
+    },
+    /// Send an HTTP/1.1 or bhttp request read from a file as is
+    Raw {
+        #[command(flatten)]
+        client: ClientArgs,
+        /// File holding the request, e.g. one captured from another client
+        request_file: PathBuf,
+        /// The request file holds a bhttp message instead of an HTTP/1.1 request
+        #[arg(long, short = 'b')]
+        binary: bool,
+    },
+    /// Send the same oblivious request repeatedly, encapsulated afresh each
+    /// time, and report latency percentiles, throughput and errors
+    Bench {
+        #[command(flatten)]
+        client: ClientArgs,
+        #[command(flatten)]
+        inner: InnerArgs,
+        #[command(flatten)]
+        body: BodyArgs,
+
+        /// Number of requests to send
+        #[arg(long, short = 'n', default_value_t = 100)]
+        requests: usize,
+
+        /// Number of requests in flight at once
+        #[arg(long, default_value_t = 1)]
+        concurrency: usize,
+    },
+    /// Work with OHTTP key configurations
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommand,
+    },
+    /// Work with KMS key generation receipts
+    Receipt {
+        #[command(subcommand)]
+        command: ReceiptCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ReceiptCommand {
+    /// Verify a CCF receipt against the KMS service certificate and print the
+    /// claims it attests to
+    Verify {
+        /// JSON file with the receipt
+        #[arg(long, short = 'r')]
+        receipt: PathBuf,
 
-    /// key configuration
+        /// Trusted KMS service certificate
+        #[arg(long, short = 'k')]
+        cert: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum KeysCommand {
+    /// Decode an encoded key configuration or key configuration list and
+    /// print its key ID, KEM and symmetric algorithms
+    Inspect {
+        /// The key configuration as hex, or @path to read it in binary from a
+        /// file, e.g. one fetched from /.well-known/ohttp-gateway
+        config: String,
+    },
+    /// Fetch the key configurations published by the KMS and verify their
+    /// receipts, without sending a request
+    Fetch {
+        /// URL of the KMS to obtain HPKE keys from. Repeat to fail over to
+        /// further KMS replicas.
+        #[arg(long, short = 'f', required = true)]
+        kms_url: Vec<String>,
+
+        /// Bearer token presented to the KMS
+        #[arg(long)]
+        kms_token: Option<String>,
+
+        /// Trusted KMS service certificate
+        #[arg(long, short = 'k')]
+        kms_cert: PathBuf,
+
+        /// Proxy for the KMS requests, e.g. http://proxy:3128 or
+        /// socks5://proxy:1080. Defaults to the HTTP(S)_PROXY environment variables.
+        #[arg(long)]
+        proxy: Option<String>,
+
+        /// Maximum time in seconds to connect to the KMS
+        #[arg(long)]
+        connect_timeout: Option<f64>,
+
+        /// Write the verified key configurations as a hex key configuration
+        /// list to this file, for later use with --config
+        #[arg(long, short = 'o')]
+        output: Option<PathBuf>,
+    },
+}
+
+impl Command {
+    /// Options of the client, for the subcommands that send requests.
+    fn client_args(&mut self) -> Option<&mut ClientArgs> {
+        match self {
+            Command::Post { client, .. }
+            | Command::Get { client, .. }
+            | Command::Raw { client, .. }
+            | Command::Bench { client, .. } => Some(client),
+            Command::Keys { .. } | Command::Receipt { .. } => None,
+        }
+    }
+}
+
+/// Where to obtain the key configuration of the gateway from.
+#[derive(Debug, Args)]
+struct KeyArgs {
+    /// key configuration as hex, or @path to load a key configuration saved
+    /// with --save-config, whose receipt is verified with --kms-cert
     #[arg(long, short = 'c')]
-    config: Option<HexArg>,
+    config: Option<String>,
+
+    /// Save the key configuration obtained from the KMS and its receipt to
+    /// this file, for later use with --config @path
+    #[arg(long, requires = "kms_url")]
+    save_config: Option<PathBuf>,
 
-    /// URL of the KMS to obtain HPKE keys from
+    /// URL of the KMS to obtain HPKE keys from. Repeat to fail over to
+    /// further KMS replicas.
     #[arg(long, short = 'f')]
-    kms_url: Option<String>,
+    kms_url: Vec<String>,
+
+    /// Bearer token presented to the KMS
+    #[arg(long)]
+    kms_token: Option<String>,
 
     /// Trusted KMS service certificate
     #[arg(long, short = 'k')]
     kms_cert: Option<PathBuf>,
 
-    /// Where to write response content.
+    /// Use a saved key configuration without verifying its receipt when no
+    /// --kms-cert is given
+    #[arg(long)]
+    allow_unverified_keys: bool,
+}
+
+/// Options of the outer request to the relay and of the client itself.
+#[derive(Debug, Args)]
+struct ClientArgs {
+    /// The URL of an oblivious proxy resource.
+    /// If you use an oblivious request resource, this also works, though
+    /// you don't get any of the privacy guarantees.
+    /// Can be omitted if set in the configuration file.
+    url: Option<String>,
+
+    #[command(flatten)]
+    keys: KeyArgs,
+
+    /// Proxy for the relay and KMS requests, e.g. http://proxy:3128 or
+    /// socks5://proxy:1080. Defaults to the HTTP(S)_PROXY environment variables.
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Send the outer request over this unix domain socket, e.g. to a
+    /// colocated relay, instead of over TCP and TLS
+    #[cfg(unix)]
+    #[arg(long, conflicts_with = "proxy")]
+    unix_socket: Option<PathBuf>,
+
+    /// PEM file with additional root certificates trusted for the relay
+    #[arg(long)]
+    relay_cacert: Vec<PathBuf>,
+
+    /// Only trust the root certificates given with --relay-cacert for the relay
+    #[arg(long)]
+    relay_no_system_roots: bool,
+
+    /// Relay to fail over to when the relay is unreachable or fails with a
+    /// server error. Repeat to fail over to several relays in order.
+    #[arg(long)]
+    relay_fallback: Vec<String>,
+
+    /// Spread the requests over the relay and the fallback relays in turn
+    #[arg(long)]
+    relay_round_robin: bool,
+
+    /// SHA-256 pin of the relay public key, as sha256/<base64>. Repeat to
+    /// allow several keys.
+    #[arg(long)]
+    relay_pin: Vec<String>,
+
+    /// Client certificate for the relay, as PEM or PKCS#12 file
+    #[arg(long)]
+    cert: Option<PathBuf>,
+
+    /// Private key of the client certificate, as PEM file
+    #[arg(long, requires = "cert")]
+    key: Option<PathBuf>,
+
+    /// Password of a PKCS#12 client certificate
+    #[arg(long, requires = "cert")]
+    cert_password: Option<String>,
+
+    /// Also present the client certificate to the KMS
+    #[arg(long, requires = "cert")]
+    kms_client_cert: bool,
+
+    /// Maximum time in seconds to connect to the relay or the KMS
+    #[arg(long)]
+    connect_timeout: Option<f64>,
+
+    /// Maximum time in seconds for the whole request, including the response
+    #[arg(long, short = 'm')]
+    max_time: Option<f64>,
+
+    /// Retry transient relay failures up to this many times
+    #[arg(long)]
+    retry: Option<u32>,
+
+    /// Also retry requests whose method is not idempotent, e.g. POST, which
+    /// the gateway may have processed before the failure
+    #[arg(long)]
+    retry_non_idempotent: bool,
+
+    /// Send idempotent requests a second time if no response arrived after
+    /// this many seconds, and use the first response
+    #[arg(long)]
+    hedge_after: Option<f64>,
+
+    /// Send at most this many requests per second, in bursts of up to as many
+    #[arg(long)]
+    rate_limit: Option<f64>,
+
+    /// Discover the key configuration from the HTTPS DNS records of the URL host
+    #[arg(long)]
+    discover_dns: bool,
+
+    /// Attestation service whose tokens are trusted. When set, the attestation
+    /// token returned by the gateway is verified and the request fails if it is invalid.
+    #[arg(long)]
+    attestation_issuer: Option<String>,
+
+    /// Expected audience of the attestation token
+    #[arg(long, requires = "attestation_issuer")]
+    attestation_audience: Option<String>,
+
+    /// JSON file with the attestation policy the gateway TEE must satisfy
+    #[arg(long, requires = "attestation_issuer")]
+    attestation_policy: Option<PathBuf>,
+
+    /// OHTTP variant spoken with the gateway: standard or chunked
+    #[arg(long, default_value = "chunked")]
+    protocol: OhttpProtocol,
+
+    /// HTTP version of the relay connection: auto (HTTP/2 if negotiated with
+    /// ALPN), 1.1, 2 (prior knowledge) or, with the http3 feature, 3
+    #[arg(long, default_value = "auto")]
+    http_version: HttpVersion,
+
+    /// Outer request header carrying the random correlation ID of each
+    /// request, for finding it in the logs of the relay and the gateway
+    #[arg(long, default_value = "x-ms-client-request-id")]
+    correlation_header: String,
+
+    /// Entra ID scope of the relay, e.g. api://relay/.default. Tokens are
+    /// acquired with AZURE_TENANT_ID, AZURE_CLIENT_ID and AZURE_CLIENT_SECRET
+    /// if set, or with a managed identity otherwise.
+    #[cfg(feature = "azure-auth")]
+    #[arg(long)]
+    azure_scope: Option<String>,
+
+    /// List of headers in the outer request
+    #[arg(long, short = 'O')]
+    outer_headers: Vec<String>,
+
+    /// User-Agent of the outer request. None is sent by default.
+    #[arg(long, conflicts_with = "minimal_outer_headers")]
+    outer_user_agent: Option<String>,
+
+    /// Only send the outer headers that the relay and the gateway need,
+    /// without correlation ID, to reduce what the relay can tell apart
+    #[arg(long)]
+    minimal_outer_headers: bool,
+
+    /// What to do with outer headers that may identify the client to the
+    /// relay, e.g. cookies: allow, warn or reject
+    #[arg(long, default_value = "warn")]
+    outer_header_check: OuterHeaderAction,
+
+    /// Outer header to send without warning, although it may identify the
+    /// client. Repeat to allow several headers.
+    #[arg(long)]
+    allow_outer_header: Vec<String>,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+/// How the response is written out.
+#[derive(Debug, Args)]
+struct OutputArgs {
+    /// Where to write response content, as received.
     /// If you omit this, output is written to `stdout`.
     #[arg(long, short = 'o')]
     output: Option<PathBuf>,
 
-    /// When creating message/bhttp, use the indeterminate-length form.
-    #[arg(long, short = 'n', alias = "indefinite")]
-    indeterminate: bool,
+    /// Write the content to the file named by the Content-Disposition header of
+    /// the response, in the current directory
+    #[arg(long, short = 'J', conflicts_with = "output")]
+    remote_name: bool,
+
+    /// Do not show upload and download progress bars, which are shown when
+    /// `stderr` is a terminal
+    #[arg(long)]
+    no_progress: bool,
+
+    /// Write the content to `stdout` as received instead of printing each
+    /// chunk as a line of text, e.g. for binary responses
+    #[arg(long, alias = "binary-output")]
+    raw_output: bool,
+
+    /// Print a single JSON object with the status, headers, attestation
+    /// token, timings and body of the response once it is complete
+    #[arg(long)]
+    json: bool,
+
+    /// Parse the content as Server-Sent Events and print the data of each
+    /// event as it arrives
+    #[arg(long, conflicts_with_all = ["json", "raw_output"])]
+    sse: bool,
+
+    /// Print the status line and headers of the response before its content
+    #[arg(long, short = 'i', conflicts_with = "json")]
+    include: bool,
+
+    /// Print the sizes of the encapsulated request and response and the time
+    /// to the relay's response headers, to the first decapsulated byte and to
+    /// the end of the body to `stderr`. Connecting to the relay is part of
+    /// the time to the response headers and is not timed on its own
+    #[arg(long)]
+    timings: bool,
+
+    /// Fail with the status and body of the response as the error instead of
+    /// printing its content if it has a 4xx or 5xx status
+    #[arg(long)]
+    fail: bool,
+
+    /// Write the status line and headers of the response to this file, or to
+    /// `stdout` if -
+    #[arg(long, short = 'D')]
+    dump_header: Option<PathBuf>,
+}
+
+impl ClientArgs {
+    fn url(&self) -> Res<&String> {
+        self.url.as_ref().ok_or_else(|| {
+            "a URL is required, on the command line or in the configuration file".into()
+        })
+    }
+}
+
+/// Options of the inner request shared by all methods.
+#[derive(Debug, Args)]
+struct InnerArgs {
+    /// Method of the inner request, instead of the one of the subcommand
+    #[arg(long, short = 'X')]
+    method: Option<Method>,
+
+    /// Target path of the oblivious resource
+    #[arg(long, short = 'p', default_value = "/")]
+    target_path: String,
+
+    /// Query parameters of the inner request as key=value, percent-encoded
+    /// and appended to the target path
+    #[arg(long, short = 'q')]
+    query: Vec<String>,
 
     /// List of headers in the inner request
     #[arg(long, short = 'H')]
     headers: Vec<String>,
 
+    /// Ask for a compressed response with Accept-Encoding in the inner
+    /// request; compressed responses are decoded either way
+    #[arg(long)]
+    compressed: bool,
+
+    /// Print the inner request as an HTTP/1.1 message and exit without
+    /// sending it to the relay
+    #[arg(long)]
+    dry_run: bool,
+
+    /// With --dry-run, also print the bhttp encoding of the request as hex
+    /// and the size of the encapsulated request
+    #[arg(long, requires = "dry_run")]
+    show_bhttp: bool,
+
+    /// Encapsulate the request and write it to enc.bin in this directory,
+    /// with a curl command posting it to the relay in curl.sh, instead of
+    /// sending it. The response to it cannot be decapsulated.
+    #[arg(long, conflicts_with = "dry_run")]
+    print_curl: Option<PathBuf>,
+}
+
+/// Body of the inner request.
+#[derive(Debug, Args)]
+struct BodyArgs {
+    /// Body of the inner request, @path to read it from a file, or @- to
+    /// read it from standard input
+    #[arg(long, short = 'd', conflicts_with = "form_fields")]
+    data: Option<String>,
+
+    /// Read the body of the inner request from standard input, same as --data @-
+    #[arg(long, conflicts_with_all = ["data", "form_fields"])]
+    stdin: bool,
+
+    /// Content type of the body given with --data or --stdin
+    #[arg(long, default_value = "application/octet-stream")]
+    content_type: String,
+
     /// List of fields in the inner request
     #[arg(long, short = 'F')]
     form_fields: Vec<String>,
 
-    /// List of headers in the outer request
-    #[arg(long, short = 'O')]
-    outer_headers: Vec<String>,
+    /// Send the SHA-256 and MD5 digests of file fields as Content-Digest and
+    /// Content-MD5 headers of each part
+    #[arg(long)]
+    digest_files: bool,
 }
 
-#[tokio::main]
-async fn main() -> Res<()> {
-    // Build a simple subscriber that outputs to stdout
-    let subscriber = FmtSubscriber::builder()
-        .with_env_filter(EnvFilter::from_default_env())
-        .with_file(true)
-        .with_line_number(true)
-        .finish();
-
-    // Set the subscriber as global default
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
-
-    let args = Args::parse();
-    let ohttp_client = OhttpClientBuilder::new()
-        .kms_url(&args.kms_url)
-        .kms_cert(&args.kms_cert)
-        .config(&args.config)
+/// Parses headers given on the command line as `name: value`.
+fn parse_headers(headers: &[String]) -> Res<HeaderMap> {
+    let mut map = HeaderMap::new();
+    for header in headers {
+        let (name, value) = header
+            .split_once(':')
+            .ok_or(format!("malformed header {header}, expected name: value"))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("invalid header name in {header}"))?;
+        let value = HeaderValue::from_str(value.trim())
+            .map_err(|_| format!("invalid header value in {header}"))?;
+        map.append(name, value);
+    }
+    Ok(map)
+}
+
+/// Converts the seconds given to a command line option to a duration.
+fn seconds(option: &str, seconds: Option<f64>) -> Res<Option<Duration>> {
+    seconds
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .map_err(|e| format!("--{option}: {e}").into())
+}
+
+/// Shows the progress of uploads and downloads as bars on `stderr`.
+fn with_progress_bars(builder: OhttpClientBuilder) -> OhttpClientBuilder {
+    let style =
+        |template: &str| ProgressStyle::with_template(template).expect("valid progress template");
+    let upload = ProgressBar::new(0).with_style(style(
+        "upload   {bytes:>10}/{total_bytes:10} [{wide_bar}] {bytes_per_sec}",
+    ));
+    let download = ProgressBar::new_spinner()
+        .with_style(style("download {bytes:>10} {spinner} {bytes_per_sec}"));
+
+    builder
+        .on_upload_progress(move |sent, total| {
+            upload.set_length(total.unwrap_or_default());
+            upload.set_position(sent);
+            if Some(sent) == total {
+                upload.finish();
+            }
+        })
+        .on_download_progress(move |received, total| {
+            download.set_position(received);
+            if total.is_some() {
+                download.finish();
+            }
+        })
+}
+
+/// Builds the client from the key, relay and attestation options.
+async fn build_client(args: &ClientArgs) -> Res<OhttpClient> {
+    let discover_dns = if args.discover_dns {
+        Url::parse(args.url()?)?.host_str().map(str::to_string)
+    } else {
+        None
+    };
+    let attestation = args.attestation_issuer.as_ref().map(|issuer| {
+        let config = AttestationConfig::new(issuer);
+        match &args.attestation_audience {
+            Some(audience) => config.audience(audience),
+            None => config,
+        }
+    });
+    let attestation_policy = match &args.attestation_policy {
+        Some(path) => Some(AttestationPolicy::from_file(path)?),
+        None => None,
+    };
+    let client_identity = match &args.cert {
+        Some(cert) => Some(ClientIdentity::from_files(
+            cert,
+            args.key.as_deref(),
+            args.cert_password.as_deref(),
+        )?),
+        None => None,
+    };
+
+    let timeouts = Timeouts {
+        connect: seconds("connect-timeout", args.connect_timeout)?,
+        total: seconds("max-time", args.max_time)?,
+        ..Timeouts::default()
+    };
+
+    let relay_retry = args.retry.map(|max_retries| RelayRetryPolicy {
+        max_retries,
+        retry_non_idempotent: args.retry_non_idempotent,
+        ..RelayRetryPolicy::default()
+    });
+
+    let relay_failover = (!args.relay_fallback.is_empty()).then(|| RelayFailoverPolicy {
+        fallbacks: args.relay_fallback.clone(),
+        round_robin: args.relay_round_robin,
+        ..RelayFailoverPolicy::default()
+    });
+
+    if let Some(rate) = args.rate_limit {
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(
+                format!("--rate-limit: {rate} is not a positive number of requests").into(),
+            );
+        }
+    }
+    let rate_limit = args.rate_limit.map(|requests_per_second| RateLimit {
+        requests_per_second,
+        burst: requests_per_second.ceil().max(1.0) as u32,
+    });
+
+    let outer_header_filter = OuterHeaderFilter {
+        action: args.outer_header_check,
+        allow: args.allow_outer_header.clone(),
+        ..OuterHeaderFilter::default()
+    };
+
+    let hedge = seconds("hedge-after", args.hedge_after)?.map(|delay| HedgePolicy {
+        delay,
+        ..HedgePolicy::default()
+    });
+
+    let (config, saved_config) = match args.keys.config.as_deref() {
+        Some(config) => match config.strip_prefix('@') {
+            Some(path) => (None, Some(PathBuf::from(path))),
+            None => (Some(config.parse::<HexArg>()?), None),
+        },
+        None => (None, None),
+    };
+
+    let mut builder = OhttpClientBuilder::new();
+    if let Some(token) = &args.keys.kms_token {
+        builder = builder.kms_auth(KmsAuth::bearer(token));
+    }
+    #[cfg(feature = "azure-auth")]
+    {
+        let relay_auth = args
+            .azure_scope
+            .as_deref()
+            .map(ohttp_client::AzureTokenProvider::from_env);
+        builder = builder.relay_auth(&relay_auth);
+    }
+    #[cfg(unix)]
+    if let Some(path) = &args.unix_socket {
+        let transport = ohttp_client::UnixSocketTransport::new(path);
+        builder = builder.transport(std::sync::Arc::new(transport));
+    }
+    if !args.output.no_progress && std::io::stderr().is_terminal() {
+        builder = with_progress_bars(builder);
+    }
+    let ohttp_client = builder
+        .kms_urls(&args.keys.kms_url)
+        .kms_cert(&args.keys.kms_cert)
+        .config(&config)
+        .saved_config(&saved_config)
+        .allow_unverified_keys(args.keys.allow_unverified_keys)
+        .discover_dns(&discover_dns)
+        .protocol(args.protocol)
+        .http_version(args.http_version)
+        .correlation_header(&args.correlation_header)
+        .proxy(&args.proxy)
+        .timeouts(&timeouts)
+        .relay_retry(&relay_retry)
+        .hedge(&hedge)
+        .rate_limit(&rate_limit)
+        .outer_header_filter(&outer_header_filter)
+        .user_agent(&args.outer_user_agent)
+        .minimal_outer_headers(args.minimal_outer_headers)
+        .relay_failover(&relay_failover)
+        .relay_ca_certs(&args.relay_cacert)
+        .relay_system_roots(!args.relay_no_system_roots)
+        .relay_spki_pins(&args.relay_pin)
+        .client_identity(&client_identity)
+        .kms_client_identity(args.kms_client_cert)
+        .attestation(&attestation)
+        .attestation_policy(&attestation_policy)
         .build()
         .await?;
 
-    let mut response = ohttp_client
-        .post(
-            &args.url,
-            &args.target_path,
-            &args.headers,
-            &args.form_fields,
-            &args.outer_headers,
-        )
+    if let Some(receipt) = ohttp_client.key_receipt() {
+        info!(
+            "Using key {} generated in transaction {}",
+            receipt.key_digest, receipt.transaction_id
+        );
+    }
+    if let Some(path) = &args.keys.save_config {
+        ohttp_client.save_key_config(path)?;
+    }
+    Ok(ohttp_client)
+}
+
+/// Prints the request that would be sent instead of sending it.
+fn print_dry_run(client: &OhttpClient, request: &InnerRequestBuilder, args: &InnerArgs) -> Res<()> {
+    use std::io::Write;
+
+    let built = client.build_only(request)?;
+    let mut out = std::io::stdout().lock();
+    out.write_all(&built.http)?;
+    if args.show_bhttp {
+        writeln!(out)?;
+        writeln!(out, "bhttp: {}", hex::encode(&built.bhttp))?;
+        writeln!(
+            out,
+            "encapsulated: {} bytes with key {}",
+            built.encapsulated_size, built.key_id
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Quotes `s` as a single argument of a POSIX shell command.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Writes the encapsulated request to `dir`/enc.bin and a curl command
+/// posting it to the relay to `dir`/curl.sh, and prints the command.
+async fn export_curl(
+    client: &OhttpClient,
+    url: &str,
+    request: &InnerRequestBuilder,
+    outer_headers: &HeaderMap,
+    dir: &Path,
+) -> Res<()> {
+    let outer = client
+        .encapsulate_only(&url.to_string(), request, outer_headers)
         .await?;
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join("enc.bin"), &outer.body)?;
+
+    let mut command = format!("curl -sS -X POST {}", shell_quote(&outer.url));
+    let content_type = format!("content-type: {}", outer.content_type);
+    command.push_str(&format!(" \\\n  -H {}", shell_quote(&content_type)));
+    for (name, value) in &outer.headers {
+        let header = format!("{name}: {}", String::from_utf8_lossy(value.as_bytes()));
+        command.push_str(&format!(" \\\n  -H {}", shell_quote(&header)));
+    }
+    command.push_str(" \\\n  --data-binary @enc.bin -o response.bin -D -");
+
+    // The command refers to enc.bin relative to the directory of the script
+    let script = format!("#!/bin/sh\ncd \"$(dirname \"$0\")\" || exit 1\n{command}\n");
+    let path = dir.join("curl.sh");
+    std::fs::write(&path, script)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o700))?;
+    }
+    info!("Wrote the encapsulated request and {}", path.display());
+    println!("{command}");
+    Ok(())
+}
+
+/// Builds the inner request from the method, target path and header options.
+fn inner_request(args: &InnerArgs, method: Method) -> Res<InnerRequestBuilder> {
+    let mut request = InnerRequestBuilder::new()
+        .method(args.method.clone().unwrap_or(method))
+        .path(&args.target_path)
+        .headers(&parse_headers(&args.headers)?);
+    if args.compressed {
+        request = request.header("accept-encoding", ACCEPTED_ENCODINGS);
+    }
+    for param in &args.query {
+        let (key, value) = param.split_once('=').ok_or(format!(
+            "malformed query parameter {param}, expected key=value"
+        ))?;
+        request = request.query(key, value);
+    }
+    Ok(request)
+}
+
+/// Adds the raw, form or file body to the inner request.
+fn with_body(mut request: InnerRequestBuilder, args: &BodyArgs) -> Res<InnerRequestBuilder> {
+    request = request.digest_files(args.digest_files);
+    match args.data.as_deref() {
+        Some("@-") => request = request.stdin_body(&args.content_type),
+        Some(data) => {
+            let body = match data.strip_prefix('@') {
+                Some(path) => std::fs::read(path)?,
+                None => data.as_bytes().to_vec(),
+            };
+            request = request.typed_body(body, &args.content_type);
+        }
+        None if args.stdin => request = request.stdin_body(&args.content_type),
+        None => {}
+    }
+    for field in &args.form_fields {
+        let (name, value) = field
+            .split_once('=')
+            .ok_or(format!("malformed form field {field}"))?;
+        // If the value starts with '@', it is treated as a file path.
+        request = match value.strip_prefix('@') {
+            Some(path) => request.file_part(name, path),
+            None => request.form_field(name, value),
+        };
+    }
+    Ok(request)
+}
+
+/// Prints the decoded contents of a key configuration or key configuration list.
+fn inspect_config(config: &str) -> Res<()> {
+    let encoded = match config.strip_prefix('@') {
+        Some(path) => std::fs::read(path)?,
+        None => hex::decode(config.trim())?,
+    };
+    let configs = KeyConfigInfo::parse_list(&encoded)
+        .or_else(|_| KeyConfigInfo::parse(&encoded).map(|info| vec![info]))?;
+
+    let name = |name: Option<&str>| name.unwrap_or("unknown").to_string();
+    for info in configs {
+        println!("key id: {}", info.key_id);
+        println!("kem: {:#06x} {}", info.kem, name(info.kem_name()));
+        println!("public key: {}", hex::encode(&info.public_key));
+        println!("symmetric algorithms:");
+        for suite in &info.symmetric {
+            println!(
+                "  kdf {:#06x} {}, aead {:#06x} {}",
+                suite.kdf,
+                name(suite.kdf_name()),
+                suite.aead,
+                name(suite.aead_name())
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Fetches the key configurations from the KMS, prints those whose receipt is
+/// valid, with when they were generated and that they do not expire, and
+/// fails if any receipt is not.
+async fn fetch_keys(
+    kms_url: &[String],
+    kms_token: &Option<String>,
+    kms_cert: &Path,
+    proxy: &Option<String>,
+    connect_timeout: Option<f64>,
+    output: &Option<PathBuf>,
+) -> Res<()> {
+    let timeouts = Timeouts {
+        connect: seconds("connect-timeout", connect_timeout)?,
+        ..Timeouts::default()
+    };
+    let mut builder = OhttpClientBuilder::new()
+        .kms_urls(kms_url)
+        .kms_cert(&Some(kms_cert.to_path_buf()))
+        .proxy(proxy)
+        .timeouts(&timeouts);
+    if let Some(token) = kms_token {
+        builder = builder.kms_auth(KmsAuth::bearer(token));
+    }
+    let keys = builder.fetch_kms_keys().await?;
+
+    let mut config_list = Vec::new();
+    let mut failed = 0;
+    for (index, key) in keys.iter().enumerate() {
+        let key = match key {
+            Ok(key) => key,
+            Err(e) => {
+                println!("key configuration {index}: verification failed: {e}");
+                failed += 1;
+                continue;
+            }
+        };
+        let info = KeyConfigInfo::parse(&key.config)?;
+        println!(
+            "key configuration {index}: key id {}, kem {:#06x} {}",
+            info.key_id,
+            info.kem,
+            info.kem_name().unwrap_or("unknown")
+        );
+        println!("  digest: {}", key.receipt.key_digest);
+        println!("  transaction: {}", key.receipt.transaction_id);
+        println!(
+            "  generated: {}",
+            key.receipt.timestamp.as_deref().unwrap_or("unknown")
+        );
+        // The KMS publishes no expiry: keys are valid until the KMS rotates
+        // them, after which the gateway rejects requests under the old key
+        println!("  expires: no expiry, valid until the KMS rotates the key");
+
+        config_list.extend_from_slice(&u16::try_from(key.config.len())?.to_be_bytes());
+        config_list.extend_from_slice(&key.config);
+    }
+
+    if let Some(output) = output {
+        std::fs::write(output, hex::encode(&config_list))?;
+    }
+    if failed > 0 {
+        return Err(OhttpClientError::KmsError(format!(
+            "{failed} of {} key configurations failed verification",
+            keys.len()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Verifies a receipt and prints its claims.
+fn verify_receipt(receipt: &Path, cert: &Path) -> Res<()> {
+    let receipt = std::fs::read_to_string(receipt)?;
+    let cert = std::fs::read_to_string(cert)?;
+    let claims = verifier::verify_receipt(&receipt, &cert)?;
+
+    println!("transaction: {}", claims.transaction_id);
+    println!("claims digest: {}", claims.claims_digest);
+    println!(
+        "node id: {}",
+        claims.node_id.as_deref().unwrap_or("unknown")
+    );
+    println!("node certificate:\n{}", claims.node_cert.trim_end());
+    Ok(())
+}
+
+/// Converts a duration to fractional milliseconds.
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
 
+/// Prints the sizes and timings of the request to `stderr`. The total
+/// includes fetching keys, encapsulating and retrying the request.
+fn print_timings(stats: &RequestStats, started: Instant) {
+    let optional = |duration: Option<Duration>| {
+        duration.map_or("-".to_string(), |duration| {
+            format!("{:.1} ms", millis(duration))
+        })
+    };
+    eprintln!("encapsulated request:   {} bytes", stats.request_size);
+    eprintln!("encapsulated response:  {} bytes", stats.response_size);
+    eprintln!(
+        "relay response headers: {:.1} ms",
+        millis(stats.response_headers)
+    );
+    eprintln!("first decapsulated byte: {}", optional(stats.first_byte));
+    eprintln!("end of body:            {}", optional(stats.total));
+    for upload in &stats.uploads {
+        eprintln!(
+            "uploaded {:?}: {} bytes, sha256 {}, md5 {}",
+            upload.name,
+            upload.size,
+            hex::encode(upload.sha256),
+            hex::encode(upload.md5)
+        );
+    }
+    eprintln!(
+        "total:                  {:.1} ms",
+        millis(started.elapsed())
+    );
+}
+
+/// Prints the response as a single JSON object. The body is included as
+/// text if it is valid UTF-8 and base64-encoded otherwise.
+async fn print_json(mut response: OhttpResponse, started: Instant) -> Res<()> {
     let status = response.status();
-    if status.is_success() {
+    let mut headers = Map::new();
+    for name in response.headers().keys() {
+        let values: Vec<_> = response
+            .headers()
+            .get_all(name)
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+            .collect();
+        headers.insert(name.to_string(), Value::from(values.join(", ")));
+    }
+    let attestation = response.attestation().map(|attestation| {
+        json!({
+            "token": attestation.token(),
+            "verified": attestation.verified(),
+            "claims": attestation.claims(),
+        })
+    });
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+    }
+    let stats = response.stats();
+    let mut output = json!({
+        "status": status.as_u16(),
+        "headers": headers,
+        "attestation": attestation,
+        "relay": response.relay(),
+        "timings": {
+            "request_size": stats.request_size,
+            "response_size": stats.response_size,
+            "response_headers_ms": millis(stats.response_headers),
+            "first_byte_ms": stats.first_byte.map(millis),
+            "body_ms": stats.total.map(millis),
+            "total_ms": millis(started.elapsed()),
+        },
+    });
+    if !stats.uploads.is_empty() {
+        let uploads: Vec<_> = stats
+            .uploads
+            .iter()
+            .map(|upload| {
+                json!({
+                    "name": upload.name,
+                    "file_name": upload.file_name,
+                    "size": upload.size,
+                    "sha256": hex::encode(upload.sha256),
+                    "md5": hex::encode(upload.md5),
+                })
+            })
+            .collect();
+        output["uploads"] = Value::from(uploads);
+    }
+    match str::from_utf8(&body) {
+        Ok(text) => output["body"] = Value::from(text),
+        Err(_) => output["body_base64"] = Value::from(general_purpose::STANDARD.encode(&body)),
+    }
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+/// Extracts the file name from the Content-Disposition header of the
+/// response, preferring the extended `filename*` parameter (RFC 6266).
+/// Directory components are dropped so that the file can only be written
+/// to the current directory.
+fn remote_name(response: &OhttpResponse) -> Res<PathBuf> {
+    let disposition = response
+        .headers()
+        .get(CONTENT_DISPOSITION)
+        .ok_or("the response has no Content-Disposition header")?
+        .to_str()?;
+
+    let mut file_name = None;
+    for param in disposition.split(';').skip(1) {
+        let Some((key, value)) = param.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "filename*" => {
+                // charset'language'percent-encoded-name
+                if let Some(encoded) = value.splitn(3, '\'').nth(2) {
+                    file_name = Some(percent_decode_str(encoded).decode_utf8()?.into_owned());
+                    break;
+                }
+            }
+            "filename" => file_name = Some(value.trim_matches('"').to_string()),
+            _ => {}
+        }
+    }
+
+    file_name
+        .as_deref()
+        .and_then(|name| Path::new(name).file_name())
+        .map(PathBuf::from)
+        .ok_or_else(|| "the Content-Disposition header has no valid file name".into())
+}
+
+/// Formats the status line and headers of the response as in HTTP/1.1.
+fn response_head(response: &OhttpResponse) -> String {
+    let status = response.status();
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\n",
+        status.as_str(),
+        status.canonical_reason().unwrap_or_default()
+    );
+    for (name, value) in response.headers() {
+        head += &format!("{name}: {}\r\n", String::from_utf8_lossy(value.as_bytes()));
+    }
+    head + "\r\n"
+}
+
+/// Prints the content of the response as it is received, or the whole
+/// response as JSON with --json.
+async fn print_response(response: OhttpResponse, args: &OutputArgs, started: Instant) -> Res<()> {
+    if let Some(path) = &args.dump_header {
+        let head = response_head(&response);
+        if path.as_os_str() == "-" {
+            print!("{head}");
+        } else {
+            std::fs::write(path, head)?;
+        }
+    }
+    let mut response = if args.fail {
+        response.error_for_status().await?
+    } else {
+        response
+    };
+    if args.json {
+        return print_json(response, started).await;
+    }
+    if args.sse {
+        return print_events(response, args, started).await;
+    }
+    if !args.raw_output && args.output.is_none() && !args.remote_name {
+        if args.include {
+            print!("{}", response_head(&response));
+        }
         while let Some(chunk) = response.chunk().await? {
             let chunk = str::from_utf8(&chunk)?;
             println!("{chunk}");
         }
     } else {
-        println!("Request failed with status {status}");
-        println!("{}", response.text().await?);
+        write_content(&mut response, args).await?;
+    }
+    if args.timings {
+        print_timings(&response.stats(), started);
     }
     Ok(())
 }
+
+/// Prints the data of each Server-Sent Event as it is received.
+async fn print_events(response: OhttpResponse, args: &OutputArgs, started: Instant) -> Res<()> {
+    let mut out: Box<dyn AsyncWrite + Unpin> = match &args.output {
+        Some(path) => Box::new(tokio::fs::File::create(path).await?),
+        None => Box::new(tokio::io::stdout()),
+    };
+    if args.include {
+        out.write_all(response_head(&response).as_bytes()).await?;
+    }
+    let mut events = response.sse();
+    while let Some(event) = events.next().await? {
+        out.write_all(format!("{}\n", event.data).as_bytes())
+            .await?;
+        out.flush().await?;
+    }
+    if args.timings {
+        print_timings(&events.response().stats(), started);
+    }
+    Ok(())
+}
+
+/// Writes the content verbatim so that binary responses are not corrupted.
+async fn write_content(response: &mut OhttpResponse, args: &OutputArgs) -> Res<()> {
+    let output = match &args.output {
+        Some(path) => Some(path.clone()),
+        None if args.remote_name => Some(remote_name(response)?),
+        None => None,
+    };
+    let mut out: Box<dyn AsyncWrite + Unpin> = match &output {
+        Some(path) => {
+            info!("Writing response content to {}", path.display());
+            Box::new(tokio::fs::File::create(path).await?)
+        }
+        None => Box::new(tokio::io::stdout()),
+    };
+    if args.include {
+        out.write_all(response_head(response).as_bytes()).await?;
+    }
+    while let Some(chunk) = response.chunk().await? {
+        out.write_all(&chunk).await?;
+    }
+    out.flush().await?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    init_logging(cli.log_format).expect("setting default subscriber failed");
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            let retry_after = e
+                .downcast_ref::<OhttpClientError>()
+                .and_then(OhttpClientError::retry_after);
+            if let Some(delay) = retry_after {
+                eprintln!("Retry after {} seconds", delay.as_secs());
+            }
+            exit_code(e.as_ref())
+        }
+    }
+}
+
+async fn run(mut cli: Cli) -> Res<()> {
+    let profile = config::load(&cli.config_file, &cli.profile)?;
+    if let Some(client) = cli.command.client_args() {
+        profile.apply(client);
+        // The certificates may come from the profile
+        if client.relay_no_system_roots && client.relay_cacert.is_empty() {
+            return Err("--relay-no-system-roots requires --relay-cacert".into());
+        }
+    }
+    // Progress bars of concurrent requests would overwrite each other
+    if let Command::Bench { client, .. } = &mut cli.command {
+        client.output.no_progress = true;
+    }
+
+    match &cli.command {
+        Command::Post {
+            client,
+            inner,
+            body,
+        } => {
+            let request = with_body(inner_request(inner, Method::POST)?, body)?;
+            let outer_headers = parse_headers(&client.outer_headers)?;
+            let ohttp_client = build_client(client).await?;
+            if inner.dry_run {
+                return print_dry_run(&ohttp_client, &request, inner);
+            }
+            if let Some(dir) = &inner.print_curl {
+                return export_curl(&ohttp_client, client.url()?, &request, &outer_headers, dir)
+                    .await;
+            }
+            let started = Instant::now();
+            let response = ohttp_client
+                .post(client.url()?, &request, &outer_headers)
+                .await?;
+            print_response(response, &client.output, started).await
+        }
+        Command::Get { client, inner } => {
+            let request = inner_request(inner, Method::GET)?;
+            let outer_headers = parse_headers(&client.outer_headers)?;
+            let ohttp_client = build_client(client).await?;
+            if inner.dry_run {
+                return print_dry_run(&ohttp_client, &request, inner);
+            }
+            if let Some(dir) = &inner.print_curl {
+                return export_curl(&ohttp_client, client.url()?, &request, &outer_headers, dir)
+                    .await;
+            }
+            let started = Instant::now();
+            let response = ohttp_client
+                .post(client.url()?, &request, &outer_headers)
+                .await?;
+            print_response(response, &client.output, started).await
+        }
+        Command::Raw {
+            client,
+            request_file,
+            binary,
+        } => {
+            let raw_request = std::fs::read(request_file)?;
+            let outer_headers = parse_headers(&client.outer_headers)?;
+            let ohttp_client = build_client(client).await?;
+            let started = Instant::now();
+            let response = if *binary {
+                ohttp_client
+                    .post_bhttp(client.url()?, &outer_headers, &raw_request)
+                    .await?
+            } else {
+                ohttp_client
+                    .post_raw(client.url()?, &outer_headers, &raw_request)
+                    .await?
+            };
+            print_response(response, &client.output, started).await
+        }
+        Command::Bench {
+            client,
+            inner,
+            body,
+            requests,
+            concurrency,
+        } => {
+            if body.stdin || body.data.as_deref() == Some("@-") {
+                return Err("bench cannot send a body read from standard input".into());
+            }
+            if inner.dry_run || inner.print_curl.is_some() {
+                return Err("bench does not support --dry-run or --print-curl".into());
+            }
+            let request = with_body(inner_request(inner, Method::POST)?, body)?;
+            let outer_headers = parse_headers(&client.outer_headers)?;
+            let ohttp_client = build_client(client).await?;
+            let target = bench::Target {
+                url: client.url()?,
+                request: &request,
+                outer_headers: &outer_headers,
+            };
+            bench::run(
+                &ohttp_client,
+                &target,
+                *requests,
+                *concurrency,
+                client.output.json,
+            )
+            .await
+        }
+        Command::Receipt { command } => match command {
+            ReceiptCommand::Verify { receipt, cert } => verify_receipt(receipt, cert),
+        },
+        Command::Keys { command } => match command {
+            KeysCommand::Inspect { config } => inspect_config(config),
+            KeysCommand::Fetch {
+                kms_url,
+                kms_token,
+                kms_cert,
+                proxy,
+                connect_timeout,
+                output,
+            } => {
+                fetch_keys(
+                    kms_url,
+                    kms_token,
+                    kms_cert,
+                    proxy,
+                    *connect_timeout,
+                    output,
+                )
+                .await
+            }
+        },
+    }
+}
@@ -0,0 +1,28 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{env, fs, path::PathBuf};
+
+/// The header is generated in the build output directory, and also in the
+/// directory given by this variable, as `make build-ffi` does.
+const HEADER_DIR_VAR: &str = "OHTTP_CLIENT_HEADER_DIR";
+
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).unwrap();
+    let bindings = cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("unable to generate C bindings");
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    bindings.write_to_file(out_dir.join("ohttp_client.h"));
+    if let Some(header_dir) = env::var_os(HEADER_DIR_VAR) {
+        let header_dir = PathBuf::from(header_dir);
+        fs::create_dir_all(&header_dir).expect("unable to create the header directory");
+        bindings.write_to_file(header_dir.join("ohttp_client.h"));
+    }
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed={HEADER_DIR_VAR}");
+}
@@ -0,0 +1,612 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! C interface of the attested OHTTP client. All calls block until they
+//! complete. Functions returning an `OhttpStatus` other than
+//! `OHTTP_STATUS_OK` describe the failure in `ohttp_last_error`. Panics are
+//! caught at the boundary: such calls return `OHTTP_STATUS_ERROR`, or null
+//! or zero, and describe the panic in `ohttp_last_error`.
+
+use ohttp_client::{AttestationConfig, HexArg, InnerRequestBuilder, OhttpClientError};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Method,
+};
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    ptr,
+    sync::OnceLock,
+};
+use tokio::runtime::Runtime;
+
+/// Outcome of a call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OhttpStatus {
+    Ok = 0,
+    /// A pointer was null or a string was not valid.
+    InvalidArgument,
+    /// The key configuration could not be obtained or verified.
+    KmsError,
+    /// The attestation token was missing, invalid or violated the policy.
+    AttestationError,
+    /// The relay could not be reached or rejected the request.
+    RelayError,
+    /// The request could not be encapsulated or the response decapsulated.
+    EncapsulationError,
+    /// Any other failure.
+    Error,
+}
+
+/// Bytes owned by the caller, to be freed with `ohttp_buffer_free`.
+#[repr(C)]
+pub struct OhttpBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl OhttpBuffer {
+    fn new(data: Vec<u8>) -> OhttpBuffer {
+        let data = data.into_boxed_slice();
+        let len = data.len();
+        OhttpBuffer {
+            data: Box::into_raw(data).cast(),
+            len,
+        }
+    }
+
+    fn empty() -> OhttpBuffer {
+        OhttpBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+}
+
+/// Collects the settings of a client.
+pub struct OhttpClientBuilder(ohttp_client::OhttpClientBuilder);
+
+/// A client, which can be used from several threads at once.
+pub struct OhttpClient(ohttp_client::OhttpClient);
+
+/// A decapsulated response, whose body is read in chunks.
+pub struct OhttpResponse(ohttp_client::OhttpResponse);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// The runtime on which all requests are run.
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Runtime::new().expect("failed to start the tokio runtime"))
+}
+
+/// Records the message of a failure for `ohttp_last_error`.
+fn fail(status: OhttpStatus, message: String) -> OhttpStatus {
+    let message = CString::new(message.replace('\0', " ")).ok();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    status
+}
+
+/// Runs the body of a call, so that a panic is reported as an
+/// `OhttpStatus::Error` in `ohttp_last_error` and the call returns
+/// `on_panic`, rather than unwinding into the caller.
+fn catch<T>(on_panic: T, call: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(call)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown cause".to_string());
+        fail(OhttpStatus::Error, format!("panicked: {}", message));
+        on_panic
+    })
+}
+
+fn client_error(e: OhttpClientError) -> OhttpStatus {
+    let status = match e {
+        OhttpClientError::KmsError(_)
+        | OhttpClientError::ReceiptVerification(_)
+        | OhttpClientError::KeyReceiptMismatch { .. }
+        | OhttpClientError::Discovery(_) => OhttpStatus::KmsError,
+        OhttpClientError::Attestation(_) => OhttpStatus::AttestationError,
+        OhttpClientError::RelayHttp { .. }
+        | OhttpClientError::GatewayProblem { .. }
+        | OhttpClientError::Http(_)
+        | OhttpClientError::Timeout(_)
+        | OhttpClientError::CircuitOpen { .. }
+        | OhttpClientError::Auth(_) => OhttpStatus::RelayError,
+        OhttpClientError::Encapsulation(_) | OhttpClientError::Bhttp(_) => {
+            OhttpStatus::EncapsulationError
+        }
+        _ => OhttpStatus::Error,
+    };
+    fail(status, e.to_string())
+}
+
+fn invalid_argument(message: String) -> OhttpStatus {
+    fail(OhttpStatus::InvalidArgument, message)
+}
+
+/// Reads an optional string argument.
+unsafe fn string_arg(value: *const c_char, name: &str) -> Result<Option<String>, OhttpStatus> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    match CStr::from_ptr(value).to_str() {
+        Ok(value) => Ok(Some(value.to_string())),
+        Err(e) => Err(invalid_argument(format!("{}: {}", name, e))),
+    }
+}
+
+/// Reads a string argument that must be given.
+unsafe fn required_arg(value: *const c_char, name: &str) -> Result<String, OhttpStatus> {
+    string_arg(value, name)?.ok_or_else(|| invalid_argument(format!("{} is null", name)))
+}
+
+/// Reads an array of `Name: value` headers.
+unsafe fn headers_arg(headers: *const *const c_char, len: usize) -> Result<HeaderMap, OhttpStatus> {
+    let mut map = HeaderMap::new();
+    if headers.is_null() {
+        return Ok(map);
+    }
+    for i in 0..len {
+        let header = required_arg(*headers.add(i), "header")?;
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| invalid_argument(format!("{}: expected `Name: value`", header)))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|e| invalid_argument(format!("{}: {}", header, e)))?;
+        let value = HeaderValue::from_str(value.trim())
+            .map_err(|e| invalid_argument(format!("{}: {}", header, e)))?;
+        map.append(name, value);
+    }
+    Ok(map)
+}
+
+/// Reads a byte array argument, empty if null.
+unsafe fn bytes_arg<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if data.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data, len)
+    }
+}
+
+/// Returns a string owned by the caller, or null if it contains a nul byte.
+fn c_string(value: String) -> *mut c_char {
+    CString::new(value).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Applies a setting to a builder, unless it is invalid.
+unsafe fn update<T>(
+    builder: *mut OhttpClientBuilder,
+    setting: Result<T, OhttpStatus>,
+    apply: impl FnOnce(ohttp_client::OhttpClientBuilder, &T) -> ohttp_client::OhttpClientBuilder,
+) -> OhttpStatus {
+    let Some(builder) = builder.as_mut() else {
+        return invalid_argument("builder is null".to_string());
+    };
+    match setting {
+        Ok(setting) => {
+            builder.0 = apply(std::mem::take(&mut builder.0), &setting);
+            OhttpStatus::Ok
+        }
+        Err(status) => status,
+    }
+}
+
+/// Describes the last failure on this thread. The string is valid until the
+/// next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn ohttp_last_error() -> *const c_char {
+    catch(ptr::null(), || {
+        LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+    })
+}
+
+/// Returns a builder, to be passed to `ohttp_builder_build` or freed with
+/// `ohttp_builder_free`.
+#[no_mangle]
+pub extern "C" fn ohttp_builder_new() -> *mut OhttpClientBuilder {
+    catch(ptr::null_mut(), || {
+        Box::into_raw(Box::new(OhttpClientBuilder(
+            ohttp_client::OhttpClientBuilder::new(),
+        )))
+    })
+}
+
+/// # Safety
+/// `builder` must be null or returned by `ohttp_builder_new` and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_builder_free(builder: *mut OhttpClientBuilder) {
+    catch((), || {
+        if !builder.is_null() {
+            drop(Box::from_raw(builder));
+        }
+    })
+}
+
+/// Obtains the key configuration from the KMS at `kms_url`.
+///
+/// # Safety
+/// `builder` must be returned by `ohttp_builder_new` and `kms_url` must be a
+/// nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_builder_kms_url(
+    builder: *mut OhttpClientBuilder,
+    kms_url: *const c_char,
+) -> OhttpStatus {
+    catch(OhttpStatus::Error, || {
+        update(builder, string_arg(kms_url, "kms_url"), |b, kms_url| {
+            b.kms_url(kms_url)
+        })
+    })
+}
+
+/// Verifies the receipts of the KMS with the certificate at this path.
+///
+/// # Safety
+/// As for `ohttp_builder_kms_url`.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_builder_kms_cert(
+    builder: *mut OhttpClientBuilder,
+    kms_cert: *const c_char,
+) -> OhttpStatus {
+    catch(OhttpStatus::Error, || {
+        let kms_cert = string_arg(kms_cert, "kms_cert").map(|path| path.map(PathBuf::from));
+        update(builder, kms_cert, |b, kms_cert| b.kms_cert(kms_cert))
+    })
+}
+
+/// Uses the key configuration given as hex instead of obtaining it.
+///
+/// # Safety
+/// As for `ohttp_builder_kms_url`.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_builder_config(
+    builder: *mut OhttpClientBuilder,
+    config: *const c_char,
+) -> OhttpStatus {
+    catch(OhttpStatus::Error, || {
+        let config = string_arg(config, "config").and_then(|config| {
+            config
+                .map(|config| config.parse::<HexArg>())
+                .transpose()
+                .map_err(|e| invalid_argument(format!("config: {}", e)))
+        });
+        update(builder, config, |b, config| b.config(config))
+    })
+}
+
+/// Loads the key configuration saved by the CLI with --save-config, whose
+/// receipt is verified with the KMS certificate, which is then required.
+///
+/// # Safety
+/// As for `ohttp_builder_kms_url`.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_builder_config_file(
+    builder: *mut OhttpClientBuilder,
+    config_file: *const c_char,
+) -> OhttpStatus {
+    catch(OhttpStatus::Error, || {
+        let config_file =
+            string_arg(config_file, "config_file").map(|path| path.map(PathBuf::from));
+        update(builder, config_file, |b, config_file| {
+            b.saved_config(config_file)
+        })
+    })
+}
+
+/// Discovers the key configuration from the HTTPS records of this gateway
+/// name.
+///
+/// # Safety
+/// As for `ohttp_builder_kms_url`.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_builder_discover_dns(
+    builder: *mut OhttpClientBuilder,
+    name: *const c_char,
+) -> OhttpStatus {
+    catch(OhttpStatus::Error, || {
+        update(builder, string_arg(name, "name"), |b, name| {
+            b.discover_dns(name)
+        })
+    })
+}
+
+/// Requests the attestation token of the gateway and verifies it against
+/// `issuer`, and against `audience` unless null.
+///
+/// # Safety
+/// As for `ohttp_builder_kms_url`.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_builder_attestation(
+    builder: *mut OhttpClientBuilder,
+    issuer: *const c_char,
+    audience: *const c_char,
+) -> OhttpStatus {
+    catch(OhttpStatus::Error, || {
+        let attestation = required_arg(issuer, "issuer").and_then(|issuer| {
+            let attestation = AttestationConfig::new(&issuer);
+            Ok(Some(match string_arg(audience, "audience")? {
+                Some(audience) => attestation.audience(&audience),
+                None => attestation,
+            }))
+        });
+        update(builder, attestation, |b, attestation| {
+            b.attestation(attestation)
+        })
+    })
+}
+
+/// Builds a client, obtaining and verifying its key configuration, and frees
+/// the builder.
+///
+/// # Safety
+/// `builder` must be returned by `ohttp_builder_new` and is invalid after
+/// this call. `client` must point to where the client is stored.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_builder_build(
+    builder: *mut OhttpClientBuilder,
+    client: *mut *mut OhttpClient,
+) -> OhttpStatus {
+    catch(OhttpStatus::Error, || {
+        if builder.is_null() || client.is_null() {
+            ohttp_builder_free(builder);
+            return invalid_argument("builder or client is null".to_string());
+        }
+        let builder = *Box::from_raw(builder);
+        match runtime().block_on(builder.0.build()) {
+            Ok(built) => {
+                *client = Box::into_raw(Box::new(OhttpClient(built)));
+                OhttpStatus::Ok
+            }
+            Err(e) => client_error(e),
+        }
+    })
+}
+
+/// # Safety
+/// `client` must be null or returned by `ohttp_builder_build` and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_client_free(client: *mut OhttpClient) {
+    catch((), || {
+        if !client.is_null() {
+            drop(Box::from_raw(client));
+        }
+    })
+}
+
+/// Sends `body` to `target_path` of the gateway with `method` and `headers`,
+/// which default to `/`, POST and none, through the relay at `url`.
+/// `headers` and `outer_headers` are arrays of `Name: value` strings.
+///
+/// # Safety
+/// `client` must be returned by `ohttp_builder_build`, strings must be
+/// nul-terminated, arrays must hold the given number of elements and
+/// `response` must point to where the response is stored.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn ohttp_client_post(
+    client: *const OhttpClient,
+    url: *const c_char,
+    method: *const c_char,
+    target_path: *const c_char,
+    headers: *const *const c_char,
+    headers_len: usize,
+    body: *const u8,
+    body_len: usize,
+    outer_headers: *const *const c_char,
+    outer_headers_len: usize,
+    response: *mut *mut OhttpResponse,
+) -> OhttpStatus {
+    catch(OhttpStatus::Error, || {
+        let (Some(client), false) = (client.as_ref(), response.is_null()) else {
+            return invalid_argument("client or response is null".to_string());
+        };
+        let request = || -> Result<_, OhttpStatus> {
+            let url = required_arg(url, "url")?;
+            let method = string_arg(method, "method")?.unwrap_or_else(|| "POST".to_string());
+            let method = Method::from_bytes(method.as_bytes())
+                .map_err(|e| invalid_argument(format!("{}: {}", method, e)))?;
+            let mut request = InnerRequestBuilder::new()
+                .method(method)
+                .path(&string_arg(target_path, "target_path")?.unwrap_or_else(|| "/".to_string()))
+                .headers(&headers_arg(headers, headers_len)?);
+            let body = bytes_arg(body, body_len);
+            if !body.is_empty() {
+                request = request.body(body.to_vec());
+            }
+            let outer_headers = headers_arg(outer_headers, outer_headers_len)?;
+            Ok((url, request, outer_headers))
+        };
+        let (url, request, outer_headers) = match request() {
+            Ok(request) => request,
+            Err(status) => return status,
+        };
+        match runtime().block_on(client.0.post(&url, &request, &outer_headers)) {
+            Ok(received) => {
+                *response = Box::into_raw(Box::new(OhttpResponse(received)));
+                OhttpStatus::Ok
+            }
+            Err(e) => client_error(e),
+        }
+    })
+}
+
+/// Sends an encoded binary HTTP request as is through the relay at `url`.
+///
+/// # Safety
+/// As for `ohttp_client_post`.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_client_post_raw(
+    client: *const OhttpClient,
+    url: *const c_char,
+    request: *const u8,
+    request_len: usize,
+    outer_headers: *const *const c_char,
+    outer_headers_len: usize,
+    response: *mut *mut OhttpResponse,
+) -> OhttpStatus {
+    catch(OhttpStatus::Error, || {
+        let (Some(client), false) = (client.as_ref(), response.is_null()) else {
+            return invalid_argument("client or response is null".to_string());
+        };
+        let url = match required_arg(url, "url") {
+            Ok(url) => url,
+            Err(status) => return status,
+        };
+        let outer_headers = match headers_arg(outer_headers, outer_headers_len) {
+            Ok(outer_headers) => outer_headers,
+            Err(status) => return status,
+        };
+        let request = bytes_arg(request, request_len).to_vec();
+        match runtime().block_on(client.0.post_raw(&url, &outer_headers, &request)) {
+            Ok(received) => {
+                *response = Box::into_raw(Box::new(OhttpResponse(received)));
+                OhttpStatus::Ok
+            }
+            Err(e) => client_error(e),
+        }
+    })
+}
+
+/// The status of the decapsulated response.
+///
+/// # Safety
+/// `response` must be returned by a `ohttp_client_post` function and not
+/// freed.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_response_status(response: *const OhttpResponse) -> u16 {
+    catch(0, || response.as_ref().map_or(0, |r| r.0.status().as_u16()))
+}
+
+/// The value of a header of the decapsulated response, or null if absent.
+/// The string must be freed with `ohttp_string_free`.
+///
+/// # Safety
+/// As for `ohttp_response_status`, and `name` must be nul-terminated.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_response_header(
+    response: *const OhttpResponse,
+    name: *const c_char,
+) -> *mut c_char {
+    catch(ptr::null_mut(), || {
+        let (Some(response), Ok(Some(name))) = (response.as_ref(), string_arg(name, "name")) else {
+            return ptr::null_mut();
+        };
+        match response.0.headers().get(name).map(HeaderValue::to_str) {
+            Some(Ok(value)) => c_string(value.to_string()),
+            _ => ptr::null_mut(),
+        }
+    })
+}
+
+/// All headers of the decapsulated response, as `Name: value` lines ending
+/// with CRLF. The string must be freed with `ohttp_string_free`.
+///
+/// # Safety
+/// As for `ohttp_response_status`.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_response_headers(response: *const OhttpResponse) -> *mut c_char {
+    catch(ptr::null_mut(), || {
+        let Some(response) = response.as_ref() else {
+            return ptr::null_mut();
+        };
+        let headers: String = response
+            .0
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some(format!("{}: {}\r\n", name, value.to_str().ok()?)))
+            .collect();
+        c_string(headers)
+    })
+}
+
+/// The attestation token returned by the gateway, or null. The string must
+/// be freed with `ohttp_string_free`.
+///
+/// # Safety
+/// As for `ohttp_response_status`.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_response_attestation_token(
+    response: *const OhttpResponse,
+) -> *mut c_char {
+    catch(ptr::null_mut(), || {
+        response
+            .as_ref()
+            .and_then(|response| response.0.attestation())
+            .map_or(ptr::null_mut(), |attestation| {
+                c_string(attestation.token().to_string())
+            })
+    })
+}
+
+/// Waits for the next chunk of the body and stores it in `chunk`, which must
+/// be freed with `ohttp_buffer_free`. At the end of the body, the data of
+/// `chunk` is null.
+///
+/// # Safety
+/// As for `ohttp_response_status`, and `chunk` must point to where the chunk
+/// is stored.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_response_next_chunk(
+    response: *mut OhttpResponse,
+    chunk: *mut OhttpBuffer,
+) -> OhttpStatus {
+    catch(OhttpStatus::Error, || {
+        let (Some(response), false) = (response.as_mut(), chunk.is_null()) else {
+            return invalid_argument("response or chunk is null".to_string());
+        };
+        match runtime().block_on(response.0.chunk()) {
+            Ok(next) => {
+                *chunk =
+                    next.map_or_else(OhttpBuffer::empty, |next| OhttpBuffer::new(next.to_vec()));
+                OhttpStatus::Ok
+            }
+            Err(e) => client_error(e),
+        }
+    })
+}
+
+/// # Safety
+/// `response` must be null or returned by a `ohttp_client_post` function and
+/// not freed.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_response_free(response: *mut OhttpResponse) {
+    catch((), || {
+        if !response.is_null() {
+            drop(Box::from_raw(response));
+        }
+    })
+}
+
+/// # Safety
+/// `buffer` must be returned by `ohttp_response_next_chunk` and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_buffer_free(buffer: OhttpBuffer) {
+    catch((), || {
+        if !buffer.data.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                buffer.data,
+                buffer.len,
+            )));
+        }
+    })
+}
+
+/// # Safety
+/// `value` must be null or a string returned by this library and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn ohttp_string_free(value: *mut c_char) {
+    catch((), || {
+        if !value.is_null() {
+            drop(CString::from_raw(value));
+        }
+    })
+}
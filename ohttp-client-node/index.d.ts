@@ -0,0 +1,8 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+export * from './binding'
+
+declare module './binding' {
+  interface OhttpResponse extends AsyncIterable<Buffer> {}
+}
@@ -0,0 +1,14 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+const binding = require('./binding.js')
+
+// Responses are async iterables over the chunks of their body
+binding.OhttpResponse.prototype[Symbol.asyncIterator] = async function* () {
+  let chunk
+  while ((chunk = await this.chunk()) != null) {
+    yield chunk
+  }
+}
+
+module.exports = binding
@@ -0,0 +1,392 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use napi::{bindgen_prelude::Buffer, Error, Result, Status};
+use napi_derive::napi;
+use ohttp_client::{
+    AttestationClaims, AttestationConfig, HexArg, InnerRequestBuilder, OhttpClientBuilder,
+    OhttpClientError, RelayRetryPolicy, Timeouts,
+};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Method,
+};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+/// Converts an error of the client into a JavaScript error.
+fn js_error(e: OhttpClientError) -> Error {
+    let status = match e {
+        OhttpClientError::Cancelled => Status::Cancelled,
+        _ => Status::GenericFailure,
+    };
+    Error::new(status, e.to_string())
+}
+
+/// An error caused by an invalid argument.
+fn invalid_arg(message: String) -> Error {
+    Error::new(Status::InvalidArg, message)
+}
+
+/// Converts headers passed from JavaScript into a header map.
+fn header_map(headers: &HashMap<String, String>) -> Result<HeaderMap> {
+    let mut map = HeaderMap::new();
+    for (key, value) in headers {
+        let name = HeaderName::from_bytes(key.as_bytes())
+            .map_err(|e| invalid_arg(format!("{}: {}", key, e)))?;
+        let value =
+            HeaderValue::from_str(value).map_err(|e| invalid_arg(format!("{}: {}", key, e)))?;
+        map.insert(name, value);
+    }
+    Ok(map)
+}
+
+/// Converts a header map into an object, dropping non UTF-8 values.
+fn header_object(headers: &HeaderMap) -> HashMap<String, String> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+/// How a client obtains its key configuration and sends requests.
+#[napi(object)]
+pub struct ClientOptions {
+    /// URL of the KMS to obtain the key configuration from.
+    pub kms_url: Option<String>,
+    /// Path of the certificate of the KMS, verified by its receipt.
+    pub kms_cert: Option<String>,
+    /// Seconds after which the key configuration is obtained again.
+    pub key_ttl: Option<f64>,
+    /// Key configuration as hex.
+    pub config: Option<String>,
+    /// Path of a key configuration saved by the CLI with --save-config,
+    /// whose receipt is verified with `kmsCert`, which is then required.
+    pub config_file: Option<String>,
+    /// Gateway name whose HTTPS records advertise the key configuration.
+    pub discover_dns: Option<String>,
+    /// Issuer against which the attestation token of the gateway is verified.
+    pub attestation_issuer: Option<String>,
+    /// Audience that the attestation token must be issued for.
+    pub attestation_audience: Option<String>,
+    /// Seconds to connect to the relay and the KMS.
+    pub connect_timeout: Option<f64>,
+    /// Seconds for a whole request.
+    pub total_timeout: Option<f64>,
+    /// Retries of requests failing because of a transient relay error.
+    pub max_retries: Option<u32>,
+    /// Seconds before the first retry, doubled for each further one.
+    pub retry_backoff: Option<f64>,
+}
+
+/// The inner request sent by `post`.
+#[napi(object)]
+#[derive(Default)]
+pub struct RequestOptions {
+    /// Path of the request at the gateway, `/` by default.
+    pub target_path: Option<String>,
+    /// Method of the request, POST by default.
+    pub method: Option<String>,
+    pub headers: Option<HashMap<String, String>>,
+    /// Multipart form fields. Values starting with `@` are paths of files.
+    pub form_fields: Option<HashMap<String, String>>,
+    /// Paths of files, by field name, which are read into memory in full
+    /// when the request is built, since the request is encapsulated at once.
+    pub files: Option<HashMap<String, String>>,
+    /// Body sent as is, with the content type given in `headers`.
+    pub body: Option<Buffer>,
+    /// Headers of the outer request to the relay, e.g. an API key.
+    pub outer_headers: Option<HashMap<String, String>>,
+}
+
+impl RequestOptions {
+    fn inner_request(&self) -> Result<InnerRequestBuilder> {
+        let form_fields = self.form_fields.clone().unwrap_or_default();
+        let files = self.files.clone().unwrap_or_default();
+        if self.body.is_some() && !(form_fields.is_empty() && files.is_empty()) {
+            return Err(invalid_arg(
+                "body cannot be combined with formFields or files".to_string(),
+            ));
+        }
+        let method = self.method.as_deref().unwrap_or("POST");
+        let method = Method::from_bytes(method.as_bytes())
+            .map_err(|e| invalid_arg(format!("{}: {}", method, e)))?;
+        let mut request = InnerRequestBuilder::new()
+            .method(method)
+            .path(self.target_path.as_deref().unwrap_or("/"))
+            .headers(&header_map(&self.headers.clone().unwrap_or_default())?);
+        for (key, value) in form_fields.iter() {
+            // If the value starts with '@', it is treated as a file path.
+            request = match value.strip_prefix('@') {
+                Some(path) => request.file_part(key, path),
+                None => request.form_field(key, value),
+            };
+        }
+        for (key, path) in files.iter() {
+            request = request.file_part(key, path);
+        }
+        if let Some(body) = &self.body {
+            request = request.body(body.to_vec());
+        }
+        Ok(request)
+    }
+}
+
+/// The Rust client of a JavaScript client, built on first use so that the
+/// key configuration is obtained and verified once rather than for every
+/// request.
+struct ClientCache {
+    kms_url: Option<String>,
+    kms_cert: Option<PathBuf>,
+    config: Option<HexArg>,
+    config_file: Option<PathBuf>,
+    discover_dns: Option<String>,
+    attestation: Option<AttestationConfig>,
+    timeouts: Timeouts,
+    relay_retry: Option<RelayRetryPolicy>,
+    key_ttl: Option<Duration>,
+    client: Mutex<Option<(ohttp_client::OhttpClient, Instant)>>,
+}
+
+impl ClientCache {
+    fn new(options: ClientOptions) -> Result<ClientCache> {
+        let seconds = |name: &str, seconds: Option<f64>| {
+            seconds
+                .map(Duration::try_from_secs_f64)
+                .transpose()
+                .map_err(|e| invalid_arg(format!("{}: {}", name, e)))
+        };
+        let key_ttl = seconds("keyTtl", options.key_ttl)?;
+        let timeouts = Timeouts {
+            connect: seconds("connectTimeout", options.connect_timeout)?,
+            total: seconds("totalTimeout", options.total_timeout)?,
+            ..Timeouts::default()
+        };
+        let retry_backoff = seconds("retryBackoff", options.retry_backoff)?;
+        let relay_retry = (options.max_retries.is_some() || retry_backoff.is_some()).then(|| {
+            let default = RelayRetryPolicy::default();
+            RelayRetryPolicy {
+                max_retries: options.max_retries.unwrap_or(default.max_retries),
+                base_delay: retry_backoff.unwrap_or(default.base_delay),
+                ..default
+            }
+        });
+        let config = options
+            .config
+            .map(|config| config.parse::<HexArg>())
+            .transpose()
+            .map_err(|e| invalid_arg(format!("config: {}", e)))?;
+        if options.kms_url.is_none()
+            && config.is_none()
+            && options.config_file.is_none()
+            && options.discover_dns.is_none()
+        {
+            return Err(invalid_arg(
+                "one of kmsUrl, config, configFile or discoverDns is required".to_string(),
+            ));
+        }
+        let attestation = options.attestation_issuer.map(|issuer| {
+            let config = AttestationConfig::new(&issuer);
+            match &options.attestation_audience {
+                Some(audience) => config.audience(audience),
+                None => config,
+            }
+        });
+        Ok(ClientCache {
+            kms_url: options.kms_url,
+            kms_cert: options.kms_cert.map(PathBuf::from),
+            config,
+            config_file: options.config_file.map(PathBuf::from),
+            discover_dns: options.discover_dns,
+            attestation,
+            timeouts,
+            relay_retry,
+            key_ttl,
+            client: Mutex::new(None),
+        })
+    }
+
+    /// Returns the cached client, building it again once it is older than the
+    /// key TTL.
+    async fn client(&self) -> Result<ohttp_client::OhttpClient> {
+        let mut cached = self.client.lock().await;
+        if let Some((client, built)) = cached.as_ref() {
+            if self.key_ttl.map_or(true, |ttl| built.elapsed() < ttl) {
+                return Ok(client.clone());
+            }
+        }
+
+        let client = OhttpClientBuilder::new()
+            .kms_url(&self.kms_url)
+            .kms_cert(&self.kms_cert)
+            .config(&self.config)
+            .saved_config(&self.config_file)
+            .discover_dns(&self.discover_dns)
+            .attestation(&self.attestation)
+            .timeouts(&self.timeouts)
+            .relay_retry(&self.relay_retry)
+            .build()
+            .await
+            .map_err(js_error)?;
+        *cached = Some((client.clone(), Instant::now()));
+        Ok(client)
+    }
+}
+
+#[napi]
+pub struct OhttpClient {
+    cache: Arc<ClientCache>,
+}
+
+#[napi]
+impl OhttpClient {
+    /// The key configuration is obtained from the KMS at `kmsUrl`, or given
+    /// as hex by `config`, or loaded from `configFile`, or discovered from
+    /// the HTTPS records of `discoverDns`.
+    #[napi(constructor)]
+    pub fn new(options: ClientOptions) -> Result<Self> {
+        Ok(OhttpClient {
+            cache: Arc::new(ClientCache::new(options)?),
+        })
+    }
+
+    /// Encapsulates and sends a binary HTTP request as is.
+    #[napi]
+    pub async fn post_raw(
+        &self,
+        url: String,
+        http_request: Buffer,
+        outer_headers: Option<HashMap<String, String>>,
+    ) -> Result<OhttpResponse> {
+        let outer_headers = header_map(&outer_headers.unwrap_or_default())?;
+        let client = self.cache.client().await?;
+        let response = client
+            .post_raw(&url, &outer_headers, &http_request.to_vec())
+            .await
+            .map_err(js_error)?;
+        Ok(OhttpResponse::new(response))
+    }
+
+    /// Encapsulates and sends a request to the gateway through the relay at
+    /// `url`.
+    #[napi]
+    pub async fn post(
+        &self,
+        url: String,
+        request: Option<RequestOptions>,
+    ) -> Result<OhttpResponse> {
+        let request = request.unwrap_or_default();
+        let inner_request = request.inner_request()?;
+        let outer_headers = header_map(&request.outer_headers.unwrap_or_default())?;
+        let client = self.cache.client().await?;
+        let response = client
+            .post(&url, &inner_request, &outer_headers)
+            .await
+            .map_err(js_error)?;
+        Ok(OhttpResponse::new(response))
+    }
+}
+
+/// A decapsulated response, whose body is read in chunks as it arrives.
+#[napi]
+pub struct OhttpResponse {
+    status: u16,
+    headers: HashMap<String, String>,
+    attestation: Option<AttestationClaims>,
+    response: Arc<Mutex<ohttp_client::OhttpResponse>>,
+}
+
+impl OhttpResponse {
+    /// Wraps a response, keeping its status and headers so that they can be
+    /// read without waiting for the body.
+    fn new(response: ohttp_client::OhttpResponse) -> Self {
+        OhttpResponse {
+            status: response.status().as_u16(),
+            headers: header_object(response.headers()),
+            attestation: response.attestation().cloned(),
+            response: Arc::new(Mutex::new(response)),
+        }
+    }
+
+    /// Reads the remainder of the body.
+    async fn read_body(&self) -> Result<Vec<u8>> {
+        let mut response = self.response.lock().await;
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(js_error)? {
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+}
+
+#[napi]
+impl OhttpResponse {
+    #[napi(getter)]
+    pub fn status(&self) -> u32 {
+        u32::from(self.status)
+    }
+
+    #[napi(getter)]
+    pub fn headers(&self) -> HashMap<String, String> {
+        self.headers.clone()
+    }
+
+    /// The attestation token returned by the gateway, if any.
+    #[napi(getter)]
+    pub fn attestation_token(&self) -> Option<String> {
+        self.attestation
+            .as_ref()
+            .map(|attestation| attestation.token().to_string())
+    }
+
+    /// The claims of the attestation token, if any.
+    #[napi(getter)]
+    pub fn attestation_claims(&self) -> Option<Value> {
+        self.attestation
+            .as_ref()
+            .map(|attestation| Value::Object(attestation.claims().clone()))
+    }
+
+    /// Whether the attestation token was verified against the issuer given
+    /// to the client.
+    #[napi(getter)]
+    pub fn attestation_verified(&self) -> bool {
+        self.attestation
+            .as_ref()
+            .is_some_and(|attestation| attestation.verified())
+    }
+
+    /// Returns the next chunk of the body, or null at its end.
+    #[napi]
+    pub async fn chunk(&self) -> Result<Option<Buffer>> {
+        let chunk = self.response.lock().await.chunk().await.map_err(js_error)?;
+        Ok(chunk.map(|chunk| Buffer::from(chunk.to_vec())))
+    }
+
+    /// Reads the remainder of the body.
+    #[napi]
+    pub async fn read(&self) -> Result<Buffer> {
+        Ok(Buffer::from(self.read_body().await?))
+    }
+
+    /// Reads the remainder of the body as text. Invalid UTF-8 sequences are
+    /// replaced.
+    #[napi]
+    pub async fn text(&self) -> Result<String> {
+        let body = self.read_body().await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Reads the remainder of the body and decodes it from JSON.
+    #[napi]
+    pub async fn json(&self) -> Result<Value> {
+        let body = self.read_body().await?;
+        serde_json::from_slice(&body).map_err(|e| Error::from_reason(e.to_string()))
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Throughput of the request pipeline across payload sizes: encoding the
+//! inner request as bhttp, encapsulating it with each AEAD of the X25519
+//! suites, and sending it through the in-process gateway, which includes the
+//! decapsulation of the standard and chunked responses. Run with
+//! `cargo bench -p ohttp-client --features test-util`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ohttp::{
+    hpke::{Aead, Kdf, Kem},
+    ClientRequest, KeyConfig, SymmetricSuite,
+};
+use ohttp_client::{InnerRequestBuilder, MockGateway, OhttpClientBuilder, OhttpProtocol};
+use reqwest::header::HeaderMap;
+use std::time::Instant;
+use tokio::runtime::Runtime;
+
+const SIZES: [usize; 4] = [1 << 10, 64 << 10, 1 << 20, 8 << 20];
+
+const AEADS: [(&str, Aead); 3] = [
+    ("aes128gcm", Aead::Aes128Gcm),
+    ("aes256gcm", Aead::Aes256Gcm),
+    ("chacha20poly1305", Aead::ChaCha20Poly1305),
+];
+
+fn request(size: usize) -> InnerRequestBuilder {
+    InnerRequestBuilder::new()
+        .path("/bench")
+        .typed_body(vec![0x5a; size], "application/octet-stream")
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for size in SIZES {
+        let request = request(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &request, |b, request| {
+            b.iter(|| request.build().unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn encapsulate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encapsulate");
+    for (name, aead) in AEADS {
+        let config = KeyConfig::new(
+            1,
+            Kem::X25519Sha256,
+            vec![SymmetricSuite::new(Kdf::HkdfSha256, aead)],
+        )
+        .unwrap();
+        let config = KeyConfig::encode_list(&[&config]).unwrap();
+        for size in SIZES {
+            let bhttp = request(size).build().unwrap();
+            group.throughput(Throughput::Bytes(size as u64));
+            group.bench_with_input(BenchmarkId::new(name, size), &bhttp, |b, bhttp| {
+                b.iter(|| {
+                    ClientRequest::from_encoded_config_list(&config)
+                        .unwrap()
+                        .encapsulate(bhttp)
+                        .unwrap()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Each sample sends its requests through a fresh gateway, since the gateway
+/// keeps every request it receives.
+fn round_trip(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let url = "https://relay.invalid/score".to_string();
+    let outer_headers = HeaderMap::new();
+    let mut group = c.benchmark_group("round_trip");
+    group.sample_size(10);
+    for (name, protocol) in [
+        ("standard", OhttpProtocol::Standard),
+        ("chunked", OhttpProtocol::Chunked),
+    ] {
+        for size in SIZES {
+            let request = request(size);
+            group.throughput(Throughput::Bytes(size as u64));
+            group.bench_with_input(BenchmarkId::new(name, size), &request, |b, request| {
+                b.to_async(&runtime).iter_custom(|iters| {
+                    let (url, outer_headers) = (&url, &outer_headers);
+                    async move {
+                        let gateway = MockGateway::new().unwrap();
+                        let builder = OhttpClientBuilder::new().protocol(protocol);
+                        let client = gateway.client(builder).await.unwrap();
+                        let start = Instant::now();
+                        for _ in 0..iters {
+                            let response = client.post(url, request, outer_headers).await.unwrap();
+                            response.bytes().await.unwrap();
+                        }
+                        start.elapsed()
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode, encapsulate, round_trip);
+criterion_main!(benches);
@@ -0,0 +1,330 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::err::{OhttpClientError, Res};
+use reqwest::{header::HeaderMap, Client};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    fs,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use verifier::{Claims, TokenValidation};
+
+/// Outer header carrying the attestation token of the gateway. The gateway
+/// only returns a token when the request carries this header set to `true`.
+pub(crate) const ATTESTATION_TOKEN_HEADER: &str = "x-attestation-token";
+
+/// Configuration of attestation token verification.
+#[derive(Debug, Clone)]
+pub struct AttestationConfig {
+    issuer: String,
+    audience: Option<String>,
+    enforce: bool,
+}
+
+impl AttestationConfig {
+    /// Trusts tokens issued by the attestation service at `issuer`, e.g.
+    /// `https://sharedeus2.eus2.attest.azure.net`. Requests fail when the
+    /// token is missing or invalid unless `enforce(false)` is set.
+    pub fn new(issuer: &str) -> AttestationConfig {
+        AttestationConfig {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            audience: None,
+            enforce: true,
+        }
+    }
+
+    pub fn audience(mut self, audience: &str) -> AttestationConfig {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    /// When disabled, verification failures are logged and the response is
+    /// returned with unverified claims.
+    pub fn enforce(mut self, enforce: bool) -> AttestationConfig {
+        self.enforce = enforce;
+        self
+    }
+}
+
+/// The attestation token returned with a response and its claims.
+#[derive(Debug, Clone)]
+pub struct AttestationClaims {
+    token: String,
+    claims: Claims,
+    verified: bool,
+}
+
+impl AttestationClaims {
+    /// Decodes the claims of a token that the client did not verify.
+    pub(crate) fn unverified(token: &str) -> AttestationClaims {
+        AttestationClaims {
+            token: token.to_string(),
+            claims: verifier::decode_claims(token).unwrap_or_default(),
+            verified: false,
+        }
+    }
+
+    /// The raw attestation token.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// All claims of the token.
+    pub fn claims(&self) -> &Claims {
+        &self.claims
+    }
+
+    /// Returns a single claim, e.g. `x-ms-sevsnpvm-hostdata`.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.claims.get(name)
+    }
+
+    /// Whether the token signature and claims were successfully verified.
+    pub fn verified(&self) -> bool {
+        self.verified
+    }
+}
+
+/// Time during which the signing keys are not fetched again, so that tokens
+/// failing verification do not each cause a request to the issuer.
+const JWKS_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The issuer's JSON web key set and when it was fetched.
+struct CachedJwks {
+    jwks: String,
+    fetched: Instant,
+}
+
+/// Verifies attestation tokens against the issuer's signing keys, which are
+/// fetched once and cached, and fetched again when a token fails
+/// verification, as the issuer may have rotated them.
+#[derive(Clone)]
+pub(crate) struct AttestationVerifier {
+    config: AttestationConfig,
+    /// Client with the proxy and timeouts of the client settings.
+    client: Client,
+    /// Held while the keys are fetched, so that concurrent verifications
+    /// fetch them once.
+    jwks: Arc<Mutex<Option<CachedJwks>>>,
+}
+
+impl AttestationVerifier {
+    pub(crate) fn new(config: AttestationConfig, client: Client) -> AttestationVerifier {
+        AttestationVerifier {
+            config,
+            client,
+            jwks: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    async fn fetch_jwks(&self) -> Res<CachedJwks> {
+        let url = format!("{}/certs", self.config.issuer);
+        info!("Fetching attestation signing keys from {url}...");
+        let jwks = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(CachedJwks {
+            jwks,
+            fetched: Instant::now(),
+        })
+    }
+
+    /// Returns the issuer's JSON web key set, fetching it if not cached.
+    async fn jwks(&self) -> Res<String> {
+        let mut cached = self.jwks.lock().await;
+        if let Some(cached) = &*cached {
+            return Ok(cached.jwks.clone());
+        }
+        let fetched = self.fetch_jwks().await?;
+        let jwks = fetched.jwks.clone();
+        *cached = Some(fetched);
+        Ok(jwks)
+    }
+
+    /// Fetches the issuer's JSON web key set again, unless it was fetched
+    /// within the refetch interval, in which case `None` is returned.
+    async fn refetch_jwks(&self) -> Res<Option<String>> {
+        let mut cached = self.jwks.lock().await;
+        if let Some(cached) = &*cached {
+            if cached.fetched.elapsed() < JWKS_REFETCH_INTERVAL {
+                return Ok(None);
+            }
+        }
+        let fetched = self.fetch_jwks().await?;
+        let jwks = fetched.jwks.clone();
+        *cached = Some(fetched);
+        Ok(Some(jwks))
+    }
+
+    async fn verify_token(&self, token: &str) -> Res<Claims> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| OhttpClientError::Attestation(e.to_string()))?
+            .as_secs();
+        let validation = TokenValidation {
+            issuer: &self.config.issuer,
+            audience: self.config.audience.as_deref(),
+            now,
+        };
+
+        let jwks = self.jwks().await?;
+        match verifier::verify_token(token, &jwks, &validation) {
+            Ok(claims) => Ok(claims),
+            // The issuer may have rotated its signing keys
+            Err(e) => match self.refetch_jwks().await {
+                Ok(Some(jwks)) => verifier::verify_token(token, &jwks, &validation)
+                    .map_err(|e| OhttpClientError::Attestation(e.to_string())),
+                Ok(None) => Err(OhttpClientError::Attestation(e.to_string())),
+                Err(fetch) => Err(OhttpClientError::Attestation(format!(
+                    "{e}, and fetching the signing keys of the issuer again failed: {fetch}"
+                ))),
+            },
+        }
+    }
+
+    /// Verifies the attestation token in the outer response headers.
+    pub(crate) async fn verify(&self, headers: &HeaderMap) -> Res<Option<AttestationClaims>> {
+        let token = match headers.get(ATTESTATION_TOKEN_HEADER) {
+            Some(token) => token
+                .to_str()
+                .map_err(|e| OhttpClientError::Attestation(e.to_string()))?
+                .to_string(),
+            None if self.config.enforce => {
+                return Err(OhttpClientError::Attestation(
+                    "response carries no attestation token".to_string(),
+                ))
+            }
+            None => {
+                warn!("Response carries no attestation token");
+                return Ok(None);
+            }
+        };
+
+        match self.verify_token(&token).await {
+            Ok(claims) => Ok(Some(AttestationClaims {
+                token,
+                claims,
+                verified: true,
+            })),
+            Err(e) if self.config.enforce => Err(e),
+            Err(e) => {
+                warn!("Attestation token verification failed: {e}");
+                Ok(Some(AttestationClaims::unverified(&token)))
+            }
+        }
+    }
+}
+
+/// Claims describing the TEE of a confidential VM are nested in this claim;
+/// confidential containers carry them at the top level.
+const ISOLATION_TEE_CLAIM: &str = "x-ms-isolation-tee";
+const ATTESTATION_TYPE_CLAIM: &str = "x-ms-attestation-type";
+const MEASUREMENT_CLAIM: &str = "x-ms-sevsnpvm-launchmeasurement";
+const HOST_DATA_CLAIM: &str = "x-ms-sevsnpvm-hostdata";
+const GUEST_SVN_CLAIM: &str = "x-ms-sevsnpvm-guestsvn";
+const DEBUGGABLE_CLAIM: &str = "x-ms-sevsnpvm-is-debuggable";
+
+/// Expectations on the TEE that served a request, evaluated against the
+/// verified attestation token before the response is handed to the caller.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttestationPolicy {
+    /// Expected TEE type, e.g. `sevsnpvm`.
+    pub tee_type: Option<String>,
+    /// Allowed launch measurements. Any measurement is allowed if empty.
+    pub measurements: Vec<String>,
+    /// Allowed host data. Any host data is allowed if empty.
+    pub host_data: Vec<String>,
+    /// Minimum guest security version number.
+    pub min_svn: Option<u64>,
+    /// Whether debuggable TEEs are accepted.
+    pub allow_debug: bool,
+}
+
+impl AttestationPolicy {
+    /// Reads a policy from a JSON file.
+    pub fn from_file(path: &Path) -> Res<AttestationPolicy> {
+        let policy = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&policy)?)
+    }
+
+    /// Looks up a TEE claim at the top level or in the isolation TEE claims.
+    fn claim<'a>(claims: &'a AttestationClaims, name: &str) -> Option<&'a Value> {
+        claims.get(name).or_else(|| {
+            claims
+                .get(ISOLATION_TEE_CLAIM)
+                .and_then(|tee| tee.get(name))
+        })
+    }
+
+    fn violation(reason: String) -> OhttpClientError {
+        OhttpClientError::Attestation(format!("attestation policy violated: {reason}"))
+    }
+
+    /// Checks the claims of a verified attestation token against the policy.
+    pub fn evaluate(&self, claims: &AttestationClaims) -> Res<()> {
+        if !claims.verified() {
+            return Err(Self::violation(
+                "the attestation token was not verified".to_string(),
+            ));
+        }
+
+        let string_claim = |name: &str| Self::claim(claims, name).and_then(Value::as_str);
+
+        if let Some(tee_type) = &self.tee_type {
+            if string_claim(ATTESTATION_TYPE_CLAIM) != Some(tee_type.as_str()) {
+                return Err(Self::violation(format!("TEE type is not {tee_type}")));
+            }
+        }
+
+        if !self.measurements.is_empty() {
+            match string_claim(MEASUREMENT_CLAIM) {
+                Some(measurement) if self.measurements.iter().any(|m| m == measurement) => {}
+                _ => {
+                    return Err(Self::violation(
+                        "launch measurement not allowed".to_string(),
+                    ))
+                }
+            }
+        }
+
+        if !self.host_data.is_empty() {
+            match string_claim(HOST_DATA_CLAIM) {
+                Some(host_data) if self.host_data.iter().any(|h| h == host_data) => {}
+                _ => return Err(Self::violation("host data not allowed".to_string())),
+            }
+        }
+
+        if let Some(min_svn) = self.min_svn {
+            match Self::claim(claims, GUEST_SVN_CLAIM).and_then(Value::as_u64) {
+                Some(svn) if svn >= min_svn => {}
+                _ => {
+                    return Err(Self::violation(format!(
+                        "guest SVN is lower than {min_svn}"
+                    )))
+                }
+            }
+        }
+
+        if !self.allow_debug {
+            // Fail closed if the claim is missing
+            if Self::claim(claims, DEBUGGABLE_CLAIM).and_then(Value::as_bool) != Some(false) {
+                return Err(Self::violation("TEE may be debuggable".to_string()));
+            }
+        }
+
+        info!("Attestation token satisfies the attestation policy.");
+        Ok(())
+    }
+}
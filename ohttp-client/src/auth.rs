@@ -0,0 +1,135 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::err::{OhttpClientError, Res};
+use base64::{engine::general_purpose, Engine};
+use hmac::{Hmac, Mac};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    Method,
+};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CONTENT_SHA256_HEADER: &str = "x-content-sha256";
+const DATE_HEADER: &str = "x-ohttp-date";
+
+/// The parts of an inner request that an `InnerAuth` hook authenticates.
+pub struct InnerAuthRequest<'a> {
+    pub method: &'a Method,
+    /// The percent-encoded path and query of the request.
+    pub target: &'a str,
+    pub headers: &'a HeaderMap,
+    /// The content of the request, or `None` if it is streamed rather than
+    /// read before the request is built.
+    pub body: Option<&'a [u8]>,
+}
+
+/// Authenticates inner requests to the target, e.g. with a token or a
+/// signature. The hook runs when the request is built and the headers it
+/// returns are added to the inner request, so that the credentials are only
+/// visible to the gateway and the target, never to the relay. They are not
+/// logged either.
+pub trait InnerAuth: Send + Sync {
+    fn headers(&self, request: &InnerAuthRequest<'_>) -> Res<HeaderMap>;
+}
+
+impl<F> InnerAuth for F
+where
+    F: Fn(&InnerAuthRequest<'_>) -> Res<HeaderMap> + Send + Sync,
+{
+    fn headers(&self, request: &InnerAuthRequest<'_>) -> Res<HeaderMap> {
+        self(request)
+    }
+}
+
+fn header_value(value: &str) -> Res<HeaderValue> {
+    HeaderValue::from_str(value).map_err(|e| OhttpClientError::Auth(e.to_string()))
+}
+
+/// Sends a fixed bearer token, e.g. a JWT, in the `Authorization` header.
+pub struct BearerAuth {
+    token: String,
+}
+
+impl BearerAuth {
+    pub fn new(token: &str) -> BearerAuth {
+        BearerAuth {
+            token: token.to_string(),
+        }
+    }
+}
+
+impl InnerAuth for BearerAuth {
+    fn headers(&self, _: &InnerAuthRequest<'_>) -> Res<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            header_value(&format!("Bearer {}", self.token))?,
+        );
+        Ok(headers)
+    }
+}
+
+/// Signs requests with a key shared with the target. The signature is the
+/// HMAC-SHA256 of the method, target, timestamp and hex SHA-256 digest of
+/// the body, separated by newlines, and is sent as
+///
+/// ```text
+/// x-content-sha256: <hex digest of the body>
+/// x-ohttp-date: <seconds since the Unix epoch>
+/// authorization: HMAC-SHA256 keyId=<key ID>,signature=<base64 signature>
+/// ```
+///
+/// Streamed bodies cannot be signed, since the headers precede the body.
+pub struct HmacAuth {
+    key_id: String,
+    key: Vec<u8>,
+}
+
+impl HmacAuth {
+    pub fn new(key_id: &str, key: &[u8]) -> HmacAuth {
+        HmacAuth {
+            key_id: key_id.to_string(),
+            key: key.to_vec(),
+        }
+    }
+}
+
+impl InnerAuth for HmacAuth {
+    fn headers(&self, request: &InnerAuthRequest<'_>) -> Res<HeaderMap> {
+        let body = request.body.ok_or_else(|| {
+            OhttpClientError::Auth("streamed request bodies cannot be signed".to_string())
+        })?;
+        let digest = hex::encode(Sha256::digest(body));
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .to_string();
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key)
+            .map_err(|e| OhttpClientError::Auth(e.to_string()))?;
+        let signed = [
+            request.method.as_str(),
+            request.target,
+            timestamp.as_str(),
+            digest.as_str(),
+        ]
+        .join("\n");
+        mac.update(signed.as_bytes());
+        let signature = general_purpose::STANDARD.encode(mac.finalize().into_bytes());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_SHA256_HEADER, header_value(&digest)?);
+        headers.insert(DATE_HEADER, header_value(&timestamp)?);
+        headers.insert(
+            AUTHORIZATION,
+            header_value(&format!(
+                "HMAC-SHA256 keyId={},signature={signature}",
+                self.key_id
+            ))?,
+        );
+        Ok(headers)
+    }
+}
@@ -0,0 +1,212 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::err::{OhttpClientError, Res};
+use reqwest::Client;
+use serde::Deserialize;
+use std::{
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{info, trace};
+
+/// Azure Instance Metadata Service token endpoint for managed identities.
+const IMDS_TOKEN_ENDPOINT: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+
+/// Tokens are refreshed this long before they expire.
+const REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+/// Time for acquiring a token from the local identity endpoint, which is
+/// unreachable outside of Azure.
+const IDENTITY_ENDPOINT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time for acquiring a token from Entra ID.
+const ENTRA_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How a token is acquired from Entra ID.
+#[derive(Debug, Clone)]
+enum AzureCredential {
+    /// The system assigned managed identity, or a user assigned identity if
+    /// a client ID is given.
+    ManagedIdentity { client_id: Option<String> },
+    /// An application registration authenticating with a client secret.
+    ClientSecret {
+        tenant_id: String,
+        client_id: String,
+        client_secret: String,
+    },
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    /// IMDS returns the lifetime as a string, Entra ID as a number.
+    expires_in: ExpiresIn,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExpiresIn {
+    Seconds(u64),
+    Text(String),
+}
+
+impl ExpiresIn {
+    fn seconds(&self) -> Res<u64> {
+        match self {
+            ExpiresIn::Seconds(seconds) => Ok(*seconds),
+            ExpiresIn::Text(text) => text.parse().map_err(|_| {
+                OhttpClientError::Auth(format!("token lifetime {text:?} is not a number"))
+            }),
+        }
+    }
+}
+
+/// Acquires Entra ID tokens for the relay and caches them until shortly
+/// before they expire. The token is sent as `Authorization: Bearer` outer
+/// header with every request.
+#[derive(Debug, Clone)]
+pub struct AzureTokenProvider {
+    credential: AzureCredential,
+    scope: String,
+    cache: Arc<Mutex<Option<(String, Instant)>>>,
+}
+
+impl AzureTokenProvider {
+    fn new(credential: AzureCredential, scope: &str) -> AzureTokenProvider {
+        AzureTokenProvider {
+            credential,
+            scope: scope.to_string(),
+            cache: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Acquires tokens for `scope` (e.g. `api://relay/.default`) with a
+    /// managed identity. `client_id` selects a user assigned identity.
+    pub fn managed_identity(scope: &str, client_id: Option<&str>) -> AzureTokenProvider {
+        let credential = AzureCredential::ManagedIdentity {
+            client_id: client_id.map(str::to_string),
+        };
+        AzureTokenProvider::new(credential, scope)
+    }
+
+    /// Acquires tokens for `scope` with the client credentials flow.
+    pub fn client_secret(
+        scope: &str,
+        tenant_id: &str,
+        client_id: &str,
+        client_secret: &str,
+    ) -> AzureTokenProvider {
+        let credential = AzureCredential::ClientSecret {
+            tenant_id: tenant_id.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+        };
+        AzureTokenProvider::new(credential, scope)
+    }
+
+    /// Uses client credentials if `AZURE_TENANT_ID`, `AZURE_CLIENT_ID` and
+    /// `AZURE_CLIENT_SECRET` are set, and a managed identity otherwise.
+    pub fn from_env(scope: &str) -> AzureTokenProvider {
+        let client_id = env::var("AZURE_CLIENT_ID").ok();
+        match (
+            env::var("AZURE_TENANT_ID"),
+            &client_id,
+            env::var("AZURE_CLIENT_SECRET"),
+        ) {
+            (Ok(tenant_id), Some(client_id), Ok(client_secret)) => {
+                AzureTokenProvider::client_secret(scope, &tenant_id, client_id, &client_secret)
+            }
+            _ => AzureTokenProvider::managed_identity(scope, client_id.as_deref()),
+        }
+    }
+
+    /// Returns a cached token, or acquires a new one if it is about to expire.
+    pub(crate) async fn token(&self) -> Res<String> {
+        if let Some((token, expires)) = self.cache.lock().unwrap().clone() {
+            if Instant::now() + REFRESH_MARGIN < expires {
+                return Ok(token);
+            }
+        }
+
+        let (token, expires_in) = self.acquire().await?;
+        let expires = Instant::now() + expires_in;
+        *self.cache.lock().unwrap() = Some((token.clone(), expires));
+        Ok(token)
+    }
+
+    /// Acquires a token and returns it with its lifetime.
+    async fn acquire(&self) -> Res<(String, Duration)> {
+        let request = match &self.credential {
+            AzureCredential::ManagedIdentity { client_id } => {
+                // The identity endpoint is local and must not be reached
+                // through the proxies of the environment
+                let client = Client::builder()
+                    .no_proxy()
+                    .timeout(IDENTITY_ENDPOINT_TIMEOUT)
+                    .build()?;
+                info!(
+                    "Acquiring token for {} with managed identity...",
+                    self.scope
+                );
+                // Managed identity endpoints take a resource rather than a scope
+                let resource = self.scope.trim_end_matches("/.default");
+                let mut query = vec![("resource", resource)];
+                if let Some(client_id) = client_id {
+                    query.push(("client_id", client_id.as_str()));
+                }
+
+                // App Service and Functions expose their own identity endpoint
+                match (env::var("IDENTITY_ENDPOINT"), env::var("IDENTITY_HEADER")) {
+                    (Ok(endpoint), Ok(header)) => {
+                        query.push(("api-version", "2019-08-01"));
+                        client
+                            .get(endpoint)
+                            .header("x-identity-header", header)
+                            .query(&query)
+                    }
+                    _ => {
+                        query.push(("api-version", "2018-02-01"));
+                        client
+                            .get(IMDS_TOKEN_ENDPOINT)
+                            .header("metadata", "true")
+                            .query(&query)
+                    }
+                }
+            }
+            AzureCredential::ClientSecret {
+                tenant_id,
+                client_id,
+                client_secret,
+            } => {
+                info!(
+                    "Acquiring token for {} with client credentials...",
+                    self.scope
+                );
+                let client = Client::builder().timeout(ENTRA_TIMEOUT).build()?;
+                let url =
+                    format!("https://login.microsoftonline.com/{tenant_id}/oauth2/v2.0/token");
+                client.post(url).form(&[
+                    ("grant_type", "client_credentials"),
+                    ("client_id", client_id),
+                    ("client_secret", client_secret),
+                    ("scope", &self.scope),
+                ])
+            }
+        };
+
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(OhttpClientError::Auth(format!(
+                "token acquisition failed with status {status}: {body}"
+            )));
+        }
+        let token: TokenResponse = serde_json::from_str(&response.text().await?)?;
+        let expires_in = token.expires_in.seconds()?;
+        trace!("Acquired token valid for {expires_in}s");
+        Ok((token.access_token, Duration::from_secs(expires_in)))
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    err::{OhttpClientError, Res},
+    relays::relay_failed,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// When requests fail fast rather than being sent, during an outage of the
+/// relay or the gateway. After `failure_threshold` consecutive failures the
+/// circuit opens, and requests fail with `CircuitOpen` without being sent.
+/// After `open_duration`, a single request is let through as a probe: the
+/// circuit closes again if it succeeds and stays open otherwise.
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerPolicy {
+    /// Consecutive failures after which the circuit opens.
+    pub failure_threshold: u32,
+    /// Time during which requests fail fast before the next probe.
+    pub open_duration: Duration,
+}
+
+impl Default for CircuitBreakerPolicy {
+    fn default() -> Self {
+        CircuitBreakerPolicy {
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A probe was sent at `since`. Another probe is let through if it has
+    /// not completed after `open_duration`, e.g. because it was cancelled.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+/// Whether the failure is caused by the relay or the gateway being down,
+/// rather than by the request.
+fn is_outage(e: &OhttpClientError) -> bool {
+    match e {
+        OhttpClientError::GatewayProblem { status, .. } => status.is_server_error(),
+        OhttpClientError::Timeout(_) => true,
+        e => relay_failed(e),
+    }
+}
+
+/// The state of the circuit, shared by the clones of a client.
+#[derive(Clone)]
+pub(crate) struct CircuitBreaker {
+    policy: CircuitBreakerPolicy,
+    state: Arc<Mutex<State>>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(policy: CircuitBreakerPolicy) -> CircuitBreaker {
+        CircuitBreaker {
+            policy,
+            state: Arc::new(Mutex::new(State::Closed { failures: 0 })),
+        }
+    }
+
+    /// Fails with `CircuitOpen` if the request must not be sent.
+    pub(crate) fn check(&self) -> Res<()> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } if now < until => Err(OhttpClientError::CircuitOpen {
+                retry_after: until - now,
+            }),
+            State::HalfOpen { since } if now < since + self.policy.open_duration => {
+                Err(OhttpClientError::CircuitOpen {
+                    retry_after: since + self.policy.open_duration - now,
+                })
+            }
+            State::Open { .. } | State::HalfOpen { .. } => {
+                info!("Circuit breaker is half-open, sending a probe request");
+                *state = State::HalfOpen { since: now };
+                Ok(())
+            }
+        }
+    }
+
+    /// Records the outcome of a request that was let through. While the
+    /// circuit is open, the outcomes of the requests let through before it
+    /// opened are ignored, only the probe closes or reopens it.
+    pub(crate) fn record<T>(&self, result: &Res<T>) {
+        let mut state = self.state.lock().unwrap();
+        let failed = matches!(result, Err(e) if is_outage(e));
+        *state = match (*state, failed) {
+            (State::Open { .. }, _) => return,
+            (State::HalfOpen { .. }, false) => {
+                info!("Circuit breaker closed");
+                State::Closed { failures: 0 }
+            }
+            (_, false) => State::Closed { failures: 0 },
+            (State::Closed { failures }, true) if failures + 1 < self.policy.failure_threshold => {
+                State::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (_, true) => {
+                warn!(
+                    "Circuit breaker opened for {:?} after repeated failures",
+                    self.policy.open_duration
+                );
+                State::Open {
+                    until: Instant::now() + self.policy.open_duration,
+                }
+            }
+        };
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    attestation::ATTESTATION_TOKEN_HEADER,
+    err::{OhttpClientError, Res},
+    mock::{MockGateway, MockResponse},
+    response::{BodyStream, OhttpResponse},
+};
+use base64::{engine::general_purpose, Engine};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use reqwest::{
+    header::{HeaderName, HeaderValue, CONTENT_LENGTH, TRANSFER_ENCODING},
+    Method, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+};
+use tracing::{error, info};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedRequest {
+    method: String,
+    path: String,
+    /// Base64-encoded body.
+    #[serde(default)]
+    body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    /// Base64-encoded body.
+    #[serde(default)]
+    body: String,
+}
+
+/// An inner request and the decapsulated response to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+impl Interaction {
+    /// The method and path to which the response is replayed.
+    fn replay(self) -> Res<(Method, String, MockResponse)> {
+        let invalid = |what: &str| {
+            OhttpClientError::InvalidRequest(format!("cassette has an invalid {what}"))
+        };
+        let method =
+            Method::from_bytes(self.request.method.as_bytes()).map_err(|_| invalid("method"))?;
+        let status = StatusCode::from_u16(self.response.status).map_err(|_| invalid("status"))?;
+        let body = general_purpose::STANDARD
+            .decode(&self.response.body)
+            .map_err(|_| invalid("body"))?;
+        let mut response = MockResponse::new(status).body(body);
+        for (name, value) in &self.response.headers {
+            let name =
+                HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid("header name"))?;
+            let value = HeaderValue::from_str(value).map_err(|_| invalid("header value"))?;
+            response.headers.append(name, value);
+        }
+        Ok((method, self.request.path, response))
+    }
+}
+
+/// The inner exchanges of a client, together with the key configuration and
+/// the attestation token of the gateway that served them.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Cassette {
+    /// Hex-encoded key configuration the requests were encapsulated with.
+    key_config: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attestation_token: Option<String>,
+    interactions: Vec<Interaction>,
+}
+
+/// Records the inner exchanges of a client to a cassette file, which is
+/// saved again as each response body is read to the end. Encapsulated
+/// messages cannot be replayed, since each request is encapsulated with
+/// fresh keys, so the decapsulated requests and responses are recorded.
+#[derive(Clone)]
+pub(crate) struct Recorder {
+    path: PathBuf,
+    cassette: Arc<Mutex<Cassette>>,
+}
+
+impl Recorder {
+    pub(crate) fn new(path: &Path) -> Recorder {
+        Recorder {
+            path: path.to_path_buf(),
+            cassette: Arc::default(),
+        }
+    }
+
+    fn save(&self, interaction: Interaction) {
+        let mut cassette = self.cassette.lock().unwrap();
+        cassette.interactions.push(interaction);
+        let result = serde_json::to_string_pretty(&*cassette)
+            .map_err(OhttpClientError::from)
+            .and_then(|json| fs::write(&self.path, json).map_err(OhttpClientError::from));
+        match result {
+            Ok(()) => info!("Recorded the response to {}", self.path.display()),
+            Err(e) => error!("Saving the cassette failed: {e}"),
+        }
+    }
+
+    /// Records the exchange once the body of `response` has been read.
+    /// Responses whose body fails to be read are not recorded.
+    pub(crate) fn record(
+        &self,
+        key_config: &[u8],
+        bhttp_request: &[u8],
+        response: OhttpResponse,
+    ) -> OhttpResponse {
+        let request = match MockGateway::inner_request(bhttp_request) {
+            Ok(request) => request,
+            Err(e) => {
+                error!("{e}");
+                return response;
+            }
+        };
+        {
+            let mut cassette = self.cassette.lock().unwrap();
+            cassette.key_config = hex::encode(key_config);
+            if let Some(claims) = response.attestation() {
+                cassette.attestation_token = Some(claims.token().to_string());
+            }
+        }
+
+        // The framing of the body and the token are set again when replayed
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| {
+                ![CONTENT_LENGTH, TRANSFER_ENCODING].contains(*name)
+                    && name.as_str() != ATTESTATION_TOKEN_HEADER
+            })
+            .map(|(name, value)| {
+                let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+                (name.to_string(), value)
+            })
+            .collect();
+        let interaction = Interaction {
+            request: RecordedRequest {
+                method: request.method.to_string(),
+                path: request.path,
+                body: general_purpose::STANDARD.encode(&request.body),
+            },
+            response: RecordedResponse {
+                status: response.status().as_u16(),
+                headers,
+                body: String::new(),
+            },
+        };
+        let recorder = self.clone();
+        response.map_body(move |body| {
+            Box::pin(RecordingBody {
+                body,
+                content: Vec::new(),
+                pending: Some(interaction),
+                recorder,
+            })
+        })
+    }
+}
+
+/// A response body that records the exchange once read to the end.
+struct RecordingBody {
+    body: BodyStream,
+    content: Vec<u8>,
+    pending: Option<Interaction>,
+    recorder: Recorder,
+}
+
+impl Stream for RecordingBody {
+    type Item = Res<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Res<Bytes>>> {
+        let next = ready!(self.body.poll_next_unpin(cx));
+        match &next {
+            Some(Ok(chunk)) => self.content.extend_from_slice(chunk),
+            Some(Err(_)) => self.pending = None,
+            None => {
+                if let Some(mut interaction) = self.pending.take() {
+                    interaction.response.body = general_purpose::STANDARD.encode(&self.content);
+                    self.recorder.save(interaction);
+                }
+            }
+        }
+        Poll::Ready(next)
+    }
+}
+
+impl MockGateway {
+    /// Creates a gateway that answers requests with the responses recorded
+    /// in the cassette at `path`, so that tests can run without access to
+    /// the relay or the KMS. Each recorded response is served once, to the
+    /// first request with the same method and path; other requests fail
+    /// with status 500. The gateway uses fresh keys rather than the recorded
+    /// key configuration, whose private key is only known to the gateway.
+    pub fn replay(path: &Path) -> Res<MockGateway> {
+        let cassette: Cassette = serde_json::from_slice(&fs::read(path)?)?;
+        let mut recorded = VecDeque::new();
+        for interaction in cassette.interactions {
+            recorded.push_back(interaction.replay()?);
+        }
+
+        let recorded = Mutex::new(recorded);
+        let gateway = MockGateway::new()?.respond_with(move |request| {
+            let mut recorded = recorded.lock().unwrap();
+            let position = recorded
+                .iter()
+                .position(|(method, path, _)| *method == request.method && *path == request.path);
+            match position.and_then(|position| recorded.remove(position)) {
+                Some((_, _, response)) => response,
+                None => MockResponse::new(StatusCode::INTERNAL_SERVER_ERROR).body(format!(
+                    "no recorded response to {} {}",
+                    request.method, request.path
+                )),
+            }
+        });
+        Ok(match &cassette.attestation_token {
+            Some(token) => gateway.attestation_token(token),
+            None => gateway,
+        })
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    err::{OhttpClientError, Res},
+    request::InnerRequestBuilder,
+    sse::SseStream,
+};
+use futures::Stream;
+use futures_util::stream::unfold;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Path of the chat completions endpoint of OpenAI-compatible services.
+pub const CHAT_COMPLETIONS_PATH: &str = "/v1/chat/completions";
+
+/// Data of the event that ends a streamed completion.
+const DONE: &str = "[DONE]";
+
+/// A message of a chat.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: &str) -> ChatMessage {
+        ChatMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    pub fn system(content: &str) -> ChatMessage {
+        ChatMessage::new("system", content)
+    }
+
+    pub fn user(content: &str) -> ChatMessage {
+        ChatMessage::new("user", content)
+    }
+
+    pub fn assistant(content: &str) -> ChatMessage {
+        ChatMessage::new("assistant", content)
+    }
+}
+
+/// A request to an OpenAI-compatible chat completions endpoint.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChatRequest {
+    #[serde(skip)]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+impl ChatRequest {
+    pub fn new() -> ChatRequest {
+        ChatRequest::default()
+    }
+
+    /// Sends the request to this path instead of `/v1/chat/completions`.
+    pub fn path(mut self, path: &str) -> ChatRequest {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn model(mut self, model: &str) -> ChatRequest {
+        self.model = Some(model.to_string());
+        self
+    }
+
+    pub fn message(mut self, message: ChatMessage) -> ChatRequest {
+        self.messages.push(message);
+        self
+    }
+
+    pub fn messages(mut self, messages: &[ChatMessage]) -> ChatRequest {
+        self.messages.extend_from_slice(messages);
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f64) -> ChatRequest {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: u32) -> ChatRequest {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Sets any other parameter of the request, e.g. `top_p`.
+    pub fn parameter(mut self, name: &str, value: Value) -> ChatRequest {
+        self.extra.insert(name.to_string(), value);
+        self
+    }
+
+    /// The inner request carrying this request as its JSON body, asking for
+    /// the completion to be streamed as Server-Sent Events with `stream`.
+    pub(crate) fn inner_request(&self, stream: bool) -> Res<InnerRequestBuilder> {
+        let body = ChatRequest {
+            stream,
+            ..self.clone()
+        };
+        let accept = if stream {
+            "text/event-stream"
+        } else {
+            "application/json"
+        };
+        Ok(InnerRequestBuilder::new()
+            .method(Method::POST)
+            .path(self.path.as_deref().unwrap_or(CHAT_COMPLETIONS_PATH))
+            .header("accept", accept)
+            .json_body(serde_json::to_vec(&body)?))
+    }
+}
+
+/// A choice of a chat completion.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatChoice {
+    #[serde(default)]
+    pub index: u32,
+    pub message: ChatMessage,
+    pub finish_reason: Option<String>,
+}
+
+/// Token counts of a chat completion.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+/// The response of a chat completions endpoint.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatCompletion {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub model: String,
+    pub choices: Vec<ChatChoice>,
+    pub usage: Option<ChatUsage>,
+}
+
+impl ChatCompletion {
+    /// Content of the first choice.
+    pub fn content(&self) -> Option<&str> {
+        self.choices
+            .first()
+            .map(|choice| choice.message.content.as_str())
+    }
+}
+
+/// The part of a message added by a streamed chunk.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ChatDelta {
+    pub role: Option<String>,
+    pub content: Option<String>,
+}
+
+/// A choice of a streamed chunk.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatChunkChoice {
+    #[serde(default)]
+    pub index: u32,
+    #[serde(default)]
+    pub delta: ChatDelta,
+    pub finish_reason: Option<String>,
+}
+
+/// A chunk of a streamed chat completion.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatCompletionChunk {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub choices: Vec<ChatChunkChoice>,
+    pub usage: Option<ChatUsage>,
+}
+
+impl ChatCompletionChunk {
+    /// Content added to the first choice by this chunk.
+    pub fn content(&self) -> Option<&str> {
+        self.choices
+            .first()
+            .and_then(|choice| choice.delta.content.as_deref())
+    }
+}
+
+/// The chunks of a streamed chat completion, parsed from the Server-Sent
+/// Events of the decapsulated response until the `[DONE]` event.
+pub struct ChatStream {
+    events: SseStream,
+    done: bool,
+}
+
+impl ChatStream {
+    pub(crate) fn new(events: SseStream) -> ChatStream {
+        ChatStream {
+            events,
+            done: false,
+        }
+    }
+
+    /// Returns the next chunk, or `None` at the end of the completion.
+    pub async fn next(&mut self) -> Res<Option<ChatCompletionChunk>> {
+        while !self.done {
+            let Some(event) = self.events.next().await? else {
+                break;
+            };
+            if event.data == DONE {
+                self.done = true;
+                continue;
+            }
+            let chunk: Value = serde_json::from_str(&event.data)?;
+            if let Some(error) = chunk.get("error") {
+                return Err(OhttpClientError::Chat(error.to_string()));
+            }
+            return Ok(Some(serde_json::from_value(chunk)?));
+        }
+        Ok(None)
+    }
+
+    /// Reads the remainder of the completion and returns the content of its
+    /// first choice.
+    pub async fn content(mut self) -> Res<String> {
+        let mut content = String::new();
+        while let Some(chunk) = self.next().await? {
+            content.push_str(chunk.content().unwrap_or_default());
+        }
+        Ok(content)
+    }
+
+    /// Turns the chunks into a stream.
+    pub fn into_stream(self) -> impl Stream<Item = Res<ChatCompletionChunk>> {
+        unfold(Some(self), |chunks| async move {
+            let mut chunks = chunks?;
+            match chunks.next().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), Some(chunks))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{err::OhttpClientError, OhttpClient, OhttpService};
+use futures::future::{self, Ready};
+use hyper::{
+    client::connect::{Connected, Connection},
+    server::conn::Http,
+    Uri,
+};
+use reqwest::header::HeaderMap;
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tower_service::Service;
+use tracing::error;
+
+/// Capacity of the in-memory pipe between hyper and the oblivious transport.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// A connection yielded by `OhttpConnector`. Requests written to it are sent
+/// obliviously and the decapsulated responses are read back from it.
+pub struct OhttpStream {
+    io: DuplexStream,
+}
+
+impl AsyncRead for OhttpStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for OhttpStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+impl Connection for OhttpStream {
+    fn connected(&self) -> Connected {
+        Connected::new()
+    }
+}
+
+/// A hyper connector that sends every request made on its connections
+/// obliviously through the relay at `url`, so that the oblivious transport
+/// can be plugged into `hyper::Client` and the libraries built on it:
+///
+/// `hyper::Client::builder().build::<_, hyper::Body>(OhttpConnector::new(client, url))`
+///
+/// Each connection is an in-memory pipe served by an `OhttpService`, which
+/// encapsulates each request as a whole, whatever the target of the URI.
+#[derive(Clone)]
+pub struct OhttpConnector {
+    service: OhttpService,
+}
+
+impl OhttpConnector {
+    pub fn new(client: OhttpClient, url: &str) -> OhttpConnector {
+        OhttpConnector {
+            service: OhttpService::new(client, url),
+        }
+    }
+
+    /// Adds headers to the outer request to the relay, e.g. an API key.
+    pub fn outer_headers(mut self, outer_headers: &HeaderMap) -> OhttpConnector {
+        self.service = self.service.outer_headers(outer_headers);
+        self
+    }
+}
+
+impl Service<Uri> for OhttpConnector {
+    type Response = OhttpStream;
+    type Error = OhttpClientError;
+    type Future = Ready<Result<OhttpStream, OhttpClientError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), OhttpClientError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _uri: Uri) -> Self::Future {
+        let (client_io, server_io) = tokio::io::duplex(PIPE_CAPACITY);
+        let connection = Http::new()
+            .http1_only(true)
+            .serve_connection(server_io, self.service.clone());
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("{e}");
+            }
+        });
+        future::ready(Ok(OhttpStream { io: client_io }))
+    }
+}
@@ -0,0 +1,204 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    err::{OhttpClientError, Res},
+    response::{BodyStream, OhttpResponse},
+};
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder, ZlibDecoder};
+use bytes::Bytes;
+use futures::{future, stream, Stream, StreamExt};
+use reqwest::header::{HeaderMap, CONTENT_ENCODING, CONTENT_LENGTH};
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+use tokio_util::io::{ReaderStream, StreamReader};
+use tracing::{error, info, warn};
+
+/// Content codings that are decoded, for the Accept-Encoding header of
+/// inner requests.
+pub const ACCEPTED_ENCODINGS: &str = "gzip, deflate, br";
+
+/// Default bound on the size of a decoded body.
+pub(crate) const DEFAULT_MAX_DECODED_SIZE: u64 = 1 << 30;
+
+/// A content coding of the inner response (RFC 9110, Section 8.4.1).
+#[derive(Debug, Clone, Copy)]
+enum Encoding {
+    Gzip,
+    /// The zlib format (RFC 1950), despite its name.
+    Deflate,
+    Brotli,
+}
+
+impl Encoding {
+    fn parse(coding: &str) -> Option<Encoding> {
+        match coding.to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            "br" => Some(Encoding::Brotli),
+            _ => None,
+        }
+    }
+}
+
+type IoStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+/// Returns the codings applied to the content, in the order they were
+/// applied, or `None` if any of them cannot be decoded.
+fn content_encodings(headers: &HeaderMap) -> Option<Vec<Encoding>> {
+    let mut encodings = Vec::new();
+    for value in headers.get_all(CONTENT_ENCODING) {
+        let Ok(value) = value.to_str() else {
+            warn!("Keeping the content encoding {value:?}, which cannot be decoded");
+            return None;
+        };
+        for coding in value.split(',').map(str::trim) {
+            if coding.is_empty() || coding.eq_ignore_ascii_case("identity") {
+                continue;
+            }
+            let Some(encoding) = Encoding::parse(coding) else {
+                warn!("Keeping the content encoding {coding}, which cannot be decoded");
+                return None;
+            };
+            encodings.push(encoding);
+        }
+    }
+    Some(encodings)
+}
+
+/// Decodes the body of `response` as it is read, according to its
+/// Content-Encoding, which is removed from the headers together with the
+/// Content-Length of the encoded body. Responses with other codings are
+/// left as they are. The body fails once more than `max_size` bytes are
+/// decoded.
+pub(crate) fn decompress(mut response: OhttpResponse, max_size: u64) -> OhttpResponse {
+    let encodings = match content_encodings(response.headers()) {
+        Some(encodings) if !encodings.is_empty() => encodings,
+        _ => return response,
+    };
+    info!("Decoding the response content: {encodings:?}");
+    let headers = response.headers_mut();
+    headers.remove(CONTENT_ENCODING);
+    headers.remove(CONTENT_LENGTH);
+    response.map_body(move |body| decode(body, encodings, max_size))
+}
+
+/// Decodes the body once its first chunk is read, since an empty body, as
+/// in responses to HEAD requests, is not a valid encoded stream.
+fn decode(mut body: BodyStream, encodings: Vec<Encoding>, max_size: u64) -> BodyStream {
+    let decoded = stream::once(async move {
+        let first = loop {
+            match body.next().await {
+                Some(Ok(chunk)) if chunk.is_empty() => continue,
+                first => break first,
+            }
+        };
+        let Some(first) = first else {
+            return Box::pin(stream::empty()) as BodyStream;
+        };
+        let body = Box::pin(stream::once(future::ready(first)).chain(body));
+        decoder(body, &encodings, max_size)
+    });
+    Box::pin(decoded.flatten())
+}
+
+/// Chains the decoders of `encodings`, undoing the last coding first.
+fn decoder(body: BodyStream, encodings: &[Encoding], max_size: u64) -> BodyStream {
+    // The decoders only see I/O errors, so a failure to read the body is
+    // kept aside and reported instead of the truncated content
+    let failure = Arc::new(Mutex::new(None));
+    let read_failure = failure.clone();
+    let mut stream: IoStream = Box::pin(body.map(move |chunk| {
+        chunk.map_err(|e| {
+            *read_failure.lock().unwrap() = Some(e);
+            io::Error::other("reading the response failed")
+        })
+    }));
+    for encoding in encodings.iter().rev() {
+        let reader = StreamReader::new(stream);
+        stream = match encoding {
+            Encoding::Gzip => {
+                let mut decoder = GzipDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::pin(ReaderStream::new(decoder))
+            }
+            Encoding::Deflate => Box::pin(ReaderStream::new(ZlibDecoder::new(reader))),
+            Encoding::Brotli => Box::pin(ReaderStream::new(BrotliDecoder::new(reader))),
+        };
+    }
+    let decoded = stream.map(move |chunk| -> Res<Bytes> {
+        chunk.map_err(|e| {
+            let read_failure = failure.lock().unwrap().take();
+            read_failure.unwrap_or(OhttpClientError::Io(e))
+        })
+    });
+    // The body ends after the error for exceeding the bound
+    Box::pin(decoded.scan(0u64, move |size, chunk| {
+        if *size > max_size {
+            return future::ready(None);
+        }
+        let chunk = chunk.and_then(|chunk| {
+            *size += chunk.len() as u64;
+            if *size > max_size {
+                let e = OhttpClientError::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("the decoded response body exceeds {max_size} bytes"),
+                ));
+                error!("{e}");
+                return Err(e);
+            }
+            Ok(chunk)
+        });
+        future::ready(Some(chunk))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::tokio::bufread::GzipEncoder;
+    use tokio::io::AsyncReadExt;
+
+    async fn gzip(data: &[u8]) -> Bytes {
+        let mut encoded = Vec::new();
+        GzipEncoder::new(data)
+            .read_to_end(&mut encoded)
+            .await
+            .unwrap();
+        Bytes::from(encoded)
+    }
+
+    fn body(content: Bytes) -> BodyStream {
+        Box::pin(stream::once(future::ready(Ok(content))))
+    }
+
+    async fn read(mut body: BodyStream) -> Res<Vec<u8>> {
+        let mut content = Vec::new();
+        while let Some(chunk) = body.next().await {
+            content.extend_from_slice(&chunk?);
+        }
+        Ok(content)
+    }
+
+    #[tokio::test]
+    async fn decodes_up_to_the_bound() {
+        let data = vec![0x5a; 1 << 20];
+        let encoded = gzip(&data).await;
+        let decoded = read(decode(body(encoded), vec![Encoding::Gzip], 1 << 20)).await;
+        assert_eq!(decoded.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn fails_beyond_the_bound() {
+        let encoded = gzip(&vec![0; 16 << 20]).await;
+        assert!(encoded.len() < 64 << 10);
+        let decoded = read(decode(body(encoded), vec![Encoding::Gzip], 1 << 20)).await;
+        assert!(matches!(
+            decoded,
+            Err(OhttpClientError::Io(e)) if e.kind() == io::ErrorKind::InvalidData
+        ));
+    }
+}
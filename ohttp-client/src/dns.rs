@@ -0,0 +1,121 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::err::{OhttpClientError, Res};
+use hickory_resolver::{
+    proto::rr::{rdata::svcb::SvcParamValue, RData, RecordType},
+    TokioAsyncResolver,
+};
+use reqwest::Client;
+use tracing::{info, trace};
+
+/// SvcParamKey of the `ohttp` parameter (draft-ietf-ohai-svcb-config).
+const OHTTP_SVC_PARAM_KEY: u16 = 8;
+
+/// Well-known path from which a gateway serves its key configurations.
+const OHTTP_GATEWAY_WELL_KNOWN_PATH: &str = "/.well-known/ohttp-gateway";
+
+/// Media type of an encoded key configuration list (RFC 9458, Section 3.2).
+const OHTTP_KEYS_CONTENT_TYPE: &str = "application/ohttp-keys";
+
+/// A gateway advertised in an HTTPS resource record.
+struct OhttpService {
+    target: String,
+    port: Option<u16>,
+}
+
+/// Resolves the HTTPS records of `name` and returns the highest priority
+/// service endpoint that carries the `ohttp` parameter.
+async fn resolve_ohttp_service(name: &str) -> Res<OhttpService> {
+    let resolver = TokioAsyncResolver::tokio_from_system_conf()
+        .map_err(|e| OhttpClientError::Discovery(e.to_string()))?;
+
+    info!("Resolving HTTPS records for {name}...");
+    let lookup = resolver
+        .lookup(name, RecordType::HTTPS)
+        .await
+        .map_err(|e| OhttpClientError::Discovery(e.to_string()))?;
+
+    let mut services = Vec::new();
+    for rdata in lookup.iter() {
+        let svcb = match rdata {
+            RData::HTTPS(https) => &https.0,
+            _ => continue,
+        };
+
+        // Priority 0 is alias mode, which does not carry service parameters
+        if svcb.svc_priority() == 0 {
+            continue;
+        }
+
+        let params = svcb.svc_params();
+        if !params
+            .iter()
+            .any(|(key, _)| u16::from(*key) == OHTTP_SVC_PARAM_KEY)
+        {
+            trace!(
+                "Skipping service {} without ohttp parameter",
+                svcb.target_name()
+            );
+            continue;
+        }
+
+        let port = params.iter().find_map(|(_, value)| match value {
+            SvcParamValue::Port(port) => Some(*port),
+            _ => None,
+        });
+
+        // A target name of "." refers to the queried name itself
+        let target = if svcb.target_name().is_root() {
+            name.to_string()
+        } else {
+            svcb.target_name()
+                .to_utf8()
+                .trim_end_matches('.')
+                .to_string()
+        };
+
+        services.push((svcb.svc_priority(), OhttpService { target, port }));
+    }
+
+    services.sort_by_key(|(priority, _)| *priority);
+    services
+        .into_iter()
+        .map(|(_, service)| service)
+        .next()
+        .ok_or_else(|| {
+            OhttpClientError::Discovery(format!("no HTTPS record with ohttp parameter for {name}"))
+        })
+}
+
+/// Fetches the encoded key configuration list of a gateway from its
+/// well-known location.
+pub(crate) async fn fetch_well_known_config(client: &Client, gateway_url: &str) -> Res<Vec<u8>> {
+    let url = gateway_url.trim_end_matches('/').to_string() + OHTTP_GATEWAY_WELL_KNOWN_PATH;
+    info!("Fetching key configurations from {url}...");
+
+    let response = client
+        .get(url)
+        .header("accept", OHTTP_KEYS_CONTENT_TYPE)
+        .send()
+        .await?
+        .error_for_status()?;
+    let config = response.bytes().await?;
+    if config.is_empty() {
+        return Err(OhttpClientError::Discovery(
+            "gateway returned an empty key configuration".to_string(),
+        ));
+    }
+    Ok(config.to_vec())
+}
+
+/// Discovers the OHTTP key configuration list of the gateway for `name`
+/// using its HTTPS resource records, and fetches it with `client`.
+pub(crate) async fn discover_key_config(client: &Client, name: &str) -> Res<Vec<u8>> {
+    let service = resolve_ohttp_service(name).await?;
+    let gateway_url = match service.port {
+        Some(port) => format!("https://{}:{port}", service.target),
+        None => format!("https://{}", service.target),
+    };
+    fetch_well_known_config(client, &gateway_url).await
+}
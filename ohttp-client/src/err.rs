@@ -0,0 +1,154 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use hex::FromHexError;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::{fmt, time::Duration};
+use thiserror::Error;
+
+/// Problem type of a gateway rejecting a request encapsulated under a key
+/// configuration it does not hold (RFC 9458, Section 5.3).
+const OHTTP_KEY_PROBLEM: &str = "https://iana.org/assignments/http-problem-types#ohttp-key";
+
+/// A problem details object (RFC 9457) returned by the gateway as an
+/// `application/problem+json` outer response.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Problem {
+    #[serde(rename = "type", default = "Problem::default_type")]
+    pub problem_type: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub status: Option<u16>,
+    #[serde(default)]
+    pub detail: Option<String>,
+    #[serde(default)]
+    pub instance: Option<String>,
+}
+
+impl Problem {
+    fn default_type() -> String {
+        "about:blank".to_string()
+    }
+
+    /// Whether the gateway does not hold the key the request was
+    /// encapsulated under.
+    pub fn is_key_mismatch(&self) -> bool {
+        self.problem_type == OHTTP_KEY_PROBLEM
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.title, &self.detail) {
+            (Some(title), Some(detail)) => write!(f, "{title}: {detail}"),
+            (Some(text), None) | (None, Some(text)) => write!(f, "{text}"),
+            (None, None) => write!(f, "{}", self.problem_type),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum OhttpClientError {
+    #[error("key management service error: {0}")]
+    KmsError(String),
+    #[error("receipt verification failed: {0}")]
+    ReceiptVerification(#[from] verifier::Error),
+    #[error("key configuration digest {key_digest} does not match receipt claims digest {claims_digest}")]
+    KeyReceiptMismatch {
+        key_digest: String,
+        claims_digest: String,
+    },
+    #[error("encapsulation error: {0}")]
+    Encapsulation(#[from] ohttp::Error),
+    /// The outer response has an error status, set by the relay or by the
+    /// gateway without problem details.
+    #[error("relay returned status {status}: {body}{}", correlation(.correlation_id))]
+    RelayHttp {
+        status: StatusCode,
+        body: String,
+        /// Delay advised by the `Retry-After` header of the response, if any.
+        retry_after: Option<Duration>,
+        /// Correlation ID sent with the request, to find it in the logs of
+        /// the relay and the gateway.
+        correlation_id: Option<String>,
+    },
+    /// The gateway rejected the request with problem details, e.g. because
+    /// it could not decapsulate it.
+    #[error("gateway returned status {status}: {problem}{}", correlation(.correlation_id))]
+    GatewayProblem {
+        status: StatusCode,
+        problem: Problem,
+        /// Delay advised by the `Retry-After` header of the response, if any.
+        retry_after: Option<Duration>,
+        correlation_id: Option<String>,
+    },
+    /// The decapsulated inner response has an error status, reported by
+    /// `OhttpResponse::error_for_status`.
+    #[error("inner response has status {status}: {body}")]
+    InnerStatus { status: StatusCode, body: String },
+    #[error("bhttp error: {0}")]
+    Bhttp(#[from] bhttp::Error),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("hex error: {0}")]
+    HexError(#[from] FromHexError),
+    #[error("attestation verification failed: {0}")]
+    Attestation(String),
+    #[error("key discovery failed: {0}")]
+    Discovery(String),
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    #[error("timed out: {0}")]
+    Timeout(String),
+    /// The circuit breaker is open after repeated failures of the relay or
+    /// the gateway, so the request was not sent.
+    #[error("circuit breaker is open after repeated failures, retry in {retry_after:?}")]
+    CircuitOpen { retry_after: Duration },
+    #[error("authentication failed: {0}")]
+    Auth(String),
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("chat completion failed: {0}")]
+    Chat(String),
+    #[error("the request was cancelled")]
+    Cancelled,
+}
+
+pub type Res<T> = Result<T, OhttpClientError>;
+
+fn correlation(correlation_id: &Option<String>) -> String {
+    match correlation_id {
+        Some(id) => format!(" (correlation ID {id})"),
+        None => String::new(),
+    }
+}
+
+impl OhttpClientError {
+    /// The delay after which the relay or the gateway advised sending the
+    /// request again, e.g. with a 503 status. Requests are only retried
+    /// automatically with a retry policy; other callers can wait this long
+    /// before retrying themselves.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            OhttpClientError::RelayHttp { retry_after, .. }
+            | OhttpClientError::GatewayProblem { retry_after, .. } => *retry_after,
+            OhttpClientError::CircuitOpen { retry_after } => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    /// The correlation ID of the request that failed, if it reached the relay.
+    pub fn correlation_id(&self) -> Option<&str> {
+        match self {
+            OhttpClientError::RelayHttp { correlation_id, .. }
+            | OhttpClientError::GatewayProblem { correlation_id, .. } => correlation_id.as_deref(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    err::{OhttpClientError, Res},
+    response::BodyStream,
+    transport::{OuterRequest, OuterResponse, OuterTransport},
+};
+use futures::{future::BoxFuture, stream, StreamExt};
+use rand::Rng;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, RETRY_AFTER},
+    StatusCode,
+};
+use std::{io, sync::Arc, time::Duration};
+use tracing::info;
+
+/// Failures injected by `FaultInjectingTransport`. Each fault affects a
+/// request with the given probability, between 0 and 1.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    /// Fails the request with a refused connection, without sending it.
+    pub refuse_connection: f64,
+    /// Answers with 503 Service Unavailable, without sending the request.
+    pub unavailable: f64,
+    /// `Retry-After` of the injected 503 responses.
+    pub retry_after: Option<Duration>,
+    /// Resets the connection after `drop_after` chunks of the response body.
+    pub drop_connection: f64,
+    pub drop_after: usize,
+    /// Ends the response body after `truncate_after` chunks, without error.
+    pub truncate: f64,
+    pub truncate_after: usize,
+    /// Delays each chunk of the response body by `chunk_delay`.
+    pub delay_chunks: f64,
+    pub chunk_delay: Duration,
+}
+
+/// The faults drawn for one request.
+#[derive(Debug, Clone, Copy, Default)]
+struct BodyFaults {
+    drop_after: Option<usize>,
+    truncate_after: Option<usize>,
+    chunk_delay: Option<Duration>,
+}
+
+impl BodyFaults {
+    fn is_empty(&self) -> bool {
+        self.drop_after.is_none() && self.truncate_after.is_none() && self.chunk_delay.is_none()
+    }
+
+    fn apply(self, body: BodyStream) -> BodyStream {
+        Box::pin(stream::unfold((body, 0), move |(mut body, n)| async move {
+            if self.truncate_after == Some(n) {
+                info!("Injected fault: truncating the response body");
+                return None;
+            }
+            if self.drop_after == Some(n) {
+                info!("Injected fault: dropping the connection");
+                let e = io::Error::new(io::ErrorKind::ConnectionReset, "injected fault");
+                let rest: BodyStream = Box::pin(stream::empty());
+                return Some((Err(OhttpClientError::Io(e)), (rest, usize::MAX)));
+            }
+            if let Some(delay) = self.chunk_delay {
+                tokio::time::sleep(delay).await;
+            }
+            let chunk = body.next().await?;
+            Some((chunk, (body, n.saturating_add(1))))
+        }))
+    }
+}
+
+/// A transport that injects failures into the requests sent with another
+/// transport, so that the retry and timeout handling of applications can be
+/// tested against the failures of the oblivious path.
+pub struct FaultInjectingTransport {
+    inner: Arc<dyn OuterTransport>,
+    faults: Faults,
+}
+
+impl FaultInjectingTransport {
+    pub fn new(inner: Arc<dyn OuterTransport>, faults: Faults) -> FaultInjectingTransport {
+        FaultInjectingTransport { inner, faults }
+    }
+}
+
+impl OuterTransport for FaultInjectingTransport {
+    fn send(&self, request: OuterRequest) -> BoxFuture<'_, Res<OuterResponse>> {
+        let faults = &self.faults;
+        let mut rng = rand::thread_rng();
+        let mut happens = |probability: f64| rng.gen_bool(probability.clamp(0.0, 1.0));
+
+        if happens(faults.refuse_connection) {
+            info!("Injected fault: refusing the connection");
+            let e = io::Error::new(io::ErrorKind::ConnectionRefused, "injected fault");
+            return Box::pin(async move { Err(OhttpClientError::Io(e)) });
+        }
+        if happens(faults.unavailable) {
+            info!("Injected fault: answering with 503");
+            let mut headers = HeaderMap::new();
+            if let Some(retry_after) = faults.retry_after {
+                headers.insert(RETRY_AFTER, HeaderValue::from(retry_after.as_secs()));
+            }
+            let response = OuterResponse {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                headers,
+                body: Box::pin(stream::empty()),
+            };
+            return Box::pin(async move { Ok(response) });
+        }
+        let body_faults = BodyFaults {
+            drop_after: happens(faults.drop_connection).then_some(faults.drop_after),
+            truncate_after: happens(faults.truncate).then_some(faults.truncate_after),
+            chunk_delay: happens(faults.delay_chunks).then_some(faults.chunk_delay),
+        };
+
+        Box::pin(async move {
+            let mut response = self.inner.send(request).await?;
+            if !body_faults.is_empty() {
+                response.body = body_faults.apply(response.body);
+            }
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,40 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::request::{bhttp_method, IDEMPOTENT_METHODS};
+use reqwest::Method;
+use std::time::Duration;
+
+/// Hedging of idempotent requests to cut the tail latency: if the response
+/// to a request has not arrived after `delay`, the request is sent a second
+/// time, encapsulated afresh, and the first response wins. The other request
+/// is dropped, which cancels it. Hedged requests reach the gateway twice, so
+/// only requests with one of `methods` are hedged.
+#[derive(Debug, Clone)]
+pub struct HedgePolicy {
+    /// Time to wait for the response headers before sending the second
+    /// request.
+    pub delay: Duration,
+    /// Inner request methods that are safe to send twice.
+    pub methods: Vec<Method>,
+}
+
+impl Default for HedgePolicy {
+    fn default() -> Self {
+        HedgePolicy {
+            delay: Duration::from_secs(1),
+            methods: IDEMPOTENT_METHODS.to_vec(),
+        }
+    }
+}
+
+impl HedgePolicy {
+    /// Whether the method of the bhttp request allows hedging it.
+    pub(crate) fn applies(&self, bhttp_request: &[u8]) -> bool {
+        bhttp_method(bhttp_request).is_some_and(|method| {
+            self.methods
+                .iter()
+                .any(|allowed| allowed.as_str().as_bytes() == method)
+        })
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    err::{OhttpClientError, Res},
+    tls::ClientIdentity,
+};
+use reqwest::{Client, ClientBuilder, Proxy, Version};
+use rustls::ClientConfig;
+use std::{str::FromStr, time::Duration};
+
+/// The HTTP version of the connections to the relay. HTTP/2 and HTTP/3
+/// multiplex concurrent requests over one connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HttpVersion {
+    /// HTTP/2 if the relay selects it with ALPN during the TLS handshake,
+    /// HTTP/1.1 otherwise.
+    #[default]
+    Auto,
+    /// HTTP/1.1 only.
+    Http1,
+    /// HTTP/2 with prior knowledge, also over plain TCP.
+    Http2,
+    /// HTTP/3 over QUIC with prior knowledge. The `http3` feature of reqwest
+    /// is unstable and requires building with `--cfg reqwest_unstable`.
+    #[cfg(feature = "http3")]
+    Http3,
+}
+
+impl HttpVersion {
+    /// The version of the requests, if fixed. Requests are only sent over
+    /// QUIC if they ask for HTTP/3.
+    pub(crate) fn request_version(&self) -> Option<Version> {
+        match self {
+            HttpVersion::Auto => None,
+            HttpVersion::Http1 => Some(Version::HTTP_11),
+            HttpVersion::Http2 => Some(Version::HTTP_2),
+            #[cfg(feature = "http3")]
+            HttpVersion::Http3 => Some(Version::HTTP_3),
+        }
+    }
+
+    /// The ALPN protocols offered to the relay.
+    fn alpn_protocols(&self) -> Vec<Vec<u8>> {
+        let protocols: &[&[u8]] = match self {
+            HttpVersion::Auto => &[b"h2", b"http/1.1"],
+            HttpVersion::Http1 => &[b"http/1.1"],
+            HttpVersion::Http2 => &[b"h2"],
+            #[cfg(feature = "http3")]
+            HttpVersion::Http3 => &[b"h3"],
+        };
+        protocols.iter().map(|protocol| protocol.to_vec()).collect()
+    }
+}
+
+#[cfg(feature = "http3")]
+const HTTP_VERSIONS: &str = "auto, 1.1, 2 or 3";
+#[cfg(not(feature = "http3"))]
+const HTTP_VERSIONS: &str = "auto, 1.1 or 2";
+
+impl FromStr for HttpVersion {
+    type Err = OhttpClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(HttpVersion::Auto),
+            "1.1" => Ok(HttpVersion::Http1),
+            "2" => Ok(HttpVersion::Http2),
+            #[cfg(feature = "http3")]
+            "3" => Ok(HttpVersion::Http3),
+            _ => Err(OhttpClientError::Config(format!(
+                "unknown HTTP version {s}, expected {HTTP_VERSIONS}"
+            ))),
+        }
+    }
+}
+
+/// Bounds on how long requests to the relay and to the KMS may take. Unset
+/// timeouts do not apply.
+#[derive(Debug, Clone, Default)]
+pub struct Timeouts {
+    /// Time to establish a connection, including the TLS handshake.
+    pub connect: Option<Duration>,
+    /// Time until the response headers are received.
+    pub request: Option<Duration>,
+    /// Time to wait for each chunk of a response body.
+    pub idle: Option<Duration>,
+    /// Time for the whole request, including reading the response body.
+    pub total: Option<Duration>,
+}
+
+/// Reuse of the connections to the relay. All requests of a client share one
+/// pool of connections, so that sequential requests to the relay skip the
+/// TCP and TLS handshakes. Unset options keep the defaults of reqwest.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionPool {
+    /// Maximum number of idle connections kept open per host.
+    pub max_idle_per_host: Option<usize>,
+    /// Time after which idle connections are closed, 90 seconds by default.
+    pub idle_timeout: Option<Duration>,
+    /// Interval of the TCP keep-alive probes, which are off by default.
+    pub tcp_keepalive: Option<Duration>,
+}
+
+/// Settings of the outer HTTP connections to the relay and to the KMS.
+#[derive(Clone, Default)]
+pub(crate) struct HttpSettings {
+    /// Explicit proxy URL (`http://`, `https://` or `socks5://`). Without it,
+    /// the `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` variables are honored.
+    pub(crate) proxy: Option<String>,
+    /// TLS configuration of the relay connection, if not the default.
+    pub(crate) relay_tls: Option<ClientConfig>,
+    /// Client certificate presented to the relay. Already part of
+    /// `relay_tls` if that is set.
+    pub(crate) relay_identity: Option<ClientIdentity>,
+    /// Client certificate presented to the KMS.
+    pub(crate) kms_identity: Option<ClientIdentity>,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) pool: ConnectionPool,
+    pub(crate) version: HttpVersion,
+    /// User-Agent of the outer requests. None is sent if unset.
+    pub(crate) user_agent: Option<String>,
+}
+
+impl HttpSettings {
+    /// Returns a client builder configured with these settings, to which
+    /// connection specific options can be added.
+    pub(crate) fn client_builder(&self) -> Res<ClientBuilder> {
+        let mut builder = Client::builder();
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+        if let Some(timeout) = self.timeouts.connect {
+            builder = builder.connect_timeout(timeout);
+        }
+        Ok(builder)
+    }
+
+    /// Returns a client builder for requests to the KMS.
+    pub(crate) fn kms_client_builder(&self) -> Res<ClientBuilder> {
+        let mut builder = self.client_builder()?;
+        if let Some(identity) = &self.kms_identity {
+            builder = builder.identity(identity.reqwest_identity()?);
+        }
+        // The KMS response is small, so bound the whole exchange
+        if let Some(timeout) = self.timeouts.request.or(self.timeouts.total) {
+            builder = builder.timeout(timeout);
+        }
+        Ok(builder)
+    }
+
+    /// Returns a client for the small requests to other services, e.g. for
+    /// the signing keys of the attestation service, which are bounded like
+    /// the requests to the KMS.
+    pub(crate) fn service_client(&self) -> Res<Client> {
+        let mut builder = self.client_builder()?;
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        if let Some(timeout) = self.timeouts.request.or(self.timeouts.total) {
+            builder = builder.timeout(timeout);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Returns a client for requests to the relay, whose connections are
+    /// kept in a pool for the following requests.
+    pub(crate) fn relay_client(&self) -> Res<Client> {
+        let mut builder = self.client_builder()?;
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        if let Some(max_idle) = self.pool.max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(timeout) = self.pool.idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        if let Some(interval) = self.pool.tcp_keepalive {
+            builder = builder.tcp_keepalive(interval);
+        }
+        builder = match self.version {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder.http2_prior_knowledge(),
+            #[cfg(feature = "http3")]
+            HttpVersion::Http3 => builder.http3_prior_knowledge(),
+        };
+        if let Some(tls) = &self.relay_tls {
+            // reqwest only sets the ALPN protocols of its own configuration
+            let mut tls = tls.clone();
+            tls.alpn_protocols = self.version.alpn_protocols();
+            builder = builder.use_preconfigured_tls(tls);
+        } else if let Some(identity) = &self.relay_identity {
+            builder = builder.identity(identity.reqwest_identity()?);
+        }
+        Ok(builder.build()?)
+    }
+}
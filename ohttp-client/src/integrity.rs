@@ -0,0 +1,128 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    attestation::AttestationClaims,
+    err::{OhttpClientError, Res},
+    response::{BodyStream, OhttpResponse},
+};
+use base64::{engine::general_purpose, Engine};
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tracing::{error, info, warn};
+
+/// Claim of the attestation token in which a gateway binds the response to
+/// the token, with the SHA-256 digest of the decapsulated response body,
+/// hex or base64 encoded.
+const RESPONSE_DIGEST_CLAIM: &str = "x-ohttp-response-sha256";
+
+/// Claims that the TEE provided at runtime, in which the digest may be nested.
+const RUNTIME_CLAIM: &str = "x-ms-runtime";
+
+/// Returns the response digest claimed by the token, if any.
+fn claimed_digest(claims: &AttestationClaims) -> Res<Option<[u8; 32]>> {
+    let claim = claims.get(RESPONSE_DIGEST_CLAIM).or_else(|| {
+        claims
+            .get(RUNTIME_CLAIM)
+            .and_then(|runtime| runtime.get(RESPONSE_DIGEST_CLAIM))
+    });
+    let Some(claim) = claim else {
+        return Ok(None);
+    };
+    let invalid = || {
+        OhttpClientError::Attestation(format!(
+            "the {RESPONSE_DIGEST_CLAIM} claim is not a SHA-256 digest"
+        ))
+    };
+    let encoded = claim.as_str().ok_or_else(invalid)?;
+    let digest = hex::decode(encoded)
+        .ok()
+        .or_else(|| general_purpose::STANDARD.decode(encoded).ok())
+        .or_else(|| general_purpose::URL_SAFE_NO_PAD.decode(encoded).ok())
+        .ok_or_else(invalid)?;
+    let digest = digest.try_into().map_err(|_| invalid())?;
+    Ok(Some(digest))
+}
+
+/// Checks the body of `response` against the digest in its attestation
+/// token, when the token carries one. The body is hashed as it is read and
+/// ends with an error instead if the digest does not match once it is
+/// complete. The digest of a token that was not verified is checked as
+/// well, but only a verified token binds the response to the TEE, which is
+/// logged.
+pub(crate) fn bind_response(response: OhttpResponse) -> Res<OhttpResponse> {
+    let expected = match response.attestation().map(claimed_digest).transpose() {
+        Ok(expected) => expected.flatten(),
+        Err(e) => {
+            error!("{e}");
+            return Err(e);
+        }
+    };
+    let Some(expected) = expected else {
+        return Ok(response);
+    };
+    if response
+        .attestation()
+        .is_some_and(|claims| !claims.verified())
+    {
+        warn!(
+            "Checking the response body against an unverified attestation token, which does not \
+             bind the response to the TEE"
+        );
+    } else {
+        info!("Checking the response body against the attestation token");
+    }
+    Ok(response.map_body(move |body| {
+        Box::pin(DigestedBody {
+            body,
+            hasher: Some(Sha256::new()),
+            expected,
+        })
+    }))
+}
+
+/// A response body that is hashed as it is read and checked at its end.
+struct DigestedBody {
+    body: BodyStream,
+    /// Dropped once the body failed or was checked.
+    hasher: Option<Sha256>,
+    expected: [u8; 32],
+}
+
+impl Stream for DigestedBody {
+    type Item = Res<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Res<Bytes>>> {
+        let next = ready!(self.body.poll_next_unpin(cx));
+        match &next {
+            Some(Ok(chunk)) => {
+                if let Some(hasher) = &mut self.hasher {
+                    hasher.update(chunk);
+                }
+            }
+            Some(Err(_)) => self.hasher = None,
+            None => {
+                // The body may not be polled past its end
+                self.body = Box::pin(stream::empty());
+                let Some(hasher) = self.hasher.take() else {
+                    return Poll::Ready(None);
+                };
+                if hasher.finalize()[..] != self.expected {
+                    let e = OhttpClientError::Attestation(
+                        "the response body does not match the digest in the attestation token"
+                            .to_string(),
+                    );
+                    error!("{e}");
+                    return Poll::Ready(Some(Err(e)));
+                }
+                info!("The response body matches the attestation token");
+            }
+        }
+        Poll::Ready(next)
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::err::{OhttpClientError, Res};
+
+/// An HPKE KDF and AEAD pair supported by a key configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SymmetricSuite {
+    pub kdf: u16,
+    pub aead: u16,
+}
+
+/// Decoded contents of an encoded OHTTP key configuration (RFC 9458, Section 3).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyConfigInfo {
+    pub key_id: u8,
+    pub kem: u16,
+    pub public_key: Vec<u8>,
+    pub symmetric: Vec<SymmetricSuite>,
+}
+
+impl SymmetricSuite {
+    /// Name of the HPKE KDF (RFC 9180, Section 7.2).
+    pub fn kdf_name(&self) -> Option<&'static str> {
+        match self.kdf {
+            0x0001 => Some("HKDF-SHA256"),
+            0x0002 => Some("HKDF-SHA384"),
+            0x0003 => Some("HKDF-SHA512"),
+            _ => None,
+        }
+    }
+
+    /// Name of the HPKE AEAD (RFC 9180, Section 7.3).
+    pub fn aead_name(&self) -> Option<&'static str> {
+        match self.aead {
+            0x0001 => Some("AES-128-GCM"),
+            0x0002 => Some("AES-256-GCM"),
+            0x0003 => Some("ChaCha20Poly1305"),
+            0xffff => Some("Export-only"),
+            _ => None,
+        }
+    }
+}
+
+/// Length of the encoded public key of the HPKE KEMs (RFC 9180, Section 7.1).
+fn public_key_len(kem: u16) -> Option<usize> {
+    match kem {
+        0x0010 => Some(65),
+        0x0011 => Some(97),
+        0x0012 => Some(133),
+        0x0020 => Some(32),
+        0x0021 => Some(56),
+        _ => None,
+    }
+}
+
+/// Reads big-endian fields from an encoded key configuration.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Res<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(OhttpClientError::Config(
+                "truncated key configuration".to_string(),
+            ));
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Res<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Res<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+}
+
+impl KeyConfigInfo {
+    /// Name of the HPKE KEM (RFC 9180, Section 7.1).
+    pub fn kem_name(&self) -> Option<&'static str> {
+        match self.kem {
+            0x0010 => Some("DHKEM(P-256, HKDF-SHA256)"),
+            0x0011 => Some("DHKEM(P-384, HKDF-SHA384)"),
+            0x0012 => Some("DHKEM(P-521, HKDF-SHA512)"),
+            0x0020 => Some("DHKEM(X25519, HKDF-SHA256)"),
+            0x0021 => Some("DHKEM(X448, HKDF-SHA512)"),
+            _ => None,
+        }
+    }
+
+    /// Decodes a single encoded key configuration.
+    pub fn parse(encoded: &[u8]) -> Res<KeyConfigInfo> {
+        let mut reader = Reader { data: encoded };
+        let info = KeyConfigInfo::read(&mut reader)?;
+        if !reader.data.is_empty() {
+            return Err(OhttpClientError::Config(
+                "trailing data after key configuration".to_string(),
+            ));
+        }
+        Ok(info)
+    }
+
+    /// Decodes an encoded key configuration list, in which every
+    /// configuration is prefixed with its length (RFC 9458, Section 3.2).
+    pub fn parse_list(encoded: &[u8]) -> Res<Vec<KeyConfigInfo>> {
+        let mut reader = Reader { data: encoded };
+        let mut configs = Vec::new();
+        while !reader.data.is_empty() {
+            let len = reader.u16()? as usize;
+            configs.push(KeyConfigInfo::parse(reader.bytes(len)?)?);
+        }
+        if configs.is_empty() {
+            return Err(OhttpClientError::Config(
+                "empty key configuration list".to_string(),
+            ));
+        }
+        Ok(configs)
+    }
+
+    fn read(reader: &mut Reader) -> Res<KeyConfigInfo> {
+        let key_id = reader.u8()?;
+        let kem = reader.u16()?;
+        let pk_len = public_key_len(kem)
+            .ok_or_else(|| OhttpClientError::Config(format!("unknown KEM {kem:#06x}")))?;
+        let public_key = reader.bytes(pk_len)?.to_vec();
+
+        let symmetric_len = reader.u16()? as usize;
+        if symmetric_len == 0 || symmetric_len % 4 != 0 {
+            return Err(OhttpClientError::Config(
+                "invalid symmetric algorithms length".to_string(),
+            ));
+        }
+        let mut symmetric = Vec::with_capacity(symmetric_len / 4);
+        for _ in 0..symmetric_len / 4 {
+            symmetric.push(SymmetricSuite {
+                kdf: reader.u16()?,
+                aead: reader.u16()?,
+            });
+        }
+
+        Ok(KeyConfigInfo {
+            key_id,
+            kem,
+            public_key,
+            symmetric,
+        })
+    }
+}
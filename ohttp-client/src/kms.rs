@@ -0,0 +1,453 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    err::{OhttpClientError, Res},
+    http::HttpSettings,
+    keyconfig::KeyConfigInfo,
+    retry::backoff,
+};
+use futures::{future::BoxFuture, Future, FutureExt};
+use ohttp::ClientRequest;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing::{info, info_span, trace, warn, Instrument};
+
+/// What was trusted when a key configuration was obtained from the KMS.
+#[derive(Debug, Clone)]
+pub struct KeyReceipt {
+    /// Hex-encoded SHA-256 digest of the encoded key configuration.
+    pub key_digest: String,
+    /// Hex-encoded claims digest bound to the key generation transaction.
+    pub claims_digest: String,
+    /// Ledger transaction ID (`view.seqno`) in which the key was generated.
+    pub transaction_id: String,
+    /// Identity of the KMS node that signed the receipt, if reported.
+    pub node_id: Option<String>,
+    /// PEM certificate of the KMS node that signed the receipt.
+    pub node_cert: String,
+    /// Time at which the key was generated, as reported by the KMS.
+    pub timestamp: Option<String>,
+    /// The CCF receipt as returned by the KMS.
+    pub receipt: String,
+}
+
+/// A key configuration published by the KMS whose receipt was verified.
+#[derive(Debug, Clone)]
+pub struct KmsKey {
+    /// The encoded key configuration.
+    pub config: Vec<u8>,
+    pub receipt: KeyReceipt,
+}
+
+/// Preferred HPKE algorithms when the KMS publishes several key
+/// configurations. Unset fields match any algorithm.
+#[derive(Debug, Clone, Default)]
+pub struct KeyPreference {
+    pub kem: Option<u16>,
+    pub kdf: Option<u16>,
+    pub aead: Option<u16>,
+}
+
+impl KeyPreference {
+    fn matches(&self, encoded_config: &[u8]) -> bool {
+        let info = match KeyConfigInfo::parse(encoded_config) {
+            Ok(info) => info,
+            Err(_) => return false,
+        };
+        self.kem.map_or(true, |kem| kem == info.kem)
+            && info.symmetric.iter().any(|suite| {
+                self.kdf.map_or(true, |kdf| kdf == suite.kdf)
+                    && self.aead.map_or(true, |aead| aead == suite.aead)
+            })
+    }
+}
+
+/// Produces bearer tokens on demand, e.g. by refreshing an OAuth token.
+type TokenCallback = Arc<dyn Fn() -> BoxFuture<'static, Res<String>> + Send + Sync>;
+
+/// Credentials presented to KMS deployments that require member or user
+/// authentication to list public keys.
+#[derive(Clone)]
+pub enum KmsAuth {
+    /// A fixed bearer token.
+    Bearer(String),
+    /// A callback invoked for every KMS request to obtain a bearer token.
+    Callback(TokenCallback),
+}
+
+impl KmsAuth {
+    pub fn bearer(token: &str) -> KmsAuth {
+        KmsAuth::Bearer(token.to_string())
+    }
+
+    pub fn callback<F, Fut>(callback: F) -> KmsAuth
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Res<String>> + Send + 'static,
+    {
+        KmsAuth::Callback(Arc::new(move || callback().boxed()))
+    }
+
+    async fn token(&self) -> Res<String> {
+        match self {
+            KmsAuth::Bearer(token) => Ok(token.clone()),
+            KmsAuth::Callback(callback) => callback().await,
+        }
+    }
+}
+
+/// A key configuration with its receipt, in the format published by the KMS
+/// and in which verified key configurations are saved to disk.
+#[derive(Serialize, Deserialize)]
+struct KmsKeyConfiguration {
+    #[serde(rename = "publicKey")]
+    key_config: String,
+    receipt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<String>,
+}
+
+/// How long to keep polling the KMS while the receipt for a key is being
+/// generated. The delay doubles with every attempt up to `max_delay`.
+#[derive(Debug, Clone)]
+pub struct KmsRetryPolicy {
+    /// Number of retries after the first request.
+    pub max_retries: u32,
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+    /// Upper bound on the delay between two retries.
+    pub max_delay: Duration,
+    /// Randomizes each delay between half and all of its value so that
+    /// clients started together do not poll the KMS in lockstep.
+    pub jitter: bool,
+}
+
+impl Default for KmsRetryPolicy {
+    fn default() -> Self {
+        KmsRetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl KmsRetryPolicy {
+    /// Delay before retry number `retry`, starting at 1.
+    fn delay(&self, retry: u32) -> Duration {
+        backoff(self.base_delay, self.max_delay, self.jitter, retry)
+    }
+}
+
+// Get key configuration from KMS
+async fn get_kms_config(
+    http: &HttpSettings,
+    kms_url: String,
+    cert: &str,
+    retry: &KmsRetryPolicy,
+    auth: Option<&KmsAuth>,
+) -> Res<String> {
+    // Create a client with the CA certificate
+    let client = http
+        .kms_client_builder()?
+        .add_root_certificate(reqwest::Certificate::from_pem(cert.as_bytes())?)
+        .build()?;
+
+    info!("Contacting key management service at {kms_url}...");
+    let mut retries = 0;
+    let url = kms_url + "/listpubkeys";
+
+    loop {
+        // Make the GET request
+        let mut request = client.get(url.clone());
+        if let Some(auth) = auth {
+            request = request.bearer_auth(auth.token().await?);
+        }
+        let response = request.send().await?.error_for_status()?;
+
+        // We may have to wait for receipt to be ready
+        match response.status().as_u16() {
+            202 => {
+                if retries < retry.max_retries {
+                    retries += 1;
+                    let delay = retry.delay(retries);
+                    trace!(
+                        "Received 202 status code, retrying in {:?}... (attempt {}/{})",
+                        delay,
+                        retries,
+                        retry.max_retries
+                    );
+                    tokio::time::sleep(delay).await;
+                } else {
+                    return Err(OhttpClientError::KmsError(
+                        "Max retries reached, giving up. Cannot reach key management service"
+                            .to_string(),
+                    ));
+                }
+            }
+            200 => {
+                let body = response.text().await?;
+                if body.is_empty() {
+                    return Err(OhttpClientError::KmsError(
+                        "KMS returned an empty key configuration".to_string(),
+                    ));
+                }
+                return Ok(body);
+            }
+            e => {
+                return Err(OhttpClientError::KmsError(format!(
+                    "KMS returned unexpected {} status code.",
+                    e
+                )));
+            }
+        }
+    }
+}
+
+/// Verifies the receipt of a key configuration published by the KMS, and
+/// checks that it covers the key configuration and that the HPKE suite of the
+/// configuration is supported.
+fn verify_kms_config(kms_config: KmsKeyConfiguration, cert: &str) -> Res<(Vec<u8>, KeyReceipt)> {
+    let _span = info_span!("kms.verify_receipt").entered();
+    let claims = verifier::verify_receipt(&kms_config.receipt, cert)?;
+    let encoded_config = hex::decode(&kms_config.key_config)?;
+
+    // Check that the receipt covers the key configuration we are going to use
+    let key_digest = hex::encode(Sha256::digest(&encoded_config));
+    if !key_digest.eq_ignore_ascii_case(&claims.claims_digest) {
+        return Err(OhttpClientError::KeyReceiptMismatch {
+            key_digest,
+            claims_digest: claims.claims_digest,
+        });
+    }
+
+    // Check that we can encapsulate requests with this configuration
+    ClientRequest::from_encoded_config(&encoded_config)?;
+
+    let receipt = KeyReceipt {
+        key_digest,
+        claims_digest: claims.claims_digest,
+        transaction_id: claims.transaction_id,
+        node_id: claims.node_id,
+        node_cert: claims.node_cert,
+        timestamp: kms_config.timestamp,
+        receipt: kms_config.receipt,
+    };
+    Ok((encoded_config, receipt))
+}
+
+/// Saves a verified key configuration together with its receipt, so that it
+/// can be used later without contacting the KMS.
+pub(crate) fn save_key_config(path: &Path, config: &[u8], receipt: &KeyReceipt) -> Res<()> {
+    let saved = KmsKeyConfiguration {
+        key_config: hex::encode(config),
+        receipt: receipt.receipt.clone(),
+        timestamp: receipt.timestamp.clone(),
+    };
+    fs::write(path, serde_json::to_string_pretty(&saved)?)?;
+    info!("Saved key {} to {}", receipt.key_digest, path.display());
+    Ok(())
+}
+
+/// Loads a saved key configuration. Its receipt is verified again with the
+/// KMS certificate, which does not require contacting the KMS. Without the
+/// certificate, the key is only loaded if `allow_unverified` is set.
+pub(crate) fn load_key_config(
+    path: &Path,
+    cert: Option<&Path>,
+    allow_unverified: bool,
+) -> Res<(Vec<u8>, Option<KeyReceipt>)> {
+    let saved: KmsKeyConfiguration = serde_json::from_str(&fs::read_to_string(path)?)?;
+    match cert {
+        Some(cert) => {
+            let (config, receipt) = verify_kms_config(saved, &fs::read_to_string(cert)?)?;
+            info!(
+                "Loaded key {} generated in transaction {}",
+                receipt.key_digest, receipt.transaction_id
+            );
+            Ok((config, Some(receipt)))
+        }
+        None if allow_unverified => {
+            warn!("The receipt of the saved key configuration is not verified without the KMS certificate");
+            Ok((hex::decode(&saved.key_config)?, None))
+        }
+        None => Err(OhttpClientError::KmsError(
+            "the receipt of the saved key configuration cannot be verified without the KMS \
+             certificate; set the KMS certificate or allow unverified keys"
+                .to_string(),
+        )),
+    }
+}
+
+/// Reads a json containing key configurations with receipts and verifies
+/// each of them.
+fn verify_kms_configs(config: &str, cert: &str) -> Res<Vec<Res<(Vec<u8>, KeyReceipt)>>> {
+    let kms_configs: Vec<KmsKeyConfiguration> = serde_json::from_str(config)?;
+    if kms_configs.is_empty() {
+        return Err(OhttpClientError::KmsError(
+            "No KMS configuration found".to_string(),
+        ));
+    }
+
+    info!("{}", "Establishing trust in key management service...");
+    Ok(kms_configs
+        .into_iter()
+        .map(|kms_config| verify_kms_config(kms_config, cert))
+        .collect())
+}
+
+/// Reads a json containing key configurations with receipts, skips the
+/// configurations that cannot be verified or are not supported, and returns
+/// the first remaining configuration that matches the preference (or the
+/// first remaining configuration if none does), together with its receipt.
+fn from_kms_config(
+    config: &str,
+    cert: &str,
+    preference: &KeyPreference,
+) -> Res<(Vec<u8>, KeyReceipt)> {
+    let mut candidates = Vec::new();
+    for (index, result) in verify_kms_configs(config, cert)?.into_iter().enumerate() {
+        match result {
+            Ok(candidate) => candidates.push(candidate),
+            Err(e) => warn!("Skipping key configuration {index}: {e}"),
+        }
+    }
+    if candidates.is_empty() {
+        return Err(OhttpClientError::KmsError(
+            "No valid KMS configuration found".to_string(),
+        ));
+    }
+    info!(
+        "{}",
+        "The receipt for the generation of the OHTTP key is valid."
+    );
+
+    let selected = candidates
+        .iter()
+        .position(|(config, _)| preference.matches(config))
+        .unwrap_or(0);
+    let (encoded_config, receipt) = candidates.swap_remove(selected);
+    info!(
+        "Key {} generated in transaction {}",
+        receipt.key_digest, receipt.transaction_id
+    );
+    Ok((encoded_config, receipt))
+}
+
+/// Whether another KMS endpoint should be tried after this error: the
+/// endpoint could not be reached, failed, or never produced a receipt.
+fn should_fail_over(e: &OhttpClientError) -> bool {
+    match e {
+        OhttpClientError::Http(e) => {
+            e.is_connect()
+                || e.is_timeout()
+                || e.status().map_or(false, |status| status.is_server_error())
+        }
+        OhttpClientError::KmsError(_) => true,
+        _ => false,
+    }
+}
+
+/// Where and how to obtain the key configuration from the KMS.
+#[derive(Clone)]
+pub(crate) struct KmsSettings {
+    urls: Vec<String>,
+    cert: PathBuf,
+    preference: KeyPreference,
+    retry: KmsRetryPolicy,
+    auth: Option<KmsAuth>,
+    http: HttpSettings,
+    /// Index of the endpoint that last served the key configuration, which is
+    /// tried first on the next refresh.
+    current: Arc<AtomicUsize>,
+}
+
+impl KmsSettings {
+    pub(crate) fn new(
+        urls: Vec<String>,
+        cert: PathBuf,
+        preference: KeyPreference,
+        retry: KmsRetryPolicy,
+        auth: Option<KmsAuth>,
+        http: HttpSettings,
+    ) -> KmsSettings {
+        KmsSettings {
+            urls,
+            cert,
+            preference,
+            retry,
+            auth,
+            http,
+            current: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Obtains the key configuration from the KMS and verifies its receipt.
+    pub(crate) async fn fetch_key_config(&self) -> Res<(Vec<u8>, KeyReceipt)> {
+        let (config, cert) = self.fetch_kms_config().await?;
+        from_kms_config(&config, &cert, &self.preference)
+    }
+
+    /// Obtains all key configurations from the KMS and verifies the receipt
+    /// of each of them.
+    pub(crate) async fn fetch_keys(&self) -> Res<Vec<Res<KmsKey>>> {
+        let (config, cert) = self.fetch_kms_config().await?;
+        Ok(verify_kms_configs(&config, &cert)?
+            .into_iter()
+            .map(|result| result.map(|(config, receipt)| KmsKey { config, receipt }))
+            .collect())
+    }
+
+    /// Obtains the key configurations published by the KMS together with the
+    /// trusted KMS certificate, failing over to the next KMS endpoint if one
+    /// is unavailable.
+    async fn fetch_kms_config(&self) -> Res<(String, String)> {
+        let cert = fs::read_to_string(&self.cert)?;
+        let start = self.current.load(Ordering::Relaxed);
+        let mut last_error = None;
+
+        for offset in 0..self.urls.len() {
+            let index = (start + offset) % self.urls.len();
+            let url = &self.urls[index];
+            #[cfg(feature = "metrics")]
+            let started = std::time::Instant::now();
+            let result = get_kms_config(
+                &self.http,
+                url.clone(),
+                &cert,
+                &self.retry,
+                self.auth.as_ref(),
+            )
+            .instrument(info_span!("kms.fetch", url.full = %url))
+            .await;
+            #[cfg(feature = "metrics")]
+            crate::telemetry::record_kms_fetch(started.elapsed(), &result);
+            match result {
+                Ok(config) => {
+                    self.current.store(index, Ordering::Relaxed);
+                    return Ok((config, cert));
+                }
+                Err(e) if should_fail_over(&e) => {
+                    warn!("Key management service at {url} is unavailable: {e}");
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            OhttpClientError::KmsError("No key management service configured".to_string())
+        }))
+    }
+}
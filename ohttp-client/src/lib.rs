@@ -2,22 +2,190 @@
 // Licensed under the MIT License.
 
 use bhttp::{Message, Mode};
-use futures_util::stream::unfold;
+use bytes::Bytes;
+use futures::{future, stream, StreamExt};
 use ohttp::ClientRequest;
-use rand::distributions::{Alphanumeric, DistString};
-use reqwest::{Client, Response};
-use serde::Deserialize;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE},
+    Client, StatusCode,
+};
+use serde::Serialize;
 use std::{
-    fs::{self, File},
-    io::{Cursor, Read, Write},
+    io::Cursor,
     ops::Deref,
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+use tokio::time::Instant;
+use tracing::{error, field, info, info_span, trace, warn, Instrument, Span};
+
+mod attestation;
+mod auth;
+#[cfg(feature = "azure-auth")]
+mod azure;
+mod breaker;
+#[cfg(feature = "test-util")]
+mod cassette;
+mod chat;
+#[cfg(feature = "hyper")]
+mod connector;
+mod decompress;
+mod dns;
+mod err;
+#[cfg(feature = "fault-injection")]
+mod faults;
+mod hedge;
+mod http;
+mod integrity;
+mod keyconfig;
+mod kms;
+mod logging;
+#[cfg(feature = "reqwest-middleware")]
+mod middleware;
+#[cfg(feature = "test-util")]
+mod mock;
+mod multipart;
+#[cfg(feature = "otel")]
+mod otel;
+mod pool;
+mod privacy;
+mod progress;
+mod ratelimit;
+mod relays;
+mod request;
+mod response;
+mod retry;
+#[cfg(feature = "tower")]
+mod service;
+mod sse;
+#[cfg(feature = "metrics")]
+mod telemetry;
+mod tls;
+mod transport;
+#[cfg(all(unix, feature = "unix-socket"))]
+mod unix;
+#[cfg(feature = "azure-auth")]
+pub use crate::azure::AzureTokenProvider;
+#[cfg(feature = "hyper")]
+pub use crate::connector::{OhttpConnector, OhttpStream};
+#[cfg(feature = "fault-injection")]
+pub use crate::faults::{FaultInjectingTransport, Faults};
+#[cfg(feature = "reqwest-middleware")]
+pub use crate::middleware::OhttpMiddleware;
+#[cfg(feature = "test-util")]
+pub use crate::mock::{MockGateway, MockRequest, MockResponse};
+#[cfg(feature = "tower")]
+pub use crate::service::{BoxBody, OhttpBody, OhttpService};
+#[cfg(feature = "prometheus")]
+pub use crate::telemetry::install_prometheus_exporter;
+#[cfg(feature = "statsd")]
+pub use crate::telemetry::install_statsd_exporter;
+#[cfg(all(unix, feature = "unix-socket"))]
+pub use crate::unix::UnixSocketTransport;
+pub use crate::{
+    attestation::{AttestationClaims, AttestationConfig, AttestationPolicy},
+    auth::{BearerAuth, HmacAuth, InnerAuth, InnerAuthRequest},
+    breaker::CircuitBreakerPolicy,
+    chat::{
+        ChatChoice, ChatChunkChoice, ChatCompletion, ChatCompletionChunk, ChatDelta, ChatMessage,
+        ChatRequest, ChatStream, ChatUsage, CHAT_COMPLETIONS_PATH,
+    },
+    decompress::ACCEPTED_ENCODINGS,
+    err::{OhttpClientError, Problem, Res},
+    hedge::HedgePolicy,
+    http::{ConnectionPool, HttpVersion, Timeouts},
+    keyconfig::{KeyConfigInfo, SymmetricSuite},
+    kms::{KeyPreference, KeyReceipt, KmsAuth, KmsKey, KmsRetryPolicy},
+    logging::{init_logging, LogFormat},
+    multipart::{Part, PartDigest},
+    pool::OhttpClientPool,
+    privacy::{OuterHeaderAction, OuterHeaderFilter},
+    progress::ProgressCallback,
+    ratelimit::RateLimit,
+    relays::RelayFailoverPolicy,
+    request::{BuiltRequest, InnerRequestBuilder},
+    response::{BodyStream, OhttpResponse, RequestStats},
+    retry::RelayRetryPolicy,
+    sse::{SseEvent, SseStream},
+    tls::ClientIdentity,
+    transport::{OuterRequest, OuterResponse, OuterTransport},
+};
+use crate::{
+    attestation::{AttestationVerifier, ATTESTATION_TOKEN_HEADER},
+    breaker::CircuitBreaker,
+    http::HttpSettings,
+    kms::{self, KmsSettings},
+    progress::Progress,
+    ratelimit::RateLimiter,
+    relays::{relay_failed, Relays},
+    request::is_idempotent,
+    retry::RelayRetry,
+    tls::RelayTls,
+    transport::ReqwestTransport,
 };
-use tracing::{error, info, trace};
-use warp::hyper::body::Body;
+pub use tokio_util::sync::CancellationToken;
+
+/// Content type of the outer request in the chunked variant.
+///
+/// The gateway decapsulates requests sent with this content type and replies
+/// with a chunked response. The request itself is currently encapsulated as a
+/// single chunk, since the `ohttp` crate only exposes single-shot request
+/// encapsulation through `ClientRequest::encapsulate`; incremental request
+/// encapsulation requires the corresponding sender API in that crate.
+const OHTTP_CHUNKED_REQUEST_CONTENT_TYPE: &str = "message/ohttp-chunked-req";
+
+/// Content type of the outer request in standard OHTTP (RFC 9458).
+const OHTTP_REQUEST_CONTENT_TYPE: &str = "message/ohttp-req";
 
-type Res<T> = Result<T, Box<dyn std::error::Error>>;
+/// Content types of outer responses carrying an encapsulated inner response,
+/// whose status may be that of the inner response.
+const OHTTP_RESPONSE_CONTENT_TYPES: [&str; 2] = ["message/ohttp-res", "message/ohttp-chunked-res"];
+
+/// Content type of the problem details returned by a gateway.
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Outer request header carrying the correlation ID of each request.
+const DEFAULT_CORRELATION_HEADER: &str = "x-ms-client-request-id";
+
+/// The flavour of oblivious HTTP spoken with the gateway.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OhttpProtocol {
+    /// Standard single-shot OHTTP (RFC 9458), with `message/ohttp-req` and
+    /// `message/ohttp-res` bodies carrying complete bhttp messages.
+    Standard,
+    /// Chunked OHTTP, where the response body is decapsulated as it streams.
+    /// The request is still sent as a single chunk holding the complete
+    /// encapsulated request, so its size is bounded by memory.
+    #[default]
+    Chunked,
+}
+
+impl OhttpProtocol {
+    fn request_content_type(&self) -> &'static str {
+        match self {
+            OhttpProtocol::Standard => OHTTP_REQUEST_CONTENT_TYPE,
+            OhttpProtocol::Chunked => OHTTP_CHUNKED_REQUEST_CONTENT_TYPE,
+        }
+    }
+}
+
+impl FromStr for OhttpProtocol {
+    type Err = OhttpClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "standard" => Ok(OhttpProtocol::Standard),
+            "chunked" => Ok(OhttpProtocol::Chunked),
+            _ => Err(OhttpClientError::Config(format!(
+                "unknown protocol {s}, expected standard or chunked"
+            ))),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 /// This allows a `HexArg` to be created from a string slice (`&str`) by decoding
@@ -39,324 +207,865 @@ impl Deref for HexArg {
     }
 }
 
-/// Writes the request line for an HTTP POST request to the provided buffer.
-/// The request line follows the format:
-/// `POST {target_path} HTTP/1.1\r\n`.
-fn write_post_request_line(request: &mut Vec<u8>, target_path: &str) -> Res<()> {
-    write!(request, "POST {target_path} HTTP/1.1\r\n")?;
-    Ok(())
-}
-
-/// Appends HTTP headers to the provided request buffer.
-fn append_headers(request: &mut Vec<u8>, headers: &Vec<String>) -> Res<()> {
-    for header in headers {
-        write!(request, "{header}\r\n")?;
-        info!("{header}\r\n");
-    }
-    Ok(())
+/// Verified encoded key configuration from which a fresh single use
+/// `ClientRequest` is minted for every encapsulated request.
+#[derive(Clone)]
+enum KeyConfigSource {
+    /// A single encoded key configuration, as published by the KMS.
+    Config(Vec<u8>),
+    /// An encoded key configuration list, as provided on the command line.
+    ConfigList(Vec<u8>),
 }
 
-/// Creates a multipart/form-data body for an HTTP request.
-fn create_multipart_body(fields: &Vec<String>, boundary: &str) -> Res<Vec<u8>> {
-    let mut body = Vec::new();
-
-    for field in fields {
-        let (name, value) = field.split_once('=').unwrap();
-        if value.starts_with('@') {
-            // If the value starts with '@', it is treated as a file path.
-            let filename = value.strip_prefix('@').unwrap();
-            let mut file = File::open(filename)?;
-            let mut file_contents = Vec::new();
-            file.read_to_end(&mut file_contents)?;
-
-            let kind = infer::get(&file_contents).expect("file type is unknown");
-            let mime_type = kind.mime_type();
-
-            // Add the file
-            write!(
-                &mut body,
-                "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\nContent-Type: {mime_type}\r\n\r\n"
-            )?;
-            body.extend_from_slice(&file_contents);
-        } else {
-            write!(
-                &mut body,
-                "\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n"
-            )?;
-            write!(&mut body, "{value}")?;
+impl KeyConfigSource {
+    /// Uses the static config provided in Args.
+    fn from_encoded_config_list(config: &Option<HexArg>) -> Res<KeyConfigSource> {
+        match config {
+            Some(config) => Ok(KeyConfigSource::ConfigList(config.to_vec())),
+            None => Err(OhttpClientError::Config("config expected".to_string())),
         }
-        write!(&mut body, "\r\n--{boundary}--\r\n")?;
     }
 
-    Ok(body)
-}
-
-/// Append the headers for a multipart/form-data HTTP request to the provided buffer.
-fn append_multipart_headers(request: &mut Vec<u8>, boundary: &str, body_len: usize) -> Res<()> {
-    write!(
-        request,
-        "Content-Type: multipart/form-data; boundary={boundary}\r\n"
-    )?;
-    write!(request, "Content-Length: {}\r\n", body_len)?;
-    write!(request, "\r\n")?;
-    Ok(())
-}
-
-/// Creates an http multipart message.
-fn create_multipart_request(
-    target_path: &str,
-    headers: &Vec<String>,
-    fields: &Vec<String>,
-) -> Res<Vec<u8>> {
-    // Define boundary for multipart
-    let boundary_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
-    let boundary = &format!("----{boundary_string}");
+    /// Discovers the key configuration list from the HTTPS records of `name`.
+    async fn from_dns(client: &Client, name: &str) -> Res<KeyConfigSource> {
+        let config = dns::discover_key_config(client, name).await?;
+        Ok(KeyConfigSource::ConfigList(config))
+    }
 
-    // Create a POST request for target target_path
-    let mut request = Vec::new();
-    write_post_request_line(&mut request, target_path)?;
-    append_headers(&mut request, headers)?;
+    /// Loads a key configuration saved with `OhttpClient::save_key_config`.
+    fn from_saved(
+        path: &Path,
+        cert: Option<&Path>,
+        allow_unverified: bool,
+    ) -> Res<(KeyConfigSource, Option<KeyReceipt>)> {
+        let (config, receipt) = kms::load_key_config(path, cert, allow_unverified)?;
+        Ok((KeyConfigSource::Config(config), receipt))
+    }
 
-    // Create multipart body
-    let mut body = create_multipart_body(fields, boundary)?;
+    /// Obtains and verifies the key configuration from KMS.
+    async fn from_kms(kms: &KmsSettings) -> Res<(KeyConfigSource, KeyReceipt)> {
+        let (config, receipt) = kms.fetch_key_config().await?;
+        Ok((KeyConfigSource::Config(config), receipt))
+    }
 
-    // Append multipart headers
-    append_multipart_headers(&mut request, boundary, body.len())?;
+    /// The encoded key configuration or key configuration list.
+    #[cfg(feature = "test-util")]
+    fn encoded(&self) -> &[u8] {
+        match self {
+            KeyConfigSource::Config(config) => config,
+            KeyConfigSource::ConfigList(list) => list,
+        }
+    }
 
-    // Append body to the request
-    request.append(&mut body);
+    /// Creates a single use OHTTP request from the key configuration.
+    fn client_request(&self) -> Res<ClientRequest> {
+        let request = match self {
+            KeyConfigSource::Config(config) => ClientRequest::from_encoded_config(config)?,
+            KeyConfigSource::ConfigList(list) => ClientRequest::from_encoded_config_list(list)?,
+        };
+        Ok(request)
+    }
+}
 
-    Ok(request)
+/// A key configuration together with the receipt under which it was trusted.
+#[derive(Clone)]
+struct VerifiedKeys {
+    config: KeyConfigSource,
+    receipt: Option<KeyReceipt>,
 }
 
-/// Prepares a http message based on the `is_bhttp` flag and other parameters.
-fn create_request_buffer(
-    target_path: &str,
-    headers: &Vec<String>,
-    form_fields: &Vec<String>,
-) -> Res<Vec<u8>> {
-    let request = create_multipart_request(target_path, headers, form_fields)?;
-    let mut cursor = Cursor::new(request);
-    let request = Message::read_http(&mut cursor)?;
-    let mut request_buf = Vec::new();
-    request.write_bhttp(Mode::KnownLength, &mut request_buf)?;
-    Ok(request_buf)
+/// Where the key configuration is obtained from. Keys obtained from the KMS
+/// or from DNS can be obtained again after the gateway rotates them.
+#[derive(Clone)]
+enum KeyProvider {
+    Kms(KmsSettings),
+    /// The name of the gateway and the client fetching its keys.
+    Dns(String, Client),
+    Static(Option<HexArg>),
+    Saved {
+        path: PathBuf,
+        kms_cert: Option<PathBuf>,
+        allow_unverified: bool,
+    },
 }
 
-// Get key configuration from KMS
-async fn get_kms_config(kms_url: String, cert: &str) -> Res<String> {
-    // Create a client with the CA certificate
-    let client = Client::builder()
-        .add_root_certificate(reqwest::Certificate::from_pem(cert.as_bytes())?)
-        .build()?;
-
-    info!("Contacting key management service at {kms_url}...");
-    let max_retries = 3;
-    let mut retries = 0;
-    let url = kms_url + "/listpubkeys";
-
-    loop {
-        // Make the GET request
-        let response = client.get(url.clone()).send().await?.error_for_status()?;
-
-        // We may have to wait for receipt to be ready
-        match response.status().as_u16() {
-            202 => {
-                if retries < max_retries {
-                    retries += 1;
-                    trace!(
-                        "Received 202 status code, retrying... (attempt {}/{})",
-                        retries,
-                        max_retries
-                    );
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                } else {
-                    Err("Max retries reached, giving up. Cannot reach key management service")?;
+impl KeyProvider {
+    async fn load(&self) -> Res<VerifiedKeys> {
+        let keys = match self {
+            KeyProvider::Kms(kms) => {
+                let (config, receipt) = KeyConfigSource::from_kms(kms).await?;
+                VerifiedKeys {
+                    config,
+                    receipt: Some(receipt),
                 }
             }
-            200 => {
-                let body = response.text().await?;
-                assert!(!body.is_empty());
-                return Ok(body);
+            KeyProvider::Dns(name, client) => {
+                let config = KeyConfigSource::from_dns(client, name).await?;
+                warn!("The key configuration discovered for {name} is not attested by the KMS");
+                VerifiedKeys {
+                    config,
+                    receipt: None,
+                }
             }
-            e => {
-                Err(format!("KMS returned unexpected {} status code.", e))?;
+            KeyProvider::Static(config) => {
+                let config = KeyConfigSource::from_encoded_config_list(config)?;
+                warn!("The given key configuration is not attested by the KMS");
+                VerifiedKeys {
+                    config,
+                    receipt: None,
+                }
             }
-        }
-    }
-}
-
-#[derive(Deserialize)]
-struct KmsKeyConfiguration {
-    #[serde(rename = "publicKey")]
-    key_config: String,
-    receipt: String,
-}
+            KeyProvider::Saved {
+                path,
+                kms_cert,
+                allow_unverified,
+            } => {
+                let (config, receipt) =
+                    KeyConfigSource::from_saved(path, kms_cert.as_deref(), *allow_unverified)?;
+                VerifiedKeys { config, receipt }
+            }
+        };
 
-/// Reads a json containing key configurations with receipts and constructs
-/// a single use client sender from the first supported configuration.
-trait ClientRequestBuilder {
-    fn from_kms_config(config: &str, cert: &str) -> Res<ClientRequest>;
-}
+        // Check that the key configuration is supported before using it
+        keys.config.client_request()?;
+        Ok(keys)
+    }
 
-impl ClientRequestBuilder for ClientRequest {
-    /// Reads a json containing key configurations with receipts and constructs
-    /// a single use client sender from the first supported configuration.
-    fn from_kms_config(config: &str, cert: &str) -> Res<ClientRequest> {
-        let mut kms_configs: Vec<KmsKeyConfiguration> = serde_json::from_str(config)?;
-        let kms_config = match kms_configs.pop() {
-            Some(config) => config,
-            None => return Err("No KMS configuration found".into()),
-        };
-        info!("{}", "Establishing trust in key management service...");
-        let _ = verifier::verify(&kms_config.receipt, cert)?;
-        info!(
-            "{}",
-            "The receipt for the generation of the OHTTP key is valid."
-        );
-        let encoded_config = hex::decode(&kms_config.key_config)?;
-        Ok(ClientRequest::from_encoded_config(&encoded_config)?)
+    fn can_refresh(&self) -> bool {
+        !matches!(self, KeyProvider::Static(_) | KeyProvider::Saved { .. })
     }
 }
 
-/// Creates an OHTTP client from the static config provided in Args.
-///
-fn create_request_from_encoded_config_list(config: &Option<HexArg>) -> Res<ohttp::ClientRequest> {
-    let config = match config {
-        Some(config) => config,
-        None => return Err("config expected".into()),
+/// Recognizes a gateway rejecting a request because it was encapsulated under
+/// a key configuration it no longer holds.
+fn is_key_mismatch(e: &OhttpClientError) -> bool {
+    let (status, body) = match e {
+        OhttpClientError::GatewayProblem { problem, .. } if problem.is_key_mismatch() => {
+            return true
+        }
+        OhttpClientError::GatewayProblem {
+            status, problem, ..
+        } => (*status, problem.to_string()),
+        OhttpClientError::RelayHttp { status, body, .. } => (*status, body.clone()),
+        _ => return false,
     };
-    Ok(ohttp::ClientRequest::from_encoded_config_list(config)?)
+    if status != StatusCode::UNAUTHORIZED && status != StatusCode::UNPROCESSABLE_ENTITY {
+        return false;
+    }
+    let body = body.to_ascii_lowercase();
+    ["key id", "key_id", "keyid", "key config", "unknown key"]
+        .iter()
+        .any(|hint| body.contains(hint))
 }
 
-/// Creates an OHTTP client from KMS.
-///
-async fn create_request_from_kms_config(
-    kms_url: &String,
-    kms_cert: &PathBuf,
-) -> Res<ohttp::ClientRequest> {
-    let cert = fs::read_to_string(kms_cert)?;
-    let config = get_kms_config(kms_url.to_owned(), &cert).await?;
-    ClientRequest::from_kms_config(&config, &cert)
+/// The essence of the content type of a response, e.g. `application/json`.
+fn content_type(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    value.split(';').next().map(str::trim)
 }
 
-fn print_response_headers(response: &Response) {
+fn print_response_headers(headers: &HeaderMap) {
     info!("Response headers:");
-    for (key, value) in response.headers() {
-        info!(
-            "{}: {}",
-            key,
-            std::str::from_utf8(value.as_bytes()).unwrap()
-        );
+    for (key, value) in headers {
+        info!("{}: {}", key, String::from_utf8_lossy(value.as_bytes()));
     }
 }
 
 async fn post_request(
+    transport: &dyn OuterTransport,
+    timeouts: &Timeouts,
     url: &String,
-    outer_headers: &Vec<String>,
+    protocol: OhttpProtocol,
+    outer_headers: &HeaderMap,
     enc_request: Vec<u8>,
-) -> Res<reqwest::Response> {
-    let client = reqwest::ClientBuilder::new().build()?;
-
-    let mut builder = client
-        .post(url)
-        .header("content-type", "message/ohttp-chunked-req");
-
+) -> Res<OuterResponse> {
     // Add outer headers
     trace!("Outer request headers:");
-    for header in outer_headers {
-        let (key, value) = header.split_once(':').unwrap();
-        trace!("Adding {key}: {value}");
-        builder = builder.header(key, value);
+    for (key, value) in outer_headers {
+        trace!("Adding {key}: {value:?}");
     }
 
-    match builder.body(enc_request).send().await {
-        Ok(response) => {
-            print_response_headers(&response);
-            let status = response.status();
-            if !status.is_success() {
-                let error_msg = format!("HTTP request failed with status {status}");
-                error!("{}", error_msg);
+    let send = transport.send(OuterRequest {
+        url: url.clone(),
+        content_type: protocol.request_content_type(),
+        headers: outer_headers.clone(),
+        body: enc_request,
+    });
+    let result = match timeouts.request {
+        Some(timeout) => match tokio::time::timeout(timeout, send).await {
+            Ok(result) => result,
+            Err(_) => {
+                let e = OhttpClientError::Timeout("waiting for the relay response".to_string());
+                error!("{e}");
+                return Err(e);
+            }
+        },
+        None => send.await,
+    };
+
+    match result {
+        Ok(mut response) => {
+            print_response_headers(&response.headers);
+            let status = response.status;
+            Span::current().record("http.response.status_code", status.as_u16());
+            // An encapsulated response is the gateway's, with the inner status
+            let encapsulated = content_type(&response.headers)
+                .is_some_and(|value| OHTTP_RESPONSE_CONTENT_TYPES.contains(&value));
+            if !status.is_success() && !encapsulated {
+                error!("HTTP request failed with status {status}");
+                let retry_after = retry::retry_after(&response.headers);
+                let mut body = Vec::new();
+                while let Some(Ok(chunk)) = response.body.next().await {
+                    body.extend_from_slice(&chunk);
+                }
+                if content_type(&response.headers) == Some(PROBLEM_CONTENT_TYPE) {
+                    if let Ok(problem) = serde_json::from_slice(&body) {
+                        return Err(OhttpClientError::GatewayProblem {
+                            status,
+                            problem,
+                            retry_after,
+                            correlation_id: None,
+                        });
+                    }
+                }
+                return Err(OhttpClientError::RelayHttp {
+                    status,
+                    body: String::from_utf8_lossy(&body).into_owned(),
+                    retry_after,
+                    correlation_id: None,
+                });
             }
             Ok(response)
         }
         Err(e) => {
             error!("Request failed: {}", e);
-            Err(Box::new(e))
+            Err(e)
         }
     }
 }
 
-/// Decapsulate the http response
+/// Decapsulate a chunked OHTTP response. The outer status and headers are
+/// those of the response, and the body is decapsulated as it streams in.
+/// Chunks are only pulled from the outer body when the decapsulated body is
+/// polled, so a slow reader holds back the relay through the flow control of
+/// the connection instead of having the response buffered in memory.
 async fn decapsulate_response(
-    response: reqwest::Response,
+    response: OuterResponse,
     client_response: ohttp::ClientResponse,
-) -> Res<Response> {
-    info!("checking token in response");
-    if let Some(token) = response.headers().get("x-attestation-token") {
-        info!("token: {}", std::str::from_utf8(token.as_bytes()).unwrap())
-    }
+    attestation: Option<AttestationClaims>,
+    encapsulated: Arc<AtomicU64>,
+) -> Res<OhttpResponse> {
+    let status = response.status;
+    let headers = response.headers;
 
-    let status = response.status();
-    let mut builder = warp::http::Response::builder().status(status);
+    // The decapsulation only sees the chunks, so a failure to read the outer
+    // body is kept aside and reported instead of the truncated message
+    let failure = Arc::new(Mutex::new(None));
+    let read_failure = failure.clone();
 
-    let headers = response.headers().clone();
-    for (key, value) in headers {
-        if let Some(key) = key {
-            builder = builder.header(key, value.clone());
-        }
+    // The span lasts as long as the body is decapsulated
+    let span = Span::current();
+    let stream = Box::pin(response.body.scan((), move |_, chunk| {
+        let _entered = span.enter();
+        future::ready(match chunk {
+            Ok(chunk) => {
+                encapsulated.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                Some(Ok(chunk.to_vec()))
+            }
+            Err(e) => {
+                error!("Reading the response failed: {e}");
+                *read_failure.lock().unwrap() = Some(e);
+                None
+            }
+        })
+    }));
+
+    let stream = client_response
+        .decapsulate_stream(stream)
+        .await
+        .map(move |chunk| {
+            chunk.map(Bytes::from).map_err(|e| {
+                let read_failure = failure.lock().unwrap().take();
+                read_failure.unwrap_or_else(|| e.into())
+            })
+        });
+    Ok(OhttpResponse::new(
+        status,
+        headers,
+        Box::pin(stream),
+        attestation,
+    ))
+}
+
+/// Decapsulate a standard (non-chunked) OHTTP response, which carries the
+/// complete inner response as a bhttp message.
+async fn decapsulate_standard_response(
+    response: OuterResponse,
+    client_response: ohttp::ClientResponse,
+    attestation: Option<AttestationClaims>,
+    encapsulated: Arc<AtomicU64>,
+) -> Res<OhttpResponse> {
+    let mut body = response.body;
+    let mut enc_response = Vec::new();
+    while let Some(chunk) = body.next().await {
+        enc_response.extend_from_slice(&chunk?);
     }
+    encapsulated.store(enc_response.len() as u64, Ordering::Relaxed);
+    let bhttp_response = client_response.decapsulate(&enc_response)?;
+    let message = Message::read_bhttp(&mut Cursor::new(&bhttp_response[..]))?;
 
-    let stream = Box::pin(unfold(response, |mut response| async move {
-        match response.chunk().await {
-            Ok(Some(chunk)) => Some((Ok(chunk.to_vec()), response)),
-            _ => None,
-        }
-    }));
+    let invalid = |what: &str| {
+        OhttpClientError::InvalidRequest(format!("decapsulated message has an invalid {what}"))
+    };
+    let status = message
+        .control()
+        .status()
+        .and_then(|status| StatusCode::from_u16(status).ok())
+        .ok_or_else(|| invalid("status"))?;
+    let mut headers = HeaderMap::new();
+    for field in message.header().fields() {
+        let name = HeaderName::from_bytes(field.name()).map_err(|_| invalid("header name"))?;
+        let value = HeaderValue::from_bytes(field.value()).map_err(|_| invalid("header value"))?;
+        headers.append(name, value);
+    }
 
-    let stream = client_response.decapsulate_stream(stream).await;
-    let response = builder.body(Body::wrap_stream(stream))?;
-    Ok(Response::from(response))
+    let body = Bytes::from(message.content().to_vec());
+    Ok(OhttpResponse::new(
+        status,
+        headers,
+        Box::pin(stream::once(async move { Ok(body) })),
+        attestation,
+    ))
 }
 
+/// A client that can be used to send any number of oblivious requests.
+/// Each request is encapsulated with a fresh `ClientRequest` minted from the
+/// cached, verified key configuration, so the KMS is only contacted again
+/// when the gateway reports that the keys were rotated.
+#[derive(Clone)]
 pub struct OhttpClient {
-    ohttp_request: ClientRequest,
+    key_provider: KeyProvider,
+    keys: Arc<RwLock<VerifiedKeys>>,
+    protocol: OhttpProtocol,
+    http: HttpSettings,
+    transport: Arc<dyn OuterTransport>,
+    retry: Option<RelayRetry>,
+    hedge: Option<HedgePolicy>,
+    relays: Option<Relays>,
+    breaker: Option<CircuitBreaker>,
+    rate_limiter: Option<RateLimiter>,
+    outer_header_filter: OuterHeaderFilter,
+    minimal_outer_headers: bool,
+    keep_content_encoding: bool,
+    max_decoded_size: u64,
+    attestation: Option<AttestationVerifier>,
+    attestation_policy: Option<AttestationPolicy>,
+    progress: Progress,
+    cancellation: Option<CancellationToken>,
+    correlation_header: HeaderName,
+    #[cfg(feature = "azure-auth")]
+    relay_auth: Option<AzureTokenProvider>,
+    #[cfg(feature = "test-util")]
+    recorder: Option<cassette::Recorder>,
+    #[cfg(feature = "otel")]
+    propagate_trace_context: bool,
 }
 
 impl OhttpClient {
-    #[allow(clippy::too_many_arguments)]
+    /// Returns a client sharing the keys and settings of this one whose
+    /// requests are aborted when `cancellation` is cancelled. Cancelling
+    /// drops the connection to the relay and makes reading the body of a
+    /// response fail with `Cancelled`.
+    pub fn with_cancellation(&self, cancellation: &CancellationToken) -> OhttpClient {
+        OhttpClient {
+            cancellation: Some(cancellation.clone()),
+            ..self.clone()
+        }
+    }
+
+    /// The verified receipt of the key configuration obtained from the KMS,
+    /// if the client was built from a KMS.
+    pub fn key_receipt(&self) -> Option<KeyReceipt> {
+        self.keys.read().unwrap().receipt.clone()
+    }
+
+    /// Saves the key configuration obtained from the KMS together with its
+    /// receipt, so that later clients can load it with `saved_config` instead
+    /// of contacting the KMS.
+    pub fn save_key_config(&self, path: &Path) -> Res<()> {
+        let keys = self.keys.read().unwrap();
+        let result = match (&keys.config, &keys.receipt) {
+            (KeyConfigSource::Config(config), Some(receipt)) => {
+                kms::save_key_config(path, config, receipt)
+            }
+            _ => Err(OhttpClientError::Config(
+                "only key configurations verified with a KMS receipt can be saved".to_string(),
+            )),
+        };
+        if let Err(e) = &result {
+            error!("{e}");
+        }
+        result
+    }
+
+    /// Obtains and verifies the key configuration again.
+    async fn refresh_keys(&self) -> Res<()> {
+        let keys = self.key_provider.load().await?;
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    /// Encapsulates and sends the request within the total timeout, which
+    /// also bounds reading the response body.
     async fn encapsulate_and_send(
-        self,
+        &self,
         url: &String,
-        headers: &Vec<String>,
+        headers: &HeaderMap,
         bhttp_request: &[u8],
-    ) -> Res<Response> {
-        // Encapsulate the http buffer using the OHTTP request
-        let (enc_request, ohttp_response) = match self.ohttp_request.encapsulate(bhttp_request) {
-            Ok(result) => result,
+    ) -> Res<OhttpResponse> {
+        if let Err(e) = self.outer_header_filter.check(headers) {
+            error!("{e}");
+            return Err(e);
+        }
+        let timeouts = &self.http.timeouts;
+        let deadline = timeouts.total.map(|total| Instant::now() + total);
+
+        // Keep the correlation ID across retries
+        let (headers, correlation_id) = self.correlate(headers);
+        let span = info_span!(
+            "ohttp.request",
+            correlation_id = %correlation_id,
+            url.full = %url,
+            ohttp.key_id = field::Empty,
+            ohttp.request_size = field::Empty,
+            http.response.status_code = field::Empty,
+        );
+        let send = async {
+            let send = self.send_with_retries(url, &headers, bhttp_request, deadline);
+            match &self.cancellation {
+                Some(cancellation) => tokio::select! {
+                    result = send => result,
+                    _ = cancellation.cancelled() => {
+                        info!("The request was cancelled");
+                        Err(OhttpClientError::Cancelled)
+                    }
+                },
+                None => send.await,
+            }
+        }
+        .instrument(span);
+        let result = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, send).await {
+                Ok(result) => result,
+                Err(_) => {
+                    let e = OhttpClientError::Timeout("sending the request".to_string());
+                    error!("{e}");
+                    Err(e)
+                }
+            },
+            None => send.await,
+        };
+        let result = result.map_err(|mut e| {
+            if let OhttpClientError::RelayHttp {
+                correlation_id: id, ..
+            }
+            | OhttpClientError::GatewayProblem {
+                correlation_id: id, ..
+            } = &mut e
+            {
+                *id = Some(correlation_id.clone());
+            }
+            e
+        });
+        #[cfg(feature = "metrics")]
+        telemetry::record_request(&result);
+        result.map(|response| {
+            response
+                .with_correlation_id(correlation_id)
+                .with_timeouts(timeouts.idle, deadline)
+                .with_progress(self.progress.download.clone())
+                .with_cancellation(self.cancellation.clone())
+        })
+    }
+
+    /// Sends the request, retrying transient relay failures according to
+    /// the retry policy. Each attempt encapsulates the request afresh.
+    async fn send_with_retries(
+        &self,
+        url: &String,
+        headers: &HeaderMap,
+        bhttp_request: &[u8],
+        deadline: Option<Instant>,
+    ) -> Res<OhttpResponse> {
+        let retry = match &self.retry {
+            Some(retry) => retry,
+            None => {
+                return self
+                    .send_guarded(url, headers, bhttp_request, deadline)
+                    .await
+            }
+        };
+        retry.record_request();
+
+        let idempotent = is_idempotent(bhttp_request);
+        let mut retries = 0;
+        loop {
+            let e = match self
+                .send_guarded(url, headers, bhttp_request, deadline)
+                .await
+            {
+                Err(e)
+                    if retries < retry.policy.max_retries
+                        && retry.policy.should_retry(&e, idempotent) =>
+                {
+                    e
+                }
+                result => return result,
+            };
+
+            let delay = match retry.policy.delay(retries + 1, e.retry_after()) {
+                Some(delay) => delay,
+                None => return Err(e),
+            };
+            if !retry.try_spend() {
+                info!("Retry budget exhausted, not retrying");
+                return Err(e);
+            }
+
+            retries += 1;
+            info!(
+                "Retrying in {:?} after transient failure (attempt {}/{}): {}",
+                delay, retries, retry.policy.max_retries, e
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Sends the request unless the circuit breaker is open, and records its
+    /// outcome with the breaker. The total timeout is applied here, so that
+    /// an attempt cut short by it is recorded as a timeout rather than
+    /// dropped unrecorded.
+    async fn send_guarded(
+        &self,
+        url: &String,
+        headers: &HeaderMap,
+        bhttp_request: &[u8],
+        deadline: Option<Instant>,
+    ) -> Res<OhttpResponse> {
+        let breaker = match &self.breaker {
+            Some(breaker) => breaker,
+            None => return self.send_hedged(url, headers, bhttp_request).await,
+        };
+        if let Err(e) = breaker.check() {
+            error!("{e}");
+            return Err(e);
+        }
+        let send = self.send_hedged(url, headers, bhttp_request);
+        let result = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, send).await {
+                Ok(result) => result,
+                Err(_) => {
+                    let e = OhttpClientError::Timeout("sending the request".to_string());
+                    error!("{e}");
+                    Err(e)
+                }
+            },
+            None => send.await,
+        };
+        breaker.record(&result);
+        result
+    }
+
+    /// Sends the request, and sends it a second time if the hedging policy
+    /// applies and no response arrived within its delay. The first response
+    /// wins, unless it is an error while the other request is in flight.
+    async fn send_hedged(
+        &self,
+        url: &String,
+        headers: &HeaderMap,
+        bhttp_request: &[u8],
+    ) -> Res<OhttpResponse> {
+        let hedge = match &self.hedge {
+            Some(hedge) if hedge.applies(bhttp_request) => hedge,
+            _ => return self.send_with_failover(url, headers, bhttp_request).await,
+        };
+
+        let first = self.send_with_failover(url, headers, bhttp_request);
+        tokio::pin!(first);
+        tokio::select! {
+            result = &mut first => return result,
+            _ = tokio::time::sleep(hedge.delay) => {}
+        }
+        info!(
+            "No response after {:?}, sending a hedged request",
+            hedge.delay
+        );
+        let second = self.send_with_failover(url, headers, bhttp_request);
+        tokio::pin!(second);
+        // Dropping the pending request cancels it
+        tokio::select! {
+            result = &mut first => match result {
+                Ok(response) => Ok(response),
+                Err(_) => second.await,
+            },
+            result = &mut second => match result {
+                Ok(response) => Ok(response),
+                Err(_) => first.await,
+            },
+        }
+    }
+
+    /// Sends the request to the first healthy relay of the failover policy,
+    /// failing over to the next one if a relay is unreachable or fails with
+    /// a server error.
+    async fn send_with_failover(
+        &self,
+        url: &String,
+        headers: &HeaderMap,
+        bhttp_request: &[u8],
+    ) -> Res<OhttpResponse> {
+        let relays = match &self.relays {
+            Some(relays) => relays,
+            None => {
+                return self
+                    .send_with_key_refresh(url, headers, bhttp_request)
+                    .await
+            }
+        };
+
+        let mut last_error = None;
+        for relay in relays.candidates(url) {
+            match self
+                .send_with_key_refresh(&relay, headers, bhttp_request)
+                .await
+            {
+                Ok(response) => {
+                    relays.succeeded(&relay);
+                    return Ok(response);
+                }
+                Err(e) if relay_failed(&e) => {
+                    warn!("Relay {relay} failed, failing over: {e}");
+                    relays.failed(&relay);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error
+            .unwrap_or_else(|| OhttpClientError::Config("no relay configured".to_string())))
+    }
+
+    /// Encapsulates and sends the request. If the gateway rejects the key
+    /// configuration, the keys are refreshed and the request is sent again once.
+    async fn send_with_key_refresh(
+        &self,
+        url: &String,
+        headers: &HeaderMap,
+        bhttp_request: &[u8],
+    ) -> Res<OhttpResponse> {
+        match self.send_once(url, headers, bhttp_request).await {
+            Err(e) if self.key_provider.can_refresh() && is_key_mismatch(&e) => {
+                info!("Gateway rejected the key configuration, refreshing keys...");
+                if let Err(e) = self.refresh_keys().await {
+                    error!("{e}");
+                    return Err(e);
+                }
+                self.send_once(url, headers, bhttp_request).await
+            }
+            result => result,
+        }
+    }
+
+    /// Adds a fresh correlation ID to the outer headers, unless the caller
+    /// already set one, and returns it.
+    fn correlate(&self, headers: &HeaderMap) -> (HeaderMap, String) {
+        let mut headers = headers.clone();
+        let correlation_id = match headers
+            .get(&self.correlation_header)
+            .and_then(|id| id.to_str().ok())
+        {
+            Some(id) => id.to_string(),
+            None => {
+                // The ID is still logged, to correlate the events of the request
+                let id = hex::encode(rand::random::<[u8; 16]>());
+                if !self.minimal_outer_headers {
+                    if let Ok(value) = HeaderValue::from_str(&id) {
+                        headers.insert(self.correlation_header.clone(), value);
+                    }
+                }
+                id
+            }
+        };
+        (headers, correlation_id)
+    }
+
+    /// Adds the headers the client sends to the relay with every request to
+    /// the outer headers given by the caller.
+    async fn relay_headers(&self, headers: &HeaderMap) -> Res<HeaderMap> {
+        // Ask the gateway for an attestation token if we are going to verify it
+        let mut headers = headers.clone();
+        if (self.attestation.is_some() || self.attestation_policy.is_some())
+            && !headers.contains_key(ATTESTATION_TOKEN_HEADER)
+        {
+            headers.insert(ATTESTATION_TOKEN_HEADER, HeaderValue::from_static("true"));
+        }
+
+        // Authenticate to the relay with a fresh token
+        #[cfg(feature = "azure-auth")]
+        if let Some(provider) = &self.relay_auth {
+            let token = provider.token().await.and_then(|token| {
+                HeaderValue::from_str(&format!("Bearer {token}"))
+                    .map_err(|e| OhttpClientError::Auth(e.to_string()))
+            });
+            match token {
+                Ok(token) => headers.insert(reqwest::header::AUTHORIZATION, token),
+                Err(e) => {
+                    error!("{e}");
+                    return Err(e);
+                }
+            };
+        }
+
+        // Only propagate the trace context when asked to, since it links the
+        // request to the trace of the application
+        #[cfg(feature = "otel")]
+        if self.propagate_trace_context && !self.minimal_outer_headers {
+            otel::inject_trace_context(&mut headers);
+        }
+        Ok(headers)
+    }
+
+    /// Encapsulates the bhttp request using a fresh OHTTP request.
+    fn encapsulate(&self, bhttp_request: &[u8]) -> Res<(Vec<u8>, ohttp::ClientResponse)> {
+        let _span = info_span!("ohttp.encapsulate").entered();
+        let ohttp_request = self.keys.read().unwrap().config.client_request()?;
+        match ohttp_request.encapsulate(bhttp_request) {
+            Ok(result) => Ok(result),
             Err(e) => {
                 error!("{e}");
-                return Err(Box::new(e));
+                Err(e.into())
             }
-        };
+        }
+    }
+
+    async fn send_once(
+        &self,
+        url: &String,
+        headers: &HeaderMap,
+        bhttp_request: &[u8],
+    ) -> Res<OhttpResponse> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+        let (enc_request, ohttp_response) = self.encapsulate(bhttp_request)?;
+        // The key ID leads the header of the encapsulated request
+        let span = Span::current();
+        span.record("ohttp.key_id", enc_request[0]);
+        span.record("ohttp.request_size", enc_request.len());
+        #[cfg(feature = "metrics")]
+        telemetry::record_request_size(enc_request.len());
         trace!(
             "Encapsulated the OHTTP request {}",
-            hex::encode(&enc_request[0..60])
+            hex::encode(enc_request.get(..60).unwrap_or(&enc_request))
         );
 
+        let headers = self.relay_headers(headers).await?;
+
         // Post the encapsulated ohttp request buffer to args.url
-        let response = match post_request(url, headers, enc_request).await {
+        let request_size = enc_request.len() as u64;
+        let sent = Instant::now();
+        let response = match post_request(
+            self.transport.as_ref(),
+            &self.http.timeouts,
+            url,
+            self.protocol,
+            &headers,
+            enc_request,
+        )
+        .instrument(info_span!(
+            "relay.post",
+            url.full = %url,
+            http.response.status_code = field::Empty
+        ))
+        .await
+        {
             Ok(response) => response,
             Err(e) => {
                 error!("{e}");
                 return Err(e);
             }
         };
+        let response_headers = sent.elapsed();
+        span.record("http.response.status_code", response.status.as_u16());
+        #[cfg(feature = "metrics")]
+        telemetry::record_response_headers(response_headers);
         trace!("Posted the OHTTP request to {}", url);
 
+        // Verify the attestation token before handing out the response
+        let attestation = match &self.attestation {
+            Some(verifier) => match verifier.verify(&response.headers).await {
+                Ok(claims) => claims,
+                Err(e) => {
+                    error!("{e}");
+                    return Err(e);
+                }
+            },
+            // Keep any token the gateway returned, decoded without verification
+            None => response
+                .headers
+                .get(ATTESTATION_TOKEN_HEADER)
+                .and_then(|token| token.to_str().ok())
+                .map(AttestationClaims::unverified),
+        };
+
+        // Evaluate the attestation policy before yielding the response body
+        if let Some(policy) = &self.attestation_policy {
+            let result = match &attestation {
+                Some(claims) => policy.evaluate(claims),
+                None => Err(OhttpClientError::Attestation(
+                    "attestation policy requires a verified attestation token".to_string(),
+                )),
+            };
+            if let Err(e) = result {
+                error!("{e}");
+                return Err(e);
+            }
+        }
+
         // decapsulate and output the http response
-        match decapsulate_response(response, ohttp_response).await {
-            Ok(response) => Ok(response),
+        let encapsulated = Arc::new(AtomicU64::new(0));
+        let result = match self.protocol {
+            OhttpProtocol::Standard => {
+                decapsulate_standard_response(
+                    response,
+                    ohttp_response,
+                    attestation,
+                    encapsulated.clone(),
+                )
+                .await
+            }
+            OhttpProtocol::Chunked => {
+                decapsulate_response(response, ohttp_response, attestation, encapsulated.clone())
+                    .await
+            }
+        };
+        match result {
+            Ok(response) => {
+                // The digest in the attestation token is that of the encoded body
+                let mut response = integrity::bind_response(response)?;
+                if !self.keep_content_encoding {
+                    response = decompress::decompress(response, self.max_decoded_size);
+                }
+                let response = response
+                    .with_stats(sent, response_headers, request_size, encapsulated)
+                    .with_relay(url);
+                #[cfg(feature = "test-util")]
+                if let Some(recorder) = &self.recorder {
+                    let config = self.keys.read().unwrap().config.clone();
+                    return Ok(recorder.record(config.encoded(), bhttp_request, response));
+                }
+                Ok(response)
+            }
             Err(e) => {
                 error!("{e}");
                 Err(e)
@@ -366,11 +1075,11 @@ impl OhttpClient {
 
     #[allow(clippy::too_many_arguments)]
     pub async fn post_raw(
-        self,
+        &self,
         url: &String,
-        outer_headers: &Vec<String>,
+        outer_headers: &HeaderMap,
         http_request: &Vec<u8>,
-    ) -> Res<Response> {
+    ) -> Res<OhttpResponse> {
         // transform the http request into bhttp
         let mut cursor = Cursor::new(http_request);
         let request = Message::read_http(&mut cursor)?;
@@ -382,17 +1091,33 @@ impl OhttpClient {
             .await
     }
 
-    #[allow(clippy::too_many_arguments)]
+    /// Sends a request that is already encoded as a bhttp message, e.g. one
+    /// captured from another client.
+    pub async fn post_bhttp(
+        &self,
+        url: &String,
+        outer_headers: &HeaderMap,
+        bhttp_request: &[u8],
+    ) -> Res<OhttpResponse> {
+        // Check that the message is well-formed before encapsulating it
+        let mut cursor = Cursor::new(bhttp_request);
+        if let Err(e) = Message::read_bhttp(&mut cursor) {
+            error!("{e}");
+            return Err(e.into());
+        }
+
+        self.encapsulate_and_send(url, outer_headers, bhttp_request)
+            .await
+    }
+
     pub async fn post(
-        self,
+        &self,
         url: &String,
-        target_path: &str,
-        headers: &Vec<String>,
-        form_fields: &Vec<String>,
-        outer_headers: &Vec<String>,
-    ) -> Res<Response> {
+        request: &InnerRequestBuilder,
+        outer_headers: &HeaderMap,
+    ) -> Res<OhttpResponse> {
         //  Create ohttp request buffer
-        let request_buf = match create_request_buffer(target_path, headers, form_fields) {
+        let (request_buf, uploads) = match request.build_digested_blocking().await {
             Ok(result) => result,
             Err(e) => {
                 error!("{e}");
@@ -403,27 +1128,222 @@ impl OhttpClient {
 
         self.encapsulate_and_send(url, outer_headers, &request_buf)
             .await
+            .map(|response| response.with_uploads(uploads))
+    }
+
+    /// Builds and encapsulates the inner request like `post`, without
+    /// sending it, to inspect the request that would be sent.
+    pub fn build_only(&self, request: &InnerRequestBuilder) -> Res<BuiltRequest> {
+        let (http, bhttp) = match request.build_messages() {
+            Ok(result) => result,
+            Err(e) => {
+                error!("{e}");
+                return Err(e);
+            }
+        };
+        let (enc_request, _) = self.encapsulate(&bhttp)?;
+        Ok(BuiltRequest {
+            http,
+            bhttp,
+            key_id: enc_request[0],
+            encapsulated_size: enc_request.len(),
+        })
+    }
+
+    /// Encapsulates the inner request and returns the outer request that
+    /// `post` would send to the relay at `url`, without sending it, e.g. to
+    /// replay it with other tools. Only this client could decapsulate the
+    /// response, with the keys of the request, which are dropped.
+    pub async fn encapsulate_only(
+        &self,
+        url: &String,
+        request: &InnerRequestBuilder,
+        outer_headers: &HeaderMap,
+    ) -> Res<OuterRequest> {
+        let (request_buf, _) = match request.build_digested_blocking().await {
+            Ok(result) => result,
+            Err(e) => {
+                error!("{e}");
+                return Err(e);
+            }
+        };
+        if let Err(e) = self.outer_header_filter.check(outer_headers) {
+            error!("{e}");
+            return Err(e);
+        }
+        let (headers, _) = self.correlate(outer_headers);
+        let (enc_request, _) = self.encapsulate(&request_buf)?;
+        Ok(OuterRequest {
+            url: url.clone(),
+            content_type: self.protocol.request_content_type(),
+            headers: self.relay_headers(&headers).await?,
+            body: enc_request,
+        })
+    }
+
+    /// Sends `body` serialized as JSON with the method, path and headers of
+    /// `request`, replacing any body or form fields of `request`.
+    pub async fn post_json<T: Serialize + ?Sized>(
+        &self,
+        url: &String,
+        request: &InnerRequestBuilder,
+        body: &T,
+        outer_headers: &HeaderMap,
+    ) -> Res<OhttpResponse> {
+        let body = match serde_json::to_vec(body) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("{e}");
+                return Err(e.into());
+            }
+        };
+        let request = request.clone().json_body(body);
+        self.post(url, &request, outer_headers).await
+    }
+
+    /// Sends a request to an OpenAI-compatible chat completions endpoint
+    /// behind the gateway and returns the completion.
+    pub async fn chat(
+        &self,
+        url: &String,
+        request: &ChatRequest,
+        outer_headers: &HeaderMap,
+    ) -> Res<ChatCompletion> {
+        let response = self
+            .post(url, &request.inner_request(false)?, outer_headers)
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let e = OhttpClientError::Chat(format!("status {status}: {body}"));
+            error!("{e}");
+            return Err(e);
+        }
+        response.json().await
+    }
+
+    /// Like `chat`, but asks for the completion to be streamed and returns
+    /// its chunks as they are decapsulated.
+    pub async fn chat_stream(
+        &self,
+        url: &String,
+        request: &ChatRequest,
+        outer_headers: &HeaderMap,
+    ) -> Res<ChatStream> {
+        let response = self
+            .post(url, &request.inner_request(true)?, outer_headers)
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let e = OhttpClientError::Chat(format!("status {status}: {body}"));
+            error!("{e}");
+            return Err(e);
+        }
+        Ok(ChatStream::new(response.sse()))
     }
 }
 
 #[derive(Default)]
 pub struct OhttpClientBuilder {
-    kms_url: Option<String>,
+    kms_urls: Vec<String>,
     kms_cert: Option<PathBuf>,
     config: Option<HexArg>,
+    saved_config: Option<PathBuf>,
+    allow_unverified_keys: bool,
+    key_preference: Option<KeyPreference>,
+    kms_retry: Option<KmsRetryPolicy>,
+    kms_auth: Option<KmsAuth>,
+    discover_dns: Option<String>,
+    protocol: OhttpProtocol,
+    proxy: Option<String>,
+    timeouts: Timeouts,
+    connection_pool: ConnectionPool,
+    http_version: HttpVersion,
+    relay_retry: Option<RelayRetryPolicy>,
+    hedge: Option<HedgePolicy>,
+    relay_failover: Option<RelayFailoverPolicy>,
+    circuit_breaker: Option<CircuitBreakerPolicy>,
+    rate_limit: Option<RateLimit>,
+    outer_header_filter: OuterHeaderFilter,
+    user_agent: Option<String>,
+    minimal_outer_headers: bool,
+    keep_content_encoding: bool,
+    max_decoded_size: u64,
+    relay_tls: RelayTls,
+    client_identity: Option<ClientIdentity>,
+    kms_client_identity: bool,
+    attestation: Option<AttestationConfig>,
+    attestation_policy: Option<AttestationPolicy>,
+    progress: Progress,
+    transport: Option<Arc<dyn OuterTransport>>,
+    correlation_header: String,
+    #[cfg(feature = "azure-auth")]
+    relay_auth: Option<AzureTokenProvider>,
+    #[cfg(feature = "test-util")]
+    record: Option<PathBuf>,
+    #[cfg(feature = "fault-injection")]
+    faults: Option<Faults>,
+    #[cfg(feature = "otel")]
+    propagate_trace_context: bool,
 }
 
 impl OhttpClientBuilder {
     pub fn new() -> OhttpClientBuilder {
         OhttpClientBuilder {
-            kms_url: None,
+            kms_urls: Vec::new(),
             kms_cert: None,
             config: None,
+            saved_config: None,
+            allow_unverified_keys: false,
+            key_preference: None,
+            kms_retry: None,
+            kms_auth: None,
+            discover_dns: None,
+            protocol: OhttpProtocol::Chunked,
+            proxy: None,
+            timeouts: Timeouts::default(),
+            connection_pool: ConnectionPool::default(),
+            http_version: HttpVersion::Auto,
+            relay_retry: None,
+            hedge: None,
+            relay_failover: None,
+            circuit_breaker: None,
+            rate_limit: None,
+            outer_header_filter: OuterHeaderFilter::default(),
+            user_agent: None,
+            minimal_outer_headers: false,
+            keep_content_encoding: false,
+            max_decoded_size: decompress::DEFAULT_MAX_DECODED_SIZE,
+            relay_tls: RelayTls::default(),
+            client_identity: None,
+            kms_client_identity: false,
+            attestation: None,
+            attestation_policy: None,
+            progress: Progress::default(),
+            transport: None,
+            correlation_header: DEFAULT_CORRELATION_HEADER.to_string(),
+            #[cfg(feature = "azure-auth")]
+            relay_auth: None,
+            #[cfg(feature = "test-util")]
+            record: None,
+            #[cfg(feature = "fault-injection")]
+            faults: None,
+            #[cfg(feature = "otel")]
+            propagate_trace_context: false,
         }
     }
 
     pub fn kms_url(mut self, kms_url: &Option<String>) -> OhttpClientBuilder {
-        self.kms_url.clone_from(kms_url);
+        self.kms_urls = kms_url.iter().cloned().collect();
+        self
+    }
+
+    /// Obtains the key configuration from the first available of several KMS
+    /// replicas, in order. The replica that last served the key configuration
+    /// is tried first when the keys are refreshed.
+    pub fn kms_urls(mut self, kms_urls: &[String]) -> OhttpClientBuilder {
+        self.kms_urls = kms_urls.to_vec();
         self
     }
 
@@ -437,24 +1357,479 @@ impl OhttpClientBuilder {
         self
     }
 
-    pub async fn build(self) -> Res<OhttpClient> {
-        //  create the OHTTP request using the KMS or the static config file
-        let result = if let (Some(kms_url), Some(kms_cert)) = (self.kms_url, self.kms_cert) {
-            create_request_from_kms_config(&kms_url, &kms_cert).await
+    /// Uses a key configuration saved with `OhttpClient::save_key_config`
+    /// instead of contacting the KMS. Its receipt is verified again with the
+    /// KMS certificate, which must be set unless unverified keys are allowed.
+    pub fn saved_config(mut self, saved_config: &Option<PathBuf>) -> OhttpClientBuilder {
+        self.saved_config.clone_from(saved_config);
+        self
+    }
+
+    /// Allows using keys whose receipt is not verified, such as a saved key
+    /// configuration loaded without the KMS certificate.
+    pub fn allow_unverified_keys(mut self, allow: bool) -> OhttpClientBuilder {
+        self.allow_unverified_keys = allow;
+        self
+    }
+
+    /// Prefers the KMS key configuration with the given HPKE algorithms when
+    /// the KMS publishes several.
+    pub fn key_preference(mut self, key_preference: &Option<KeyPreference>) -> OhttpClientBuilder {
+        self.key_preference.clone_from(key_preference);
+        self
+    }
+
+    /// How long to wait for the KMS to generate the receipt of a new key.
+    pub fn kms_retry(mut self, kms_retry: &Option<KmsRetryPolicy>) -> OhttpClientBuilder {
+        self.kms_retry.clone_from(kms_retry);
+        self
+    }
+
+    /// Authenticates to the KMS with a bearer token when listing public keys.
+    pub fn kms_auth(mut self, kms_auth: KmsAuth) -> OhttpClientBuilder {
+        self.kms_auth = Some(kms_auth);
+        self
+    }
+
+    /// Discovers the key configuration from the HTTPS resource records of the
+    /// given gateway name instead of a KMS or a static config. The keys are
+    /// fetched from the gateway and are not attested by the KMS.
+    pub fn discover_dns(mut self, name: &Option<String>) -> OhttpClientBuilder {
+        self.discover_dns.clone_from(name);
+        self
+    }
+
+    pub fn protocol(mut self, protocol: OhttpProtocol) -> OhttpClientBuilder {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Name of the outer request header carrying the correlation ID of each
+    /// request, `x-ms-client-request-id` by default. A fresh random ID is
+    /// generated for each request unless the outer headers already set it.
+    pub fn correlation_header(mut self, name: &str) -> OhttpClientBuilder {
+        self.correlation_header = name.to_string();
+        self
+    }
+
+    /// Sends the relay and KMS requests through an HTTP or SOCKS5 proxy, e.g.
+    /// `http://proxy:3128` or `socks5://proxy:1080`. Without it, the
+    /// `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` variables are honored.
+    pub fn proxy(mut self, proxy: &Option<String>) -> OhttpClientBuilder {
+        self.proxy.clone_from(proxy);
+        self
+    }
+
+    /// Bounds how long relay and KMS requests may take.
+    pub fn timeouts(mut self, timeouts: &Timeouts) -> OhttpClientBuilder {
+        self.timeouts.clone_from(timeouts);
+        self
+    }
+
+    /// Sizes the pool of connections to the relay, which are reused by the
+    /// requests of the client.
+    pub fn connection_pool(mut self, pool: &ConnectionPool) -> OhttpClientBuilder {
+        self.connection_pool.clone_from(pool);
+        self
+    }
+
+    /// Selects the HTTP version of the connections to the relay.
+    pub fn http_version(mut self, version: HttpVersion) -> OhttpClientBuilder {
+        self.http_version = version;
+        self
+    }
+
+    /// Retries requests that fail because of transient relay errors.
+    pub fn relay_retry(mut self, relay_retry: &Option<RelayRetryPolicy>) -> OhttpClientBuilder {
+        self.relay_retry.clone_from(relay_retry);
+        self
+    }
+
+    /// Fails over to other relays when the relay of a request is unreachable
+    /// or fails with a server error.
+    pub fn relay_failover(
+        mut self,
+        relay_failover: &Option<RelayFailoverPolicy>,
+    ) -> OhttpClientBuilder {
+        self.relay_failover.clone_from(relay_failover);
+        self
+    }
+
+    /// Fails requests fast, without sending them, while the relay or the
+    /// gateway keeps failing.
+    pub fn circuit_breaker(
+        mut self,
+        circuit_breaker: &Option<CircuitBreakerPolicy>,
+    ) -> OhttpClientBuilder {
+        self.circuit_breaker.clone_from(circuit_breaker);
+        self
+    }
+
+    /// Limits the rate at which requests are sent to the relay, across all
+    /// the clones of the client.
+    pub fn rate_limit(mut self, rate_limit: &Option<RateLimit>) -> OhttpClientBuilder {
+        self.rate_limit.clone_from(rate_limit);
+        self
+    }
+
+    /// Checks the outer headers of the requests for headers that belong in
+    /// the inner request. By default, a warning is logged for cookies,
+    /// credentials and forwarding headers.
+    pub fn outer_header_filter(mut self, filter: &OuterHeaderFilter) -> OhttpClientBuilder {
+        self.outer_header_filter.clone_from(filter);
+        self
+    }
+
+    /// Sends this User-Agent to the relay. No User-Agent is sent by default.
+    pub fn user_agent(mut self, user_agent: &Option<String>) -> OhttpClientBuilder {
+        self.user_agent.clone_from(user_agent);
+        self
+    }
+
+    /// Only sends the outer headers that the relay and the gateway need, so
+    /// that the relay sees as little as possible of what tells clients apart:
+    /// no User-Agent, correlation ID or trace context. The outer headers
+    /// given by the caller and the request for an attestation token, if the
+    /// token is verified, are still sent.
+    pub fn minimal_outer_headers(mut self, minimal: bool) -> OhttpClientBuilder {
+        self.minimal_outer_headers = minimal;
+        self
+    }
+
+    /// Decodes inner responses with a gzip, deflate or br Content-Encoding as
+    /// their body is read, which is the default. When disabled, the body is
+    /// returned as sent by the target.
+    pub fn decompress(mut self, decompress: bool) -> OhttpClientBuilder {
+        self.keep_content_encoding = !decompress;
+        self
+    }
+
+    /// Bounds the size of decoded response bodies, 1 GiB by default, so that
+    /// a small compressed body cannot expand without limit. Reading a body
+    /// fails once more is decoded.
+    pub fn max_decoded_size(mut self, max_decoded_size: u64) -> OhttpClientBuilder {
+        self.max_decoded_size = max_decoded_size;
+        self
+    }
+
+    /// Hedges idempotent requests whose response is late.
+    pub fn hedge(mut self, hedge: &Option<HedgePolicy>) -> OhttpClientBuilder {
+        self.hedge.clone_from(hedge);
+        self
+    }
+
+    /// Trusts the root certificates in the given PEM files for the relay
+    /// connection, in addition to the built-in roots unless disabled.
+    pub fn relay_ca_certs(mut self, ca_certs: &[PathBuf]) -> OhttpClientBuilder {
+        self.relay_tls.ca_certs = ca_certs.to_vec();
+        self
+    }
+
+    /// Whether the built-in web PKI roots are trusted for the relay connection.
+    pub fn relay_system_roots(mut self, system_roots: bool) -> OhttpClientBuilder {
+        self.relay_tls.system_roots = system_roots;
+        self
+    }
+
+    /// Requires the relay certificate to carry one of the given public keys,
+    /// each given as base64-encoded SHA-256 digest of its subject public key
+    /// info, e.g. `sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=`.
+    pub fn relay_spki_pins(mut self, pins: &[String]) -> OhttpClientBuilder {
+        self.relay_tls.spki_pins = pins.to_vec();
+        self
+    }
+
+    /// Authenticates to the relay with a client certificate, read with
+    /// `ClientIdentity::from_pem` or `ClientIdentity::from_pkcs12`.
+    pub fn client_identity(mut self, identity: &Option<ClientIdentity>) -> OhttpClientBuilder {
+        self.client_identity.clone_from(identity);
+        self
+    }
+
+    /// Also presents the client certificate to the KMS.
+    pub fn kms_client_identity(mut self, enabled: bool) -> OhttpClientBuilder {
+        self.kms_client_identity = enabled;
+        self
+    }
+
+    /// Verifies the attestation token returned by the gateway with every response.
+    pub fn attestation(mut self, attestation: &Option<AttestationConfig>) -> OhttpClientBuilder {
+        self.attestation.clone_from(attestation);
+        self
+    }
+
+    /// Requires every response to come from a TEE that satisfies the policy.
+    /// The policy is evaluated against the token verified with `attestation`.
+    pub fn attestation_policy(
+        mut self,
+        attestation_policy: &Option<AttestationPolicy>,
+    ) -> OhttpClientBuilder {
+        self.attestation_policy.clone_from(attestation_policy);
+        self
+    }
+
+    /// Reports the number of encapsulated bytes handed to the relay
+    /// connection as each request is sent, out of the encapsulated size.
+    pub fn on_upload_progress<F>(mut self, callback: F) -> OhttpClientBuilder
+    where
+        F: Fn(u64, Option<u64>) + Send + Sync + 'static,
+    {
+        self.progress.upload = Some(Arc::new(callback));
+        self
+    }
+
+    /// Reports the number of decapsulated body bytes read from each response.
+    /// The total is only reported once the body is complete.
+    pub fn on_download_progress<F>(mut self, callback: F) -> OhttpClientBuilder
+    where
+        F: Fn(u64, Option<u64>) + Send + Sync + 'static,
+    {
+        self.progress.download = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sends the encapsulated requests to the relay with the given transport
+    /// instead of reqwest. The proxy, TLS, connect timeout and upload progress
+    /// settings then only apply to the KMS, if any.
+    pub fn transport(mut self, transport: Arc<dyn OuterTransport>) -> OhttpClientBuilder {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Authenticates to the relay with Entra ID tokens, which are acquired
+    /// and refreshed automatically.
+    #[cfg(feature = "azure-auth")]
+    pub fn relay_auth(mut self, relay_auth: &Option<AzureTokenProvider>) -> OhttpClientBuilder {
+        self.relay_auth.clone_from(relay_auth);
+        self
+    }
+
+    /// Records the decapsulated requests and responses to a cassette file,
+    /// which `MockGateway::replay` serves back in tests.
+    #[cfg(feature = "test-util")]
+    pub fn record(mut self, cassette: &Option<PathBuf>) -> OhttpClientBuilder {
+        self.record.clone_from(cassette);
+        self
+    }
+
+    /// Injects failures into the requests sent to the relay, to test how
+    /// applications cope with them.
+    #[cfg(feature = "fault-injection")]
+    pub fn faults(mut self, faults: &Option<Faults>) -> OhttpClientBuilder {
+        self.faults.clone_from(faults);
+        self
+    }
+
+    /// Propagates the OpenTelemetry context of the current span to the relay
+    /// in the outer request headers. Off by default, since the relay could
+    /// use the trace ID to link requests that are otherwise unlinkable.
+    #[cfg(feature = "otel")]
+    pub fn propagate_trace_context(mut self, propagate: bool) -> OhttpClientBuilder {
+        self.propagate_trace_context = propagate;
+        self
+    }
+
+    fn http_settings(&self) -> Res<HttpSettings> {
+        let relay_tls = match self.relay_tls.client_config(self.client_identity.as_ref()) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("{e}");
+                return Err(e);
+            }
+        };
+        let kms_identity = if self.kms_client_identity {
+            self.client_identity.clone()
         } else {
-            create_request_from_encoded_config_list(&self.config)
+            None
+        };
+        Ok(HttpSettings {
+            proxy: self.proxy.clone(),
+            relay_tls,
+            relay_identity: self.client_identity.clone(),
+            kms_identity,
+            timeouts: self.timeouts.clone(),
+            pool: self.connection_pool.clone(),
+            version: self.http_version,
+            user_agent: if self.minimal_outer_headers {
+                None
+            } else {
+                self.user_agent.clone()
+            },
+        })
+    }
+
+    fn kms_settings(&self, http: &HttpSettings) -> Option<KmsSettings> {
+        match &self.kms_cert {
+            Some(cert) if !self.kms_urls.is_empty() => Some(KmsSettings::new(
+                self.kms_urls.clone(),
+                cert.clone(),
+                self.key_preference.clone().unwrap_or_default(),
+                self.kms_retry.clone().unwrap_or_default(),
+                self.kms_auth.clone(),
+                http.clone(),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Fails unless the key configuration is obtained from a single source,
+    /// and the KMS certificate is set if that is the KMS.
+    fn check_key_source(&self) -> Res<()> {
+        let sources: Vec<_> = [
+            ("a KMS URL", !self.kms_urls.is_empty()),
+            ("a key configuration", self.config.is_some()),
+            ("a saved key configuration", self.saved_config.is_some()),
+            ("DNS discovery", self.discover_dns.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(source, set)| set.then_some(source))
+        .collect();
+        if sources.len() > 1 {
+            return Err(OhttpClientError::Config(format!(
+                "the key configuration is obtained from one source only, but {} are set",
+                sources.join(" and ")
+            )));
+        }
+        if !self.kms_urls.is_empty() && self.kms_cert.is_none() {
+            return Err(OhttpClientError::Config(
+                "the KMS certificate is required to verify the keys of the KMS".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fetches all key configurations published by the KMS and verifies the
+    /// receipt of each of them, without building a client. Configurations
+    /// that fail verification are returned as errors.
+    pub async fn fetch_kms_keys(&self) -> Res<Vec<Res<KmsKey>>> {
+        let http = self.http_settings()?;
+        let kms = self.kms_settings(&http).ok_or_else(|| {
+            OhttpClientError::Config("a KMS URL and certificate are required".to_string())
+        })?;
+        let result = kms.fetch_keys().await;
+        if let Err(e) = &result {
+            error!("{e}");
+        }
+        result
+    }
+
+    pub async fn build(self) -> Res<OhttpClient> {
+        let http = self.http_settings()?;
+        let correlation_header = match HeaderName::from_bytes(self.correlation_header.as_bytes()) {
+            Ok(name) => name,
+            Err(_) => {
+                let e = OhttpClientError::Config(format!(
+                    "invalid correlation header {}",
+                    self.correlation_header
+                ));
+                error!("{e}");
+                return Err(e);
+            }
+        };
+
+        if let Some(limit) = &self.rate_limit {
+            let rate = limit.requests_per_second;
+            if !rate.is_finite() || rate <= 0.0 || limit.burst == 0 {
+                let e = OhttpClientError::Config(
+                    "the rate limit must have a finite, positive rate and a nonzero burst"
+                        .to_string(),
+                );
+                error!("{e}");
+                return Err(e);
+            }
+        }
+
+        if let Err(e) = self.check_key_source() {
+            error!("{e}");
+            return Err(e);
+        }
+
+        //  obtain the key configuration using the KMS or the static config file
+        let kms = self.kms_settings(&http);
+        let key_provider = match (self.saved_config, kms, self.discover_dns) {
+            (Some(path), _, _) => KeyProvider::Saved {
+                path,
+                kms_cert: self.kms_cert,
+                allow_unverified: self.allow_unverified_keys,
+            },
+            (None, Some(kms), _) => KeyProvider::Kms(kms),
+            (None, None, Some(name)) => match http.service_client() {
+                Ok(client) => KeyProvider::Dns(name, client),
+                Err(e) => {
+                    error!("{e}");
+                    return Err(e);
+                }
+            },
+            (None, None, None) => KeyProvider::Static(self.config),
+        };
+
+        let keys = match key_provider.load().await {
+            Ok(keys) => keys,
+            Err(e) => {
+                error!("{e}");
+                return Err(e);
+            }
         };
 
-        let ohttp_request = match result {
-            Ok(request) => request,
+        let attestation = self
+            .attestation
+            .map(|config| {
+                http.service_client()
+                    .map(|client| AttestationVerifier::new(config, client))
+            })
+            .transpose();
+        let attestation = match attestation {
+            Ok(attestation) => attestation,
             Err(e) => {
                 error!("{e}");
                 return Err(e);
             }
         };
 
-        trace!("Created ohttp client request");
+        let transport: Arc<dyn OuterTransport> = match self.transport {
+            Some(transport) => transport,
+            None => match ReqwestTransport::new(&http, self.progress.upload.clone()) {
+                Ok(transport) => Arc::new(transport),
+                Err(e) => {
+                    error!("{e}");
+                    return Err(e);
+                }
+            },
+        };
+        #[cfg(feature = "fault-injection")]
+        let transport: Arc<dyn OuterTransport> = match self.faults {
+            Some(faults) => Arc::new(FaultInjectingTransport::new(transport, faults)),
+            None => transport,
+        };
+
+        trace!("Created ohttp client");
 
-        Ok(OhttpClient { ohttp_request })
+        Ok(OhttpClient {
+            key_provider,
+            keys: Arc::new(RwLock::new(keys)),
+            protocol: self.protocol,
+            http,
+            transport,
+            retry: self.relay_retry.map(RelayRetry::new),
+            hedge: self.hedge,
+            relays: self.relay_failover.map(Relays::new),
+            breaker: self.circuit_breaker.map(CircuitBreaker::new),
+            rate_limiter: self.rate_limit.map(RateLimiter::new),
+            outer_header_filter: self.outer_header_filter,
+            minimal_outer_headers: self.minimal_outer_headers,
+            keep_content_encoding: self.keep_content_encoding,
+            max_decoded_size: self.max_decoded_size,
+            attestation,
+            attestation_policy: self.attestation_policy,
+            progress: self.progress,
+            cancellation: None,
+            correlation_header,
+            #[cfg(feature = "azure-auth")]
+            relay_auth: self.relay_auth,
+            #[cfg(feature = "test-util")]
+            recorder: self.record.as_deref().map(cassette::Recorder::new),
+            #[cfg(feature = "otel")]
+            propagate_trace_context: self.propagate_trace_context,
+        })
     }
 }
@@ -0,0 +1,52 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::err::{OhttpClientError, Res};
+use std::str::FromStr;
+use tracing_subscriber::{EnvFilter, FmtSubscriber};
+
+/// The format of the events written by the subscriber of `init_logging`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines, with the file and line of each event.
+    #[default]
+    Text,
+    /// One JSON object per event, with the fields of the event and of the
+    /// spans it occurred in, e.g. the URL and correlation ID of a request,
+    /// for ingestion into log analytics.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = OhttpClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(OhttpClientError::Config(format!(
+                "unknown log format {s}, expected text or json"
+            ))),
+        }
+    }
+}
+
+/// Installs a global tracing subscriber writing the events of the client to
+/// stdout in `format`, filtered with the `RUST_LOG` environment variable.
+pub fn init_logging(format: LogFormat) -> Res<()> {
+    let builder = FmtSubscriber::builder()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_file(true)
+        .with_line_number(true);
+    let result = match format {
+        LogFormat::Text => tracing::subscriber::set_global_default(builder.finish()),
+        LogFormat::Json => tracing::subscriber::set_global_default(
+            builder
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .finish(),
+        ),
+    };
+    result.map_err(|e| OhttpClientError::Config(format!("logging: {e}")))
+}
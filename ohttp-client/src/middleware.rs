@@ -0,0 +1,157 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    err::{OhttpClientError, Res},
+    request::InnerRequestBuilder,
+    response::{BodyStream, OhttpResponse},
+    OhttpClient,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use reqwest::{
+    header::{HeaderMap, CONTENT_LENGTH, TRANSFER_ENCODING},
+    Request, Response,
+};
+use reqwest_middleware::{Middleware, Next};
+use std::{
+    pin::Pin,
+    sync::{Mutex, PoisonError},
+    task::{Context, Poll},
+};
+use task_local_extensions::Extensions;
+use tracing::{error, trace};
+
+/// Makes a body stream `Sync`, as `reqwest::Body::wrap_stream` requires. The
+/// stream is only polled through a mutable reference, so the mutex is never
+/// locked.
+struct SyncBodyStream(Mutex<BodyStream>);
+
+impl Stream for SyncBodyStream {
+    type Item = Res<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Res<Bytes>>> {
+        self.get_mut()
+            .0
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .poll_next_unpin(cx)
+    }
+}
+
+/// Converts a reqwest request into an inner request. Streaming bodies are
+/// not supported, since the request is encapsulated as a whole.
+fn inner_request(request: &Request) -> Res<InnerRequestBuilder> {
+    let url = request.url();
+    let target = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+
+    // The body is framed again when the request is encoded
+    let mut headers = request.headers().clone();
+    headers.remove(CONTENT_LENGTH);
+    headers.remove(TRANSFER_ENCODING);
+    let mut inner = InnerRequestBuilder::new()
+        .method(request.method().clone())
+        .path(&target)
+        .headers(&headers);
+    if let Some(body) = request.body() {
+        let body = body.as_bytes().ok_or_else(|| {
+            OhttpClientError::InvalidRequest(
+                "streaming request bodies cannot be sent obliviously".to_string(),
+            )
+        })?;
+        if !body.is_empty() {
+            inner = inner.body(body.to_vec());
+        }
+    }
+    Ok(inner)
+}
+
+/// Converts a decapsulated response into a reqwest response whose body
+/// streams the decapsulated chunks.
+fn reqwest_response(response: OhttpResponse) -> Res<Response> {
+    let mut builder = http::Response::builder().status(response.status());
+    if let Some(headers) = builder.headers_mut() {
+        headers.extend(response.headers().clone());
+    }
+    let stream: BodyStream = Box::pin(response.bytes_stream());
+    let body = reqwest::Body::wrap_stream(SyncBodyStream(Mutex::new(stream)));
+    let response = builder
+        .body(body)
+        .map_err(|e| OhttpClientError::InvalidRequest(e.to_string()))?;
+    Ok(Response::from(response))
+}
+
+/// A `reqwest_middleware` middleware that sends requests to the configured
+/// hosts obliviously through the relay at `url`, so that existing reqwest
+/// code can use the oblivious transport unchanged. The path and query of the
+/// request URL are sent to the gateway. Requests to other hosts are passed
+/// on to the next middleware.
+#[derive(Clone)]
+pub struct OhttpMiddleware {
+    client: OhttpClient,
+    url: String,
+    hosts: Vec<String>,
+    outer_headers: HeaderMap,
+}
+
+impl OhttpMiddleware {
+    pub fn new(client: OhttpClient, url: &str) -> OhttpMiddleware {
+        OhttpMiddleware {
+            client,
+            url: url.to_string(),
+            hosts: Vec::new(),
+            outer_headers: HeaderMap::new(),
+        }
+    }
+
+    /// Sends requests to this host obliviously.
+    pub fn host(mut self, host: &str) -> OhttpMiddleware {
+        self.hosts.push(host.to_ascii_lowercase());
+        self
+    }
+
+    /// Adds headers to the outer request to the relay, e.g. an API key.
+    pub fn outer_headers(mut self, outer_headers: &HeaderMap) -> OhttpMiddleware {
+        self.outer_headers.extend(outer_headers.clone());
+        self
+    }
+
+    fn intercepts(&self, request: &Request) -> bool {
+        request
+            .url()
+            .host_str()
+            .is_some_and(|host| self.hosts.iter().any(|h| h == host))
+    }
+
+    async fn send(&self, request: &Request) -> Res<Response> {
+        let inner = inner_request(request)?;
+        let response = self
+            .client
+            .post(&self.url, &inner, &self.outer_headers)
+            .await?;
+        reqwest_response(response)
+    }
+}
+
+#[async_trait]
+impl Middleware for OhttpMiddleware {
+    async fn handle(
+        &self,
+        request: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        if !self.intercepts(&request) {
+            return next.run(request, extensions).await;
+        }
+        trace!("Sending {} {} obliviously", request.method(), request.url());
+        self.send(&request).await.map_err(|e| {
+            error!("{e}");
+            reqwest_middleware::Error::Middleware(e.into())
+        })
+    }
+}
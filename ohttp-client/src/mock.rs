@@ -0,0 +1,286 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    attestation::ATTESTATION_TOKEN_HEADER,
+    err::{OhttpClientError, Res},
+    request::{write_varint, write_vec},
+    transport::{OuterRequest, OuterResponse, OuterTransport},
+    HexArg, OhttpClient, OhttpClientBuilder, OHTTP_CHUNKED_REQUEST_CONTENT_TYPE,
+};
+use bhttp::Message;
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream, StreamExt};
+use ohttp::{
+    hpke::{Aead, Kdf, Kem},
+    KeyConfig, Server, SymmetricSuite,
+};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE},
+    Method, StatusCode,
+};
+use serde_json::json;
+use std::{
+    io::Cursor,
+    sync::{Arc, Mutex},
+};
+use tracing::{error, trace};
+
+const OHTTP_CHUNKED_RESPONSE_CONTENT_TYPE: &str = "message/ohttp-chunked-res";
+const OHTTP_RESPONSE_CONTENT_TYPE: &str = "message/ohttp-res";
+
+/// Size of the chunks in which chunked responses are encapsulated, so that
+/// clients decapsulate them as they are read, as from a real gateway.
+const CHUNK_SIZE: usize = 16 << 10;
+
+/// An inner request decapsulated by the mock gateway.
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: Method,
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+/// An inner response scripted by a test.
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl MockResponse {
+    pub fn new(status: StatusCode) -> MockResponse {
+        MockResponse {
+            status,
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Adds a header. Panics if the name or value is invalid.
+    pub fn header(mut self, name: &str, value: &str) -> MockResponse {
+        let name = HeaderName::from_bytes(name.as_bytes()).expect("valid header name");
+        let value = HeaderValue::from_str(value).expect("valid header value");
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> MockResponse {
+        self.body = body.into();
+        self
+    }
+}
+
+type Handler = Arc<dyn Fn(&MockRequest) -> MockResponse + Send + Sync>;
+
+/// An in-process gateway for testing applications that embed the client,
+/// without a relay, gateway or KMS. It generates its own key configuration,
+/// decapsulates the requests sent through it, answers them with the scripted
+/// inner responses, and speaks both standard and chunked OHTTP. It takes the
+/// place of the relay as the transport of the client:
+///
+/// ```ignore
+/// let gateway = MockGateway::new()?
+///     .respond_with(|request| MockResponse::new(StatusCode::OK).body(request.path.clone()));
+/// let client = gateway.client(OhttpClientBuilder::new()).await?;
+/// ```
+///
+/// Since the KMS receipts cannot be minted outside of the KMS, the key
+/// configuration is given to the client directly rather than through a KMS.
+#[derive(Clone)]
+pub struct MockGateway {
+    server: Arc<Mutex<Server>>,
+    config: Vec<u8>,
+    handler: Handler,
+    attestation_token: Option<String>,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockGateway {
+    /// Creates a gateway with a fresh X25519 key, which echoes the body of
+    /// every request until other responses are scripted.
+    pub fn new() -> Res<MockGateway> {
+        let config = KeyConfig::new(
+            1,
+            Kem::X25519Sha256,
+            vec![SymmetricSuite::new(Kdf::HkdfSha256, Aead::Aes128Gcm)],
+        )?;
+        let encoded = KeyConfig::encode_list(&[&config])?;
+        Ok(MockGateway {
+            server: Arc::new(Mutex::new(Server::new(config)?)),
+            config: encoded,
+            handler: Arc::new(|request: &MockRequest| {
+                MockResponse::new(StatusCode::OK).body(request.body.clone())
+            }),
+            attestation_token: None,
+            requests: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    /// Answers every request with the response returned by `handler`.
+    pub fn respond_with<F>(mut self, handler: F) -> MockGateway
+    where
+        F: Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+    {
+        self.handler = Arc::new(handler);
+        self
+    }
+
+    /// Returns this attestation token with every response.
+    pub fn attestation_token(mut self, token: &str) -> MockGateway {
+        self.attestation_token = Some(token.to_string());
+        self
+    }
+
+    /// The encoded key configuration list of the gateway.
+    pub fn config(&self) -> HexArg {
+        HexArg(self.config.clone())
+    }
+
+    /// The inner requests received so far, in order.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Builds a client that sends its requests to this gateway, with the
+    /// other settings of `builder`.
+    pub async fn client(&self, builder: OhttpClientBuilder) -> Res<OhttpClient> {
+        builder
+            .config(&Some(self.config()))
+            .transport(Arc::new(self.clone()))
+            .build()
+            .await
+    }
+
+    pub(crate) fn inner_request(bhttp_request: &[u8]) -> Res<MockRequest> {
+        let message = Message::read_bhttp(&mut Cursor::new(bhttp_request))?;
+        let invalid = |what: &str| {
+            OhttpClientError::InvalidRequest(format!("inner request has an invalid {what}"))
+        };
+        let control = message.control();
+        let method = Method::from_bytes(control.method().unwrap_or_default())
+            .map_err(|_| invalid("method"))?;
+        let path = String::from_utf8(control.path().unwrap_or_default().to_vec())
+            .map_err(|_| invalid("path"))?;
+        let mut headers = HeaderMap::new();
+        for field in message.header().fields() {
+            let name = HeaderName::from_bytes(field.name()).map_err(|_| invalid("header name"))?;
+            let value =
+                HeaderValue::from_bytes(field.value()).map_err(|_| invalid("header value"))?;
+            headers.append(name, value);
+        }
+        Ok(MockRequest {
+            method,
+            path,
+            headers,
+            body: message.content().to_vec(),
+        })
+    }
+
+    /// Encodes the response as a known-length bhttp message (RFC 9292,
+    /// Section 3.1).
+    fn bhttp_response(response: &MockResponse) -> Vec<u8> {
+        let mut fields = Vec::new();
+        for (name, value) in &response.headers {
+            write_vec(&mut fields, name.as_str().as_bytes());
+            write_vec(&mut fields, value.as_bytes());
+        }
+        let mut buf = Vec::new();
+        write_varint(&mut buf, 1);
+        write_varint(&mut buf, response.status.as_u16().into());
+        write_vec(&mut buf, &fields);
+        write_vec(&mut buf, &response.body);
+        write_varint(&mut buf, 0);
+        buf
+    }
+
+    async fn respond(&self, request: OuterRequest) -> Res<OuterResponse> {
+        let (bhttp_request, server_response) =
+            self.server.lock().unwrap().decapsulate(&request.body)?;
+        let inner = Self::inner_request(&bhttp_request)?;
+        trace!("Mock gateway received {} {}", inner.method, inner.path);
+        let response = (self.handler)(&inner);
+        self.requests.lock().unwrap().push(inner);
+
+        let mut headers = HeaderMap::new();
+        let body = if request.content_type == OHTTP_CHUNKED_REQUEST_CONTENT_TYPE {
+            // The inner status and headers are those of the outer response
+            headers.extend(response.headers.clone());
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static(OHTTP_CHUNKED_RESPONSE_CONTENT_TYPE),
+            );
+            let mut chunks: Vec<_> = response
+                .body
+                .chunks(CHUNK_SIZE)
+                .map(|chunk| Ok(chunk.to_vec()))
+                .collect();
+            if chunks.is_empty() {
+                chunks.push(Ok(Vec::new()));
+            }
+            let chunks = stream::iter(chunks);
+            server_response
+                .encapsulate_stream(chunks)
+                .map(|chunk| chunk.map(Bytes::from).map_err(OhttpClientError::from))
+                .collect::<Vec<_>>()
+                .await
+        } else {
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static(OHTTP_RESPONSE_CONTENT_TYPE),
+            );
+            let enc_response = server_response.encapsulate(&Self::bhttp_response(&response))?;
+            vec![Ok(Bytes::from(enc_response))]
+        };
+        if let Some(token) = &self.attestation_token {
+            let token = HeaderValue::from_str(token).map_err(|_| {
+                OhttpClientError::Attestation("the token is not a valid header value".to_string())
+            })?;
+            headers.insert(ATTESTATION_TOKEN_HEADER, token);
+        }
+
+        let status = if request.content_type == OHTTP_CHUNKED_REQUEST_CONTENT_TYPE {
+            response.status
+        } else {
+            StatusCode::OK
+        };
+        Ok(OuterResponse {
+            status,
+            headers,
+            body: Box::pin(stream::iter(body)),
+        })
+    }
+}
+
+impl OuterTransport for MockGateway {
+    fn send(&self, request: OuterRequest) -> BoxFuture<'_, Res<OuterResponse>> {
+        Box::pin(async move {
+            // Requests the gateway cannot decapsulate are rejected like a
+            // gateway would, with problem details
+            let response = self.respond(request).await.unwrap_or_else(|e| {
+                error!("{e}");
+                let mut headers = HeaderMap::new();
+                headers.insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static("application/problem+json"),
+                );
+                let problem = json!({
+                    "type": "about:blank",
+                    "title": "Bad Request",
+                    "status": StatusCode::BAD_REQUEST.as_u16(),
+                    "detail": e.to_string(),
+                });
+                OuterResponse {
+                    status: StatusCode::BAD_REQUEST,
+                    headers,
+                    body: Box::pin(stream::once(
+                        async move { Ok(Bytes::from(problem.to_string())) },
+                    )),
+                }
+            });
+            Ok(response)
+        })
+    }
+}
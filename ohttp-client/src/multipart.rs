@@ -0,0 +1,440 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::err::Res;
+use base64::{engine::general_purpose, Engine};
+use md5::Md5;
+use rand::distributions::{Alphanumeric, DistString};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Content type of parts whose type is neither given nor recognized.
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Digests of the content of an uploaded file part, sent with the part as
+/// `Content-Digest` (RFC 9530) and `Content-MD5` (RFC 1864) headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartDigest {
+    /// Name of the form field.
+    pub name: String,
+    pub file_name: Option<String>,
+    /// Size of the content.
+    pub size: u64,
+    pub sha256: [u8; 32],
+    pub md5: [u8; 16],
+}
+
+/// Computes the digests of a part as its content is read.
+struct PartHasher {
+    sha256: Sha256,
+    md5: Md5,
+    size: u64,
+}
+
+impl PartHasher {
+    fn new() -> PartHasher {
+        PartHasher {
+            sha256: Sha256::new(),
+            md5: Md5::new(),
+            size: 0,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+        self.md5.update(data);
+        self.size += data.len() as u64;
+    }
+
+    fn finish(self, part: &Part) -> PartDigest {
+        PartDigest {
+            name: part.name.clone(),
+            file_name: part.file_name.clone(),
+            size: self.size,
+            sha256: self.sha256.finalize().into(),
+            md5: self.md5.finalize().into(),
+        }
+    }
+}
+
+/// Where the content of a part comes from.
+#[derive(Debug, Clone)]
+enum PartContent {
+    Text(String),
+    Bytes(Vec<u8>),
+    /// A file read when the request is built.
+    File(PathBuf),
+}
+
+/// A part of a multipart/form-data body (RFC 7578).
+#[derive(Debug, Clone)]
+pub struct Part {
+    name: String,
+    file_name: Option<String>,
+    content_type: Option<String>,
+    content: PartContent,
+}
+
+impl Part {
+    /// A text field, sent without a content type.
+    pub fn text(name: &str, value: &str) -> Part {
+        Part {
+            name: name.to_string(),
+            file_name: None,
+            content_type: None,
+            content: PartContent::Text(value.to_string()),
+        }
+    }
+
+    /// A part with binary content. Its content type is inferred from the
+    /// content unless set.
+    pub fn bytes(name: &str, data: Vec<u8>) -> Part {
+        Part {
+            name: name.to_string(),
+            file_name: None,
+            content_type: None,
+            content: PartContent::Bytes(data),
+        }
+    }
+
+    /// A file part. The file name defaults to the last component of `path`
+    /// and the content type is inferred from the file content unless set.
+    pub fn file(name: &str, path: impl AsRef<Path>) -> Part {
+        let path = path.as_ref();
+        Part {
+            name: name.to_string(),
+            file_name: path
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().into_owned()),
+            content_type: None,
+            content: PartContent::File(path.to_path_buf()),
+        }
+    }
+
+    /// Sets the file name sent in the content disposition.
+    pub fn file_name(mut self, file_name: &str) -> Part {
+        self.file_name = Some(file_name.to_string());
+        self
+    }
+
+    /// Sets the content type of the part.
+    pub fn content_type(mut self, content_type: &str) -> Part {
+        self.content_type = Some(content_type.to_string());
+        self
+    }
+
+    /// Whether the content of the part is read from a file.
+    pub(crate) fn is_file(&self) -> bool {
+        matches!(self.content, PartContent::File(_))
+    }
+
+    /// The content of the part, reading files into memory.
+    fn read(&self) -> Res<Cow<'_, [u8]>> {
+        Ok(match &self.content {
+            PartContent::Text(text) => Cow::Borrowed(text.as_bytes()),
+            PartContent::Bytes(data) => Cow::Borrowed(data),
+            PartContent::File(path) => Cow::Owned(fs::read(path)?),
+        })
+    }
+
+    /// The content type to send, given the leading bytes of the content.
+    fn resolve_content_type(&self, head: &[u8]) -> Option<String> {
+        if let Some(content_type) = &self.content_type {
+            return Some(content_type.clone());
+        }
+        match self.content {
+            PartContent::Text(_) => None,
+            PartContent::Bytes(_) | PartContent::File(_) => Some(
+                infer::get(head)
+                    .map(|kind| kind.mime_type())
+                    .unwrap_or(DEFAULT_CONTENT_TYPE)
+                    .to_string(),
+            ),
+        }
+    }
+
+    /// Writes the boundary delimiter and the headers of the part, up to the
+    /// start of its content.
+    fn write_preamble(
+        &self,
+        buf: &mut Vec<u8>,
+        boundary: &str,
+        head: &[u8],
+        digest: Option<&PartDigest>,
+    ) -> Res<()> {
+        write!(
+            buf,
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"{}\"",
+            escape(&self.name)
+        )?;
+        if let Some(file_name) = &self.file_name {
+            write!(buf, "; filename=\"{}\"", escape(file_name))?;
+        }
+        write!(buf, "\r\n")?;
+        if let Some(content_type) = self.resolve_content_type(head) {
+            write!(buf, "Content-Type: {content_type}\r\n")?;
+        }
+        if let Some(digest) = digest {
+            let base64 = &general_purpose::STANDARD;
+            write!(
+                buf,
+                "Content-Digest: sha-256=:{}:\r\n",
+                base64.encode(digest.sha256)
+            )?;
+            write!(buf, "Content-MD5: {}\r\n", base64.encode(digest.md5))?;
+        }
+        write!(buf, "\r\n")?;
+        Ok(())
+    }
+}
+
+/// Escapes quotes and line breaks in a name or file name (HTML Living
+/// Standard, multipart/form-data encoding algorithm).
+fn escape(value: &str) -> String {
+    value
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Returns a random boundary of 32 alphanumeric characters.
+fn random_boundary() -> String {
+    let boundary_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    format!("----{boundary_string}")
+}
+
+/// Returns the content type of a body delimited by `boundary`.
+pub(crate) fn content_type(boundary: &str) -> String {
+    format!("multipart/form-data; boundary={boundary}")
+}
+
+/// Line break ending the content of every part.
+const PART_END: &[u8] = b"\r\n";
+
+/// Returns the delimiter that closes the body.
+fn close_delimiter(boundary: &str) -> String {
+    format!("--{boundary}--\r\n")
+}
+
+/// Whether `needle` occurs in `haystack`.
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+/// Encodes the parts as a complete multipart/form-data body, reading files
+/// into memory. Returns the boundary delimiting the parts, the body and the
+/// digests of the file parts if `digest_files` is set.
+pub(crate) fn encode(
+    parts: &[Part],
+    digest_files: bool,
+) -> Res<(String, Vec<u8>, Vec<PartDigest>)> {
+    encode_with(parts, digest_files, random_boundary)
+}
+
+/// Encodes the parts with a boundary from `draw`, which is drawn again and
+/// the parts encoded again if it occurs in the content of a part.
+fn encode_with(
+    parts: &[Part],
+    digest_files: bool,
+    mut draw: impl FnMut() -> String,
+) -> Res<(String, Vec<u8>, Vec<PartDigest>)> {
+    'draw: loop {
+        let boundary = draw();
+        let mut body = Vec::new();
+        let mut digests = Vec::new();
+        for part in parts {
+            let content = part.read()?;
+            if contains(&content, boundary.as_bytes()) {
+                continue 'draw;
+            }
+            let digest = (digest_files && part.is_file()).then(|| {
+                let mut hasher = PartHasher::new();
+                hasher.update(&content);
+                hasher.finish(part)
+            });
+            part.write_preamble(&mut body, &boundary, &content, digest.as_ref())?;
+            body.extend_from_slice(&content);
+            body.extend_from_slice(PART_END);
+            digests.extend(digest);
+        }
+        body.extend_from_slice(close_delimiter(&boundary).as_bytes());
+        return Ok((boundary, body, digests));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::stream;
+
+    /// Magic number of a PNG image, which the content type is inferred from.
+    const PNG_HEAD: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+    /// A part of an encoded body, as parsed back.
+    #[derive(Debug)]
+    struct Parsed {
+        name: Option<String>,
+        file_name: Option<String>,
+        content_type: Option<String>,
+        content_digest: Option<String>,
+        content_md5: Option<String>,
+        content: Bytes,
+    }
+
+    /// Parses a body with multer, which fails on malformed delimiters and
+    /// on a body that is not closed.
+    async fn parse(boundary: &str, body: Vec<u8>) -> Vec<Parsed> {
+        let body = stream::once(async move { Ok::<_, std::io::Error>(Bytes::from(body)) });
+        let mut multipart = multer::Multipart::new(body, boundary);
+        let mut parsed = Vec::new();
+        while let Some(field) = multipart.next_field().await.unwrap() {
+            let header = |name: &str| {
+                field
+                    .headers()
+                    .get(name)
+                    .map(|value| value.to_str().unwrap().to_string())
+            };
+            let content_digest = header("content-digest");
+            let content_md5 = header("content-md5");
+            parsed.push(Parsed {
+                name: field.name().map(str::to_string),
+                file_name: field.file_name().map(str::to_string),
+                content_type: field.content_type().map(ToString::to_string),
+                content_digest,
+                content_md5,
+                content: field.bytes().await.unwrap(),
+            });
+        }
+        parsed
+    }
+
+    /// Writes `content` to a file in the temporary directory.
+    fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("ohttp-multipart-{}-{name}", std::process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn escapes_quotes_and_line_breaks() {
+        assert_eq!(escape("a\"b\r\nc"), "a%22b%0D%0Ac");
+        let mut buf = Vec::new();
+        Part::text("say \"hi\"", "")
+            .file_name("x\ny.txt")
+            .write_preamble(&mut buf, "B", b"", None)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "--B\r\nContent-Disposition: form-data; name=\"say %22hi%22\"; \
+             filename=\"x%0Ay.txt\"\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn parses_escaped_names() {
+        let parts = [Part::text("a\"b\r\nc", "value")];
+        let (boundary, body, _) = encode(&parts, false).unwrap();
+        let parsed = parse(&boundary, body).await;
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].name.as_deref(), Some("a%22b%0D%0Ac"));
+        assert_eq!(parsed[0].content, "value");
+    }
+
+    #[test]
+    fn file_part_preamble() {
+        let mut buf = Vec::new();
+        Part::file("upload", "/images/photo.png")
+            .write_preamble(&mut buf, "B", PNG_HEAD, None)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "--B\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"photo.png\"\r\n\
+             Content-Type: image/png\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn digests_file_parts() {
+        let path = temp_file("digest.txt", b"hello world");
+        let parts = [Part::text("note", "hi"), Part::file("doc", &path)];
+        let (boundary, body, digests) = encode(&parts, true).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            digests,
+            [PartDigest {
+                name: "doc".to_string(),
+                file_name: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned()),
+                size: 11,
+                sha256: Sha256::digest(b"hello world").into(),
+                md5: Md5::digest(b"hello world").into(),
+            }]
+        );
+        let parsed = parse(&boundary, body).await;
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].content_digest, None);
+        assert_eq!(parsed[0].content_md5, None);
+        assert_eq!(
+            parsed[1].content_digest.as_deref(),
+            Some("sha-256=:uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=:")
+        );
+        assert_eq!(
+            parsed[1].content_md5.as_deref(),
+            Some("XrY7u+Ae7tCTyyK7j1rNww==")
+        );
+        assert_eq!(parsed[1].content, "hello world");
+    }
+
+    #[tokio::test]
+    async fn closes_the_body() {
+        let path = temp_file("song.mp3", b"not really audio");
+        let parts = [
+            Part::text("greeting", "hello\r\n--world"),
+            Part::bytes("image", PNG_HEAD.to_vec()),
+            Part::file("song", &path).content_type("audio/mpeg"),
+        ];
+        let (boundary, body, digests) = encode(&parts, false).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(digests.is_empty());
+        assert!(body.ends_with(format!("\r\n--{boundary}--\r\n").as_bytes()));
+        let parsed = parse(&boundary, body).await;
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[0].name.as_deref(), Some("greeting"));
+        assert_eq!(parsed[0].content_type, None);
+        assert_eq!(parsed[0].content, "hello\r\n--world");
+        assert_eq!(parsed[1].file_name, None);
+        assert_eq!(parsed[1].content_type.as_deref(), Some("image/png"));
+        assert_eq!(parsed[1].content, PNG_HEAD);
+        assert!(parsed[2]
+            .file_name
+            .as_deref()
+            .unwrap()
+            .ends_with("song.mp3"));
+        assert_eq!(parsed[2].content_type.as_deref(), Some("audio/mpeg"));
+        assert_eq!(parsed[2].content, "not really audio");
+    }
+
+    #[tokio::test]
+    async fn draws_another_boundary_on_collision() {
+        let parts = [Part::text("text", "contains --first--")];
+        let mut boundaries = ["first", "second"].into_iter();
+        let (boundary, body, _) =
+            encode_with(&parts, false, || boundaries.next().unwrap().to_string()).unwrap();
+        assert_eq!(boundary, "second");
+        let parsed = parse(&boundary, body).await;
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].content, "contains --first--");
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    err::Res,
+    request::InnerRequestBuilder,
+    response::{BodyStream, OhttpResponse},
+    OhttpClient,
+};
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt};
+use reqwest::header::HeaderMap;
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinHandle,
+};
+
+/// Sends many requests concurrently, e.g. for batch inference, with one
+/// client whose verified key configuration and relay connections are shared
+/// by all the requests. Each request is still encapsulated with a fresh
+/// `ClientRequest`. At most `concurrency` requests are in flight at a time:
+/// a request counts until its response body has been read or dropped, and
+/// the others wait for their turn.
+#[derive(Clone)]
+pub struct OhttpClientPool {
+    client: OhttpClient,
+    permits: Arc<Semaphore>,
+}
+
+impl OhttpClientPool {
+    pub fn new(client: OhttpClient, concurrency: usize) -> OhttpClientPool {
+        OhttpClientPool {
+            client,
+            permits: Arc::new(Semaphore::new(concurrency.max(1))),
+        }
+    }
+
+    /// The client sending the requests of the pool.
+    pub fn client(&self) -> &OhttpClient {
+        &self.client
+    }
+
+    /// Number of requests that can be sent before the next one waits.
+    pub fn available(&self) -> usize {
+        self.permits.available_permits()
+    }
+
+    /// Sends the request in a task of its own, once fewer than
+    /// `concurrency` requests are in flight, and returns the handle of the
+    /// task. The body of the response is streamed like that of `post`.
+    pub fn spawn_post(
+        &self,
+        url: &str,
+        request: InnerRequestBuilder,
+        outer_headers: HeaderMap,
+    ) -> JoinHandle<Res<OhttpResponse>> {
+        let client = self.client.clone();
+        let permits = self.permits.clone();
+        let url = url.to_string();
+        tokio::spawn(async move {
+            // The semaphore is never closed
+            let permit = permits.acquire_owned().await.expect("open semaphore");
+            let response = client.post(&url, &request, &outer_headers).await?;
+            Ok(response.map_body(move |body| {
+                Box::pin(PermitBody {
+                    body,
+                    permit: Some(permit),
+                })
+            }))
+        })
+    }
+}
+
+/// A response body that holds the permit of its request until it has been
+/// read to its end, has failed or is dropped.
+struct PermitBody {
+    body: BodyStream,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Stream for PermitBody {
+    type Item = Res<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Res<Bytes>>> {
+        let next = ready!(self.body.poll_next_unpin(cx));
+        match &next {
+            Some(Ok(_)) => {}
+            Some(Err(_)) => self.permit = None,
+            None => {
+                // The body may not be polled past its end
+                self.body = Box::pin(stream::empty());
+                self.permit = None;
+            }
+        }
+        Poll::Ready(next)
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::err::{OhttpClientError, Res};
+use reqwest::header::HeaderMap;
+use std::str::FromStr;
+use tracing::warn;
+
+/// Outer headers that identify the user or carry credentials for the
+/// target, and so usually belong in the inner request.
+const DENIED_OUTER_HEADERS: [&str; 8] = [
+    "authorization",
+    "cookie",
+    "forwarded",
+    "origin",
+    "referer",
+    "x-client-ip",
+    "x-forwarded-for",
+    "x-real-ip",
+];
+
+/// What happens to a request with an outer header that looks like it belongs
+/// in the inner request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OuterHeaderAction {
+    /// Sends the request without checking its outer headers.
+    Allow,
+    /// Logs a warning and sends the request.
+    #[default]
+    Warn,
+    /// Fails the request with `InvalidRequest`, without sending it.
+    Reject,
+}
+
+impl FromStr for OuterHeaderAction {
+    type Err = OhttpClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(OuterHeaderAction::Allow),
+            "warn" => Ok(OuterHeaderAction::Warn),
+            "reject" => Ok(OuterHeaderAction::Reject),
+            _ => Err(OhttpClientError::Config(format!(
+                "unknown outer header action {s}, expected allow, warn or reject"
+            ))),
+        }
+    }
+}
+
+/// Checks the outer headers given by the caller, which the relay can read,
+/// unlike the inner request which only the gateway can decapsulate. Headers
+/// in `deny` and not in `allow`, e.g. cookies, are handled with `action`.
+/// Header names are compared case-insensitively.
+#[derive(Debug, Clone)]
+pub struct OuterHeaderFilter {
+    pub action: OuterHeaderAction,
+    pub deny: Vec<String>,
+    pub allow: Vec<String>,
+}
+
+impl Default for OuterHeaderFilter {
+    fn default() -> Self {
+        OuterHeaderFilter {
+            action: OuterHeaderAction::default(),
+            deny: DENIED_OUTER_HEADERS.map(String::from).to_vec(),
+            allow: Vec::new(),
+        }
+    }
+}
+
+impl OuterHeaderFilter {
+    pub(crate) fn check(&self, headers: &HeaderMap) -> Res<()> {
+        if self.action == OuterHeaderAction::Allow {
+            return Ok(());
+        }
+        let listed = |list: &[String], name: &str| {
+            list.iter().any(|listed| listed.eq_ignore_ascii_case(name))
+        };
+        for name in headers.keys() {
+            if !listed(&self.deny, name.as_str()) || listed(&self.allow, name.as_str()) {
+                continue;
+            }
+            let message = format!(
+                "outer header {name} is visible to the relay and may identify the client; \
+                 send it in the inner request or allow it explicitly"
+            );
+            match self.action {
+                OuterHeaderAction::Reject => return Err(OhttpClientError::InvalidRequest(message)),
+                _ => warn!("{message}"),
+            }
+        }
+        Ok(())
+    }
+}
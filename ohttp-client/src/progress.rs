@@ -0,0 +1,40 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use bytes::Bytes;
+use futures::{stream, StreamExt};
+use std::sync::Arc;
+
+/// Called with the number of bytes transferred so far and the total number of
+/// bytes, if known.
+pub type ProgressCallback = Arc<dyn Fn(u64, Option<u64>) + Send + Sync>;
+
+/// Size of the chunks in which an encapsulated request is handed to the
+/// connection when upload progress is reported.
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Callbacks reporting the progress of requests and responses.
+#[derive(Clone, Default)]
+pub(crate) struct Progress {
+    pub(crate) upload: Option<ProgressCallback>,
+    pub(crate) download: Option<ProgressCallback>,
+}
+
+/// Turns an encapsulated request into a body that reports the bytes handed
+/// to the connection as it is sent.
+pub(crate) fn upload_body(body: Vec<u8>, progress: ProgressCallback) -> reqwest::Body {
+    let total = body.len() as u64;
+    let body = Bytes::from(body);
+    let chunks: Vec<Bytes> = (0..body.len())
+        .step_by(UPLOAD_CHUNK_SIZE)
+        .map(|start| body.slice(start..(start + UPLOAD_CHUNK_SIZE).min(body.len())))
+        .collect();
+
+    let mut sent = 0;
+    let stream = stream::iter(chunks).map(move |chunk| {
+        sent += chunk.len() as u64;
+        progress(sent, Some(total));
+        Ok::<_, std::io::Error>(chunk)
+    });
+    reqwest::Body::wrap_stream(stream)
+}
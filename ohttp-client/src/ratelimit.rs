@@ -0,0 +1,76 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+use tracing::trace;
+
+/// Client-side limit on the rate of requests, so that the rate limits of
+/// the gateway are not hit with requests that were encapsulated for nothing.
+/// Requests are let through at `requests_per_second` on average, with bursts
+/// of up to `burst` requests, and otherwise wait for their turn. Every
+/// attempt counts, including retries and hedged requests.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    pub requests_per_second: f64,
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            requests_per_second: 10.0,
+            burst: 10,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A token bucket shared by the clones of a client.
+#[derive(Clone)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> RateLimiter {
+        let bucket = Bucket {
+            tokens: f64::from(limit.burst),
+            updated: Instant::now(),
+        };
+        RateLimiter {
+            limit,
+            bucket: Arc::new(Mutex::new(bucket)),
+        }
+    }
+
+    /// Waits until a request may be sent.
+    pub(crate) async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                let earned = (now - bucket.updated).as_secs_f64() * self.limit.requests_per_second;
+                bucket.tokens = (bucket.tokens + earned).min(f64::from(self.limit.burst));
+                bucket.updated = now;
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                (1.0 - bucket.tokens) / self.limit.requests_per_second
+            };
+            trace!("Rate limited, waiting {wait:.3}s");
+            // A rate too small for the wait to fit in a duration waits for ever
+            tokio::time::sleep(Duration::try_from_secs_f64(wait).unwrap_or(Duration::MAX)).await;
+        }
+    }
+}
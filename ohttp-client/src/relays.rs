@@ -0,0 +1,101 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{err::OhttpClientError, retry::RelayRetryPolicy};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Relays that requests fail over to when the relay of a request is
+/// unreachable or fails with a server error. A relay that failed is skipped
+/// for `cooldown`, unless all the relays failed, so that a dead relay does
+/// not delay every request.
+#[derive(Debug, Clone)]
+pub struct RelayFailoverPolicy {
+    /// URLs of the other relays, tried in order after the URL of the request.
+    pub fallbacks: Vec<String>,
+    /// Spreads the requests over the relays in turn, rather than sending
+    /// them to the first healthy relay.
+    pub round_robin: bool,
+    /// Time during which a relay that failed is skipped.
+    pub cooldown: Duration,
+}
+
+impl Default for RelayFailoverPolicy {
+    fn default() -> Self {
+        RelayFailoverPolicy {
+            fallbacks: Vec::new(),
+            round_robin: false,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Whether the relay failed, rather than the gateway or the request.
+pub(crate) fn relay_failed(e: &OhttpClientError) -> bool {
+    match e {
+        OhttpClientError::RelayHttp { status, .. } => status.is_server_error(),
+        OhttpClientError::GatewayProblem { .. } => false,
+        e => RelayRetryPolicy::is_transient(e),
+    }
+}
+
+/// The failover policy with the health of the relays, shared by the clones
+/// of a client.
+#[derive(Clone)]
+pub(crate) struct Relays {
+    policy: RelayFailoverPolicy,
+    /// When each relay that is skipped last failed.
+    failed: Arc<Mutex<HashMap<String, Instant>>>,
+    next: Arc<AtomicUsize>,
+}
+
+impl Relays {
+    pub(crate) fn new(policy: RelayFailoverPolicy) -> Relays {
+        Relays {
+            policy,
+            failed: Arc::default(),
+            next: Arc::default(),
+        }
+    }
+
+    /// The relays to try for a request to `url`, healthy relays first, and
+    /// then the relays that failed, least recently failed first.
+    pub(crate) fn candidates(&self, url: &str) -> Vec<String> {
+        let mut relays = vec![url.to_string()];
+        for fallback in &self.policy.fallbacks {
+            if !relays.contains(fallback) {
+                relays.push(fallback.clone());
+            }
+        }
+        if self.policy.round_robin {
+            let start = self.next.fetch_add(1, Ordering::Relaxed) % relays.len();
+            relays.rotate_left(start);
+        }
+
+        let mut failed = self.failed.lock().unwrap();
+        failed.retain(|_, at| at.elapsed() < self.policy.cooldown);
+        let (mut healthy, mut unhealthy): (Vec<_>, Vec<_>) = relays
+            .into_iter()
+            .partition(|relay| !failed.contains_key(relay));
+        unhealthy.sort_by_key(|relay| failed[relay]);
+        healthy.append(&mut unhealthy);
+        healthy
+    }
+
+    pub(crate) fn failed(&self, url: &str) {
+        self.failed
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), Instant::now());
+    }
+
+    pub(crate) fn succeeded(&self, url: &str) {
+        self.failed.lock().unwrap().remove(url);
+    }
+}
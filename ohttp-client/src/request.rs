@@ -0,0 +1,494 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    auth::{InnerAuth, InnerAuthRequest},
+    err::{OhttpClientError, Res},
+    multipart::{self, Part, PartDigest},
+};
+use bytes::Bytes;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE},
+    Method,
+};
+use std::{
+    fmt,
+    io::{Read, Write},
+    path::Path,
+    sync::Arc,
+};
+use tracing::{error, info};
+use url::form_urlencoded;
+
+/// Characters that are percent-encoded in a target path. Other ASCII
+/// characters, including `?`, `&` and `=`, are sent as given.
+const TARGET_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'<')
+    .add(b'>')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}')
+    .add(b'%');
+
+/// Source of a raw request body.
+#[derive(Debug, Clone)]
+enum Body {
+    /// Shared rather than copied when the builder is cloned.
+    Bytes(Bytes),
+    /// Read from standard input when the request is built.
+    Stdin,
+}
+
+/// The authentication hook of a request, shared when the builder is cloned.
+#[derive(Clone)]
+struct AuthHook(Arc<dyn InnerAuth>);
+
+impl fmt::Debug for AuthHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("InnerAuth")
+    }
+}
+
+/// An inner request built and encapsulated without being sent, for
+/// inspecting the message a request would carry.
+#[derive(Debug, Clone)]
+pub struct BuiltRequest {
+    /// The inner request as an HTTP/1.1 message.
+    pub http: Vec<u8>,
+    /// The inner request as a known-length bhttp message.
+    pub bhttp: Vec<u8>,
+    /// ID of the key the request was encapsulated with.
+    pub key_id: u8,
+    /// Size of the encapsulated request.
+    pub encapsulated_size: usize,
+}
+
+/// Builds the inner HTTP request that is encapsulated and sent to the gateway.
+///
+/// The request defaults to `POST /` with no headers and an empty body.
+#[derive(Debug, Clone)]
+pub struct InnerRequestBuilder {
+    method: Method,
+    path: String,
+    query: Vec<(String, String)>,
+    headers: HeaderMap,
+    body: Option<Body>,
+    parts: Vec<Part>,
+    digest_files: bool,
+    auth: Option<AuthHook>,
+    /// The first invalid header added, reported when the request is built.
+    invalid_header: Option<String>,
+}
+
+impl Default for InnerRequestBuilder {
+    fn default() -> Self {
+        InnerRequestBuilder::new()
+    }
+}
+
+impl InnerRequestBuilder {
+    pub fn new() -> InnerRequestBuilder {
+        InnerRequestBuilder {
+            method: Method::POST,
+            path: "/".to_string(),
+            query: Vec::new(),
+            headers: HeaderMap::new(),
+            body: None,
+            parts: Vec::new(),
+            digest_files: false,
+            auth: None,
+            invalid_header: None,
+        }
+    }
+
+    pub fn method(mut self, method: Method) -> InnerRequestBuilder {
+        self.method = method;
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> InnerRequestBuilder {
+        self.path = path.to_string();
+        self
+    }
+
+    /// Adds a header to the inner request. Repeated names are sent repeatedly.
+    /// An invalid name or value makes building the request fail.
+    pub fn header(mut self, name: &str, value: &str) -> InnerRequestBuilder {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(value)) => {
+                self.headers.append(name, value);
+            }
+            _ => {
+                self.invalid_header
+                    .get_or_insert_with(|| format!("invalid header {name}: {value}"));
+            }
+        }
+        self
+    }
+
+    /// Adds headers to the inner request, keeping any already added.
+    pub fn headers(mut self, headers: &HeaderMap) -> InnerRequestBuilder {
+        for (name, value) in headers {
+            self.headers.append(name, value.clone());
+        }
+        self
+    }
+
+    fn check_headers(&self) -> Res<()> {
+        match &self.invalid_header {
+            Some(e) => Err(OhttpClientError::InvalidRequest(e.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds a query parameter, percent-encoded and appended to the path.
+    pub fn query(mut self, key: &str, value: &str) -> InnerRequestBuilder {
+        self.query.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets a raw body. A body cannot be combined with form fields or file parts.
+    pub fn body(mut self, body: Vec<u8>) -> InnerRequestBuilder {
+        self.body = Some(Body::Bytes(Bytes::from(body)));
+        self
+    }
+
+    /// Sets a raw body of the given content type, e.g. `application/cbor`.
+    pub fn typed_body(self, body: Vec<u8>, content_type: &str) -> InnerRequestBuilder {
+        self.content_type(content_type).body(body)
+    }
+
+    /// Reads a raw body of the given content type from standard input when
+    /// the request is built.
+    pub fn stdin_body(mut self, content_type: &str) -> InnerRequestBuilder {
+        self.body = Some(Body::Stdin);
+        self.content_type(content_type)
+    }
+
+    fn content_type(mut self, content_type: &str) -> InnerRequestBuilder {
+        match HeaderValue::from_str(content_type) {
+            Ok(value) => {
+                self.headers.insert(CONTENT_TYPE, value);
+            }
+            Err(_) => {
+                self.invalid_header
+                    .get_or_insert_with(|| format!("invalid content type {content_type}"));
+            }
+        }
+        self
+    }
+
+    /// Sets an already serialized JSON body, dropping any form parts.
+    pub(crate) fn json_body(mut self, body: Vec<u8>) -> InnerRequestBuilder {
+        self.parts.clear();
+        self.typed_body(body, "application/json")
+    }
+
+    /// Adds a text field to a multipart/form-data body.
+    pub fn form_field(self, name: &str, value: &str) -> InnerRequestBuilder {
+        self.part(Part::text(name, value))
+    }
+
+    /// Adds a file part to a multipart/form-data body.
+    pub fn file_part(self, name: &str, path: impl AsRef<Path>) -> InnerRequestBuilder {
+        self.part(Part::file(name, path))
+    }
+
+    /// Adds a part to a multipart/form-data body.
+    pub fn part(mut self, part: Part) -> InnerRequestBuilder {
+        self.parts.push(part);
+        self
+    }
+
+    /// Computes the SHA-256 and MD5 digests of file parts as the request is
+    /// built, and sends them with each part as `Content-Digest` and
+    /// `Content-MD5` headers. The digests are also reported in the
+    /// `RequestStats` of the response.
+    pub fn digest_files(mut self, enabled: bool) -> InnerRequestBuilder {
+        self.digest_files = enabled;
+        self
+    }
+
+    /// Authenticates the request with the headers returned by `auth`, which
+    /// is invoked whenever the request is built.
+    pub fn auth<A: InnerAuth + 'static>(mut self, auth: A) -> InnerRequestBuilder {
+        self.auth = Some(AuthHook(Arc::new(auth)));
+        self
+    }
+
+    /// Adds the headers of the authentication hook, if any.
+    fn authenticate(&self, headers: &mut HeaderMap, body: Option<&[u8]>) -> Res<()> {
+        let Some(AuthHook(auth)) = &self.auth else {
+            return Ok(());
+        };
+        let target = self.target()?;
+        let request = InnerAuthRequest {
+            method: &self.method,
+            target: &target,
+            headers,
+            body,
+        };
+        let auth_headers = match auth.headers(&request) {
+            Ok(auth_headers) => auth_headers,
+            Err(e) => {
+                error!("{e}");
+                return Err(e);
+            }
+        };
+        headers.extend(auth_headers);
+        Ok(())
+    }
+
+    /// Returns the percent-encoded target path including the query string.
+    fn target(&self) -> Res<String> {
+        let path = encode_target_path(&self.path)?;
+        if self.query.is_empty() {
+            return Ok(path);
+        }
+        let query = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(&self.query)
+            .finish();
+        let separator = if path.contains('?') { '&' } else { '?' };
+        Ok(format!("{path}{separator}{query}"))
+    }
+
+    /// The headers and content of the request, with the content type of a
+    /// multipart body and the content length added to the headers, and the
+    /// digests of the file parts. Requests without a body or form fields
+    /// have no content.
+    fn content(&self) -> Res<(HeaderMap, Option<Bytes>, Vec<PartDigest>)> {
+        self.check_headers()?;
+        let mut headers = self.headers.clone();
+        let mut digests = Vec::new();
+        let content = match (&self.body, self.parts.is_empty()) {
+            (Some(_), false) => {
+                return Err(OhttpClientError::InvalidRequest(
+                    "a request body cannot be combined with form fields".to_string(),
+                ))
+            }
+            (Some(Body::Bytes(body)), true) => Some(body.clone()),
+            (Some(Body::Stdin), true) => {
+                let mut buf = Vec::new();
+                std::io::stdin().read_to_end(&mut buf)?;
+                Some(Bytes::from(buf))
+            }
+            (None, true) => None,
+            (None, false) => {
+                let (boundary, body, part_digests) =
+                    multipart::encode(&self.parts, self.digest_files)?;
+                digests = part_digests;
+                headers.insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_str(&multipart::content_type(&boundary))
+                        .map_err(|e| OhttpClientError::InvalidRequest(e.to_string()))?,
+                );
+                Some(Bytes::from(body))
+            }
+        };
+        if let Some(content) = &content {
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(content.len()));
+        }
+        for (name, value) in &headers {
+            info!("{name}: {value:?}");
+        }
+        // The credentials are added after logging the headers
+        self.authenticate(&mut headers, content.as_deref())?;
+        log_digests(&digests);
+        Ok((headers, content, digests))
+    }
+
+    /// Encodes the request as an HTTP/1.1 message.
+    fn encode_http(&self, headers: &HeaderMap, content: Option<&[u8]>) -> Res<Vec<u8>> {
+        let content = content.unwrap_or_default();
+        let mut request = Vec::with_capacity(content.len() + 1024);
+        write_request_line(&mut request, &self.method, &self.target()?)?;
+        append_headers(&mut request, headers)?;
+        write!(request, "\r\n")?;
+        request.extend_from_slice(content);
+        Ok(request)
+    }
+
+    /// Encodes the request as a known-length bhttp message (RFC 9292,
+    /// Section 3.1), with the content copied once, straight into the message.
+    fn encode_bhttp(&self, headers: &HeaderMap, content: Option<&[u8]>) -> Res<Vec<u8>> {
+        let content = content.unwrap_or_default();
+        let target = self.target()?;
+        let mut fields = Vec::new();
+        for (name, value) in headers {
+            write_vec(&mut fields, name.as_str().as_bytes());
+            write_vec(&mut fields, value.as_bytes());
+        }
+
+        let mut request_buf = Vec::with_capacity(content.len() + fields.len() + target.len() + 64);
+        write_varint(&mut request_buf, 0);
+        write_vec(&mut request_buf, self.method.as_str().as_bytes());
+        write_vec(&mut request_buf, b"https");
+        write_vec(&mut request_buf, b"");
+        write_vec(&mut request_buf, target.as_bytes());
+        write_vec(&mut request_buf, &fields);
+        write_vec(&mut request_buf, content);
+        write_varint(&mut request_buf, 0);
+        Ok(request_buf)
+    }
+
+    /// Creates the inner request as an HTTP/1.1 message and as the bhttp
+    /// message it is encoded to.
+    pub(crate) fn build_messages(&self) -> Res<(Vec<u8>, Vec<u8>)> {
+        let (headers, content, _) = self.content()?;
+        Ok((
+            self.encode_http(&headers, content.as_deref())?,
+            self.encode_bhttp(&headers, content.as_deref())?,
+        ))
+    }
+
+    /// Creates the inner request as a bhttp message, without going through
+    /// an HTTP/1.1 message.
+    pub fn build(&self) -> Res<Vec<u8>> {
+        self.build_digested().map(|(request, _)| request)
+    }
+
+    /// Like `build`, also returning the digests of the file parts.
+    pub(crate) fn build_digested(&self) -> Res<(Vec<u8>, Vec<PartDigest>)> {
+        let (headers, content, digests) = self.content()?;
+        Ok((self.encode_bhttp(&headers, content.as_deref())?, digests))
+    }
+
+    /// Like `build_digested`, reading files and standard input on the
+    /// blocking thread pool rather than on the async runtime.
+    pub(crate) async fn build_digested_blocking(&self) -> Res<(Vec<u8>, Vec<PartDigest>)> {
+        let reads_blocking =
+            matches!(self.body, Some(Body::Stdin)) || self.parts.iter().any(Part::is_file);
+        if !reads_blocking {
+            return self.build_digested();
+        }
+        let request = self.clone();
+        tokio::task::spawn_blocking(move || request.build_digested())
+            .await
+            .map_err(|e| OhttpClientError::Io(std::io::Error::other(e)))?
+    }
+}
+
+/// Logs the digests of the uploaded file parts.
+fn log_digests(digests: &[PartDigest]) {
+    for digest in digests {
+        info!(
+            "Part {:?}: {} bytes, sha256 {}, md5 {}",
+            digest.name,
+            digest.size,
+            hex::encode(digest.sha256),
+            hex::encode(digest.md5)
+        );
+    }
+}
+
+/// Percent-encodes the characters of a target path that are not allowed in
+/// a request target, such as spaces and non-ASCII characters. Valid
+/// percent-encoded sequences are kept as they are.
+fn encode_target_path(path: &str) -> Res<String> {
+    if !path.starts_with('/') {
+        return Err(OhttpClientError::InvalidRequest(format!(
+            "target path {path:?} must start with /"
+        )));
+    }
+
+    let mut encoded = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(i) = rest.find('%') {
+        encoded.extend(utf8_percent_encode(&rest[..i], TARGET_ENCODE_SET));
+        rest = &rest[i + 1..];
+        let escaped = rest.len() >= 2 && rest.as_bytes()[..2].iter().all(u8::is_ascii_hexdigit);
+        encoded.push_str(if escaped { "%" } else { "%25" });
+    }
+    encoded.extend(utf8_percent_encode(rest, TARGET_ENCODE_SET));
+    Ok(encoded)
+}
+
+/// Writes the request line for an HTTP request to the provided buffer.
+/// The request line follows the format:
+/// `{method} {target_path} HTTP/1.1\r\n`.
+fn write_request_line(request: &mut Vec<u8>, method: &Method, target_path: &str) -> Res<()> {
+    write!(request, "{method} {target_path} HTTP/1.1\r\n")?;
+    Ok(())
+}
+
+/// Appends HTTP headers to the provided request buffer.
+fn append_headers(request: &mut Vec<u8>, headers: &HeaderMap) -> Res<()> {
+    for (name, value) in headers {
+        write!(request, "{name}: ")?;
+        request.extend_from_slice(value.as_bytes());
+        write!(request, "\r\n")?;
+    }
+    Ok(())
+}
+
+/// Appends a variable-length integer (RFC 9000, Section 16) to the buffer.
+pub(crate) fn write_varint(buf: &mut Vec<u8>, v: usize) {
+    let v = v as u64;
+    if v < 1 << 6 {
+        buf.push(v as u8);
+    } else if v < 1 << 14 {
+        buf.extend_from_slice(&(v as u16 | 0x4000).to_be_bytes());
+    } else if v < 1 << 30 {
+        buf.extend_from_slice(&(v as u32 | 0x8000_0000).to_be_bytes());
+    } else {
+        buf.extend_from_slice(&(v | 0xc000_0000_0000_0000).to_be_bytes());
+    }
+}
+
+/// Reads a variable-length integer from the start of the buffer and returns
+/// it with the rest of the buffer, or `None` if the buffer is too short.
+pub(crate) fn read_varint(buf: &[u8]) -> Option<(u64, &[u8])> {
+    let first = *buf.first()?;
+    let len = 1 << (first >> 6);
+    let bytes = buf.get(1..len)?;
+    let v = bytes
+        .iter()
+        .fold(u64::from(first & 0x3f), |v, b| (v << 8) | u64::from(*b));
+    Some((v, &buf[len..]))
+}
+
+/// Methods whose requests have the same effect when sent several times as
+/// when sent once (RFC 9110, Section 9.2.2).
+pub(crate) const IDEMPOTENT_METHODS: [Method; 6] = [
+    Method::GET,
+    Method::HEAD,
+    Method::OPTIONS,
+    Method::TRACE,
+    Method::PUT,
+    Method::DELETE,
+];
+
+/// The method of a bhttp request, which follows the framing indicator of
+/// both known and indeterminate-length requests (RFC 9292, Section 3).
+pub(crate) fn bhttp_method(bhttp_request: &[u8]) -> Option<&[u8]> {
+    let (_, rest) = read_varint(bhttp_request)?;
+    let (len, rest) = read_varint(rest)?;
+    rest.get(..usize::try_from(len).ok()?)
+}
+
+/// Whether the method of the bhttp request is idempotent.
+pub(crate) fn is_idempotent(bhttp_request: &[u8]) -> bool {
+    bhttp_method(bhttp_request).is_some_and(|method| {
+        IDEMPOTENT_METHODS
+            .iter()
+            .any(|idempotent| idempotent.as_str().as_bytes() == method)
+    })
+}
+
+/// Appends a length-prefixed byte string to the buffer.
+pub(crate) fn write_vec(buf: &mut Vec<u8>, data: &[u8]) {
+    write_varint(buf, data.len());
+    buf.extend_from_slice(data);
+}
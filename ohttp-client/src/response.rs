@@ -0,0 +1,325 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    attestation::AttestationClaims,
+    err::{OhttpClientError, Res},
+    multipart::PartDigest,
+    progress::ProgressCallback,
+    sse::SseStream,
+};
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt};
+use futures_util::stream::unfold;
+use reqwest::{header::HeaderMap, StatusCode};
+use serde::de::DeserializeOwned;
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time::{timeout, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// A stream of decapsulated response body chunks.
+pub type BodyStream = Pin<Box<dyn Stream<Item = Res<Bytes>> + Send>>;
+
+/// Sizes and timings of an oblivious request, measured from the moment the
+/// encapsulated request was handed to the relay. Connecting to the relay,
+/// including DNS resolution and the TLS handshake, is part of the time to the
+/// response headers unless a pooled connection was reused; the time between
+/// the response headers and the first byte is spent by the gateway and the
+/// backend producing the body.
+///
+/// DNS resolution, connecting and the TLS handshake are not timed on their
+/// own, since reqwest 0.11 offers no hook into its connector to observe them.
+#[derive(Debug, Clone, Default)]
+pub struct RequestStats {
+    /// Size of the encapsulated request.
+    pub request_size: u64,
+    /// Size of the encapsulated response body received so far.
+    pub response_size: u64,
+    /// Time until the relay returned the response headers.
+    pub response_headers: Duration,
+    /// Time until the first decapsulated body chunk, once read.
+    pub first_byte: Option<Duration>,
+    /// Time until the body was read completely, once read.
+    pub total: Option<Duration>,
+    /// Digests of the uploaded file parts, if enabled with
+    /// `InnerRequestBuilder::digest_files`.
+    pub uploads: Vec<PartDigest>,
+}
+
+/// The decapsulated response to an oblivious request, together with the
+/// attestation token of the gateway that produced it. The body is
+/// decapsulated as it is read.
+pub struct OhttpResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: BodyStream,
+    attestation: Option<AttestationClaims>,
+    idle_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    progress: Option<ProgressCallback>,
+    received: u64,
+    sent: Instant,
+    stats: RequestStats,
+    encapsulated: Arc<AtomicU64>,
+    cancellation: Option<CancellationToken>,
+    correlation_id: Option<String>,
+    relay: Option<String>,
+}
+
+impl OhttpResponse {
+    pub(crate) fn new(
+        status: StatusCode,
+        headers: HeaderMap,
+        body: BodyStream,
+        attestation: Option<AttestationClaims>,
+    ) -> OhttpResponse {
+        OhttpResponse {
+            status,
+            headers,
+            body,
+            attestation,
+            idle_timeout: None,
+            deadline: None,
+            progress: None,
+            received: 0,
+            sent: Instant::now(),
+            stats: RequestStats::default(),
+            encapsulated: Arc::default(),
+            cancellation: None,
+            correlation_id: None,
+            relay: None,
+        }
+    }
+
+    /// Measures the request from `sent`, when the encapsulated request of
+    /// `request_size` bytes was posted. `encapsulated` counts the bytes of
+    /// the encapsulated response as they are decapsulated.
+    pub(crate) fn with_stats(
+        mut self,
+        sent: Instant,
+        response_headers: Duration,
+        request_size: u64,
+        encapsulated: Arc<AtomicU64>,
+    ) -> OhttpResponse {
+        self.sent = sent;
+        self.stats.request_size = request_size;
+        self.stats.response_headers = response_headers;
+        self.encapsulated = encapsulated;
+        self
+    }
+
+    pub(crate) fn with_correlation_id(mut self, correlation_id: String) -> OhttpResponse {
+        self.correlation_id = Some(correlation_id);
+        self
+    }
+
+    pub(crate) fn with_relay(mut self, relay: &str) -> OhttpResponse {
+        self.relay = Some(relay.to_string());
+        self
+    }
+
+    pub(crate) fn with_uploads(mut self, uploads: Vec<PartDigest>) -> OhttpResponse {
+        self.stats.uploads = uploads;
+        self
+    }
+
+    /// Bounds the time to wait for each chunk and for the whole body.
+    pub(crate) fn with_timeouts(
+        mut self,
+        idle_timeout: Option<Duration>,
+        deadline: Option<Instant>,
+    ) -> OhttpResponse {
+        self.idle_timeout = idle_timeout;
+        self.deadline = deadline;
+        self
+    }
+
+    /// Reports the body bytes read so far to `progress`.
+    pub(crate) fn with_progress(mut self, progress: Option<ProgressCallback>) -> OhttpResponse {
+        self.progress = progress;
+        self
+    }
+
+    /// Stops reading the body once `cancellation` is cancelled.
+    pub(crate) fn with_cancellation(
+        mut self,
+        cancellation: Option<CancellationToken>,
+    ) -> OhttpResponse {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Replaces the body with `f` applied to it.
+    pub(crate) fn map_body<F>(mut self, f: F) -> OhttpResponse
+    where
+        F: FnOnce(BodyStream) -> BodyStream,
+    {
+        let body = std::mem::replace(&mut self.body, Box::pin(stream::empty()));
+        self.body = f(body);
+        self
+    }
+
+    /// Time to wait for the next chunk, if bounded.
+    fn chunk_timeout(&self) -> Option<Duration> {
+        let remaining = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        match (self.idle_timeout, remaining) {
+            (Some(idle), Some(remaining)) => Some(idle.min(remaining)),
+            (idle, remaining) => idle.or(remaining),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub(crate) fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
+    /// The attestation token returned by the gateway and its claims, if any.
+    pub fn attestation(&self) -> Option<&AttestationClaims> {
+        self.attestation.as_ref()
+    }
+
+    /// The correlation ID sent to the relay with the request.
+    pub fn correlation_id(&self) -> Option<&str> {
+        self.correlation_id.as_deref()
+    }
+
+    /// URL of the relay that served the request, which differs from the URL
+    /// of the request if it failed over to another relay.
+    pub fn relay(&self) -> Option<&str> {
+        self.relay.as_deref()
+    }
+
+    /// Sizes and timings of the request, so far.
+    pub fn stats(&self) -> RequestStats {
+        RequestStats {
+            response_size: self.encapsulated.load(Ordering::Relaxed),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Waits for the next item of the body, unless the request is cancelled.
+    async fn next_chunk(&mut self) -> Res<Option<Res<Bytes>>> {
+        let Some(cancellation) = self.cancellation.clone() else {
+            return Ok(self.body.next().await);
+        };
+        let next = tokio::select! {
+            biased;
+            _ = cancellation.cancelled() => None,
+            next = self.body.next() => Some(next),
+        };
+        match next {
+            Some(next) => Ok(next),
+            None => {
+                // Drop the body to tear down the connection to the relay
+                self.body = Box::pin(stream::empty());
+                Err(OhttpClientError::Cancelled)
+            }
+        }
+    }
+
+    /// Returns the next decapsulated chunk of the body, or `None` at the end.
+    /// Fails with `Cancelled` once the request is cancelled.
+    pub async fn chunk(&mut self) -> Res<Option<Bytes>> {
+        let next = match self.chunk_timeout() {
+            Some(limit) => match timeout(limit, self.next_chunk()).await {
+                Ok(next) => next?,
+                Err(_) => {
+                    return Err(OhttpClientError::Timeout(
+                        "waiting for the response body".to_string(),
+                    ))
+                }
+            },
+            None => self.next_chunk().await?,
+        };
+        let chunk = next.transpose()?;
+
+        match &chunk {
+            Some(_) if self.stats.first_byte.is_none() => {
+                self.stats.first_byte = Some(self.sent.elapsed());
+            }
+            None if self.stats.total.is_none() => {
+                self.stats.total = Some(self.sent.elapsed());
+                #[cfg(feature = "metrics")]
+                crate::telemetry::record_response(&self.stats());
+            }
+            _ => {}
+        }
+
+        if let Some(progress) = &self.progress {
+            match &chunk {
+                Some(chunk) => {
+                    self.received += chunk.len() as u64;
+                    progress(self.received, None);
+                }
+                None => progress(self.received, Some(self.received)),
+            }
+        }
+        Ok(chunk)
+    }
+
+    /// Reads the remainder of the body.
+    pub async fn bytes(mut self) -> Res<Bytes> {
+        let mut body = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            body.extend_from_slice(&chunk);
+        }
+        Ok(Bytes::from(body))
+    }
+
+    /// Reads the remainder of the body as text. Invalid UTF-8 sequences are
+    /// replaced.
+    pub async fn text(self) -> Res<String> {
+        let body = self.bytes().await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Fails with `InnerStatus` if the inner response has a 4xx or 5xx
+    /// status, with its body read into the error.
+    pub async fn error_for_status(self) -> Res<OhttpResponse> {
+        let status = self.status;
+        if !status.is_client_error() && !status.is_server_error() {
+            return Ok(self);
+        }
+        let body = self.text().await.unwrap_or_default();
+        Err(OhttpClientError::InnerStatus { status, body })
+    }
+
+    /// Reads the remainder of the body and deserializes it from JSON.
+    pub async fn json<T: DeserializeOwned>(self) -> Res<T> {
+        let body = self.bytes().await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Parses the body as a stream of Server-Sent Events.
+    pub fn sse(self) -> SseStream {
+        SseStream::new(self)
+    }
+
+    /// Turns the body into a stream of decapsulated chunks.
+    pub fn bytes_stream(self) -> impl Stream<Item = Res<Bytes>> {
+        unfold(Some(self), |response| async move {
+            let mut response = response?;
+            match response.chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), Some(response))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::err::OhttpClientError;
+use rand::Rng;
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    StatusCode,
+};
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+/// The delay advised by the `Retry-After` header of a response, given in
+/// seconds or as an HTTP date (RFC 9110, Section 10.2.3). Dates in the past
+/// advise retrying at once.
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Exponential backoff: `base` doubled for every retry after the first,
+/// bounded by `max`, and randomized between half and all of its value if
+/// `jitter` is set.
+pub(crate) fn backoff(base: Duration, max: Duration, jitter: bool, retry: u32) -> Duration {
+    let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+    let delay = base.saturating_mul(factor).min(max);
+    if jitter {
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    } else {
+        delay
+    }
+}
+
+/// How requests that failed because of a transient relay error (a 502, 503
+/// or 504 response, or a failed connection) are retried. Every attempt is
+/// encapsulated afresh, since an OHTTP request context can only be used once.
+#[derive(Debug, Clone)]
+pub struct RelayRetryPolicy {
+    /// Number of retries after the first attempt.
+    pub max_retries: u32,
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+    /// Upper bound on the delay between two attempts.
+    pub max_delay: Duration,
+    /// Randomizes each delay between half and all of its value.
+    pub jitter: bool,
+    /// Longest `Retry-After` delay that is waited for. The request fails if
+    /// the relay asks for a longer delay.
+    pub max_retry_after: Duration,
+    /// Also retries requests with methods that are not idempotent, e.g.
+    /// POST, which the gateway may have processed before the failure. By
+    /// default they are only retried if they could not reach the relay.
+    pub retry_non_idempotent: bool,
+    /// Retries earned by each request, e.g. 0.1 allows retries for about one
+    /// in ten requests once the reserve is spent.
+    pub budget_ratio: f64,
+    /// Retries that can be spent before any are earned.
+    pub budget_reserve: u32,
+}
+
+impl Default for RelayRetryPolicy {
+    fn default() -> Self {
+        RelayRetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+            max_retry_after: Duration::from_secs(30),
+            retry_non_idempotent: false,
+            budget_ratio: 0.1,
+            budget_reserve: 10,
+        }
+    }
+}
+
+impl RelayRetryPolicy {
+    /// Whether a request that failed with this error may succeed if sent again.
+    pub(crate) fn is_transient(e: &OhttpClientError) -> bool {
+        match e {
+            OhttpClientError::RelayHttp { status, .. }
+            | OhttpClientError::GatewayProblem { status, .. } => matches!(
+                *status,
+                StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            OhttpClientError::Http(e) => e.is_connect() || e.is_request(),
+            OhttpClientError::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+            ),
+            _ => false,
+        }
+    }
+
+    /// Whether the request failed before reaching the relay, so that it can
+    /// be sent again whatever its method.
+    fn not_sent(e: &OhttpClientError) -> bool {
+        match e {
+            OhttpClientError::Http(e) => e.is_connect(),
+            OhttpClientError::Io(e) => e.kind() == io::ErrorKind::ConnectionRefused,
+            _ => false,
+        }
+    }
+
+    /// Whether a request that failed with this error is retried, given
+    /// whether its inner method is idempotent.
+    pub(crate) fn should_retry(&self, e: &OhttpClientError, idempotent: bool) -> bool {
+        Self::is_transient(e) && (idempotent || self.retry_non_idempotent || Self::not_sent(e))
+    }
+
+    /// Delay before retry number `retry`, starting at 1, or `None` if the
+    /// relay asked to wait longer than we are willing to.
+    pub(crate) fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        match retry_after {
+            Some(retry_after) if retry_after > self.max_retry_after => None,
+            Some(retry_after) => Some(retry_after),
+            None => Some(backoff(self.base_delay, self.max_delay, self.jitter, retry)),
+        }
+    }
+}
+
+/// Bounds retries to a fraction of all requests, so that retries do not
+/// multiply the load on a relay that is already overloaded.
+#[derive(Debug)]
+struct RetryBudget {
+    tokens: Mutex<f64>,
+    ratio: f64,
+    reserve: f64,
+}
+
+impl RetryBudget {
+    fn deposit(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens + self.ratio).min(self.reserve);
+    }
+
+    fn withdraw(&self) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A retry policy together with the budget shared by all clones of a client.
+#[derive(Debug, Clone)]
+pub(crate) struct RelayRetry {
+    pub(crate) policy: RelayRetryPolicy,
+    budget: Arc<RetryBudget>,
+}
+
+impl RelayRetry {
+    pub(crate) fn new(policy: RelayRetryPolicy) -> RelayRetry {
+        let reserve = f64::from(policy.budget_reserve);
+        let budget = RetryBudget {
+            tokens: Mutex::new(reserve),
+            ratio: policy.budget_ratio,
+            reserve,
+        };
+        RelayRetry {
+            policy,
+            budget: Arc::new(budget),
+        }
+    }
+
+    /// Records a new request, which earns a fraction of a retry.
+    pub(crate) fn record_request(&self) {
+        self.budget.deposit();
+    }
+
+    /// Spends a retry from the budget, returning false if none is left.
+    pub(crate) fn try_spend(&self) -> bool {
+        self.budget.withdraw()
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    err::{OhttpClientError, Res},
+    request::InnerRequestBuilder,
+    response::{BodyStream, OhttpResponse},
+    OhttpClient,
+};
+use bytes::{Buf, Bytes};
+use futures::{future::BoxFuture, StreamExt};
+use http_body::Body;
+use reqwest::header::{HeaderMap, CONTENT_LENGTH, TRANSFER_ENCODING};
+use std::{
+    error::Error,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tower_service::Service;
+
+/// A type-erased response body, as yielded by `OhttpService`.
+pub type BoxBody = http_body::combinators::UnsyncBoxBody<Bytes, OhttpClientError>;
+
+/// The decapsulated body of a response, streamed as it is decapsulated.
+pub struct OhttpBody {
+    stream: BodyStream,
+}
+
+impl OhttpBody {
+    pub fn new(response: OhttpResponse) -> OhttpBody {
+        OhttpBody {
+            stream: Box::pin(response.bytes_stream()),
+        }
+    }
+}
+
+impl Body for OhttpBody {
+    type Data = Bytes;
+    type Error = OhttpClientError;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, OhttpClientError>>> {
+        self.stream.poll_next_unpin(cx)
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, OhttpClientError>> {
+        Poll::Ready(Ok(None))
+    }
+}
+
+/// Reads an `http` request into an inner request. The body is read
+/// completely, since it is encapsulated as a whole.
+pub(crate) async fn inner_request<B>(request: http::Request<B>) -> Res<InnerRequestBuilder>
+where
+    B: Body,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    let (parts, body) = request.into_parts();
+    let mut body = Box::pin(body);
+    let mut content = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let mut chunk = chunk.map_err(|e| {
+            let e: Box<dyn Error + Send + Sync> = e.into();
+            OhttpClientError::InvalidRequest(format!("reading the request body failed: {e}"))
+        })?;
+        while chunk.has_remaining() {
+            let bytes = chunk.chunk();
+            content.extend_from_slice(bytes);
+            let len = bytes.len();
+            chunk.advance(len);
+        }
+    }
+
+    // The body is framed again when the request is encoded
+    let mut headers = parts.headers;
+    headers.remove(CONTENT_LENGTH);
+    headers.remove(TRANSFER_ENCODING);
+    let target = parts
+        .uri
+        .path_and_query()
+        .map_or("/", |target| target.as_str());
+    let mut request = InnerRequestBuilder::new()
+        .method(parts.method)
+        .path(target)
+        .headers(&headers);
+    if !content.is_empty() {
+        request = request.body(content);
+    }
+    Ok(request)
+}
+
+/// Converts a decapsulated response into an `http` response whose body
+/// streams the decapsulated chunks.
+pub(crate) fn http_response(response: OhttpResponse) -> Res<http::Response<OhttpBody>> {
+    let mut builder = http::Response::builder().status(response.status());
+    if let Some(headers) = builder.headers_mut() {
+        headers.extend(response.headers().clone());
+    }
+    builder
+        .body(OhttpBody::new(response))
+        .map_err(|e| OhttpClientError::InvalidRequest(e.to_string()))
+}
+
+/// A `tower::Service` that sends `http` requests obliviously through the relay
+/// at `url` and yields the decapsulated responses, so that the oblivious
+/// transport can be used within tower, axum or tonic middleware stacks.
+/// The path and query of the request URI are sent to the gateway.
+#[derive(Clone)]
+pub struct OhttpService {
+    client: OhttpClient,
+    url: String,
+    outer_headers: HeaderMap,
+}
+
+impl OhttpService {
+    pub fn new(client: OhttpClient, url: &str) -> OhttpService {
+        OhttpService {
+            client,
+            url: url.to_string(),
+            outer_headers: HeaderMap::new(),
+        }
+    }
+
+    /// Adds headers to the outer request to the relay, e.g. an API key.
+    pub fn outer_headers(mut self, outer_headers: &HeaderMap) -> OhttpService {
+        self.outer_headers.extend(outer_headers.clone());
+        self
+    }
+}
+
+impl<B> Service<http::Request<B>> for OhttpService
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn Error + Send + Sync>> + Send,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = OhttpClientError;
+    type Future = BoxFuture<'static, Res<http::Response<BoxBody>>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Res<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move {
+            let request = inner_request(request).await?;
+            let response = service
+                .client
+                .post(&service.url, &request, &service.outer_headers)
+                .await?;
+            Ok(http_response(response)?.map(OhttpBody::boxed_unsync))
+        })
+    }
+}
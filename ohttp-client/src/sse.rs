@@ -0,0 +1,166 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{err::Res, response::OhttpResponse};
+use futures::Stream;
+use futures_util::stream::unfold;
+use std::time::Duration;
+
+/// UTF-8 byte order mark, which may start the stream.
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// An event of a `text/event-stream` response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    /// Type of the event, `message` unless given.
+    pub event: String,
+    /// Data of the event, with the lines of multi-line data joined by `\n`.
+    pub data: String,
+    /// The last event ID set by this or an earlier event, if any.
+    pub id: Option<String>,
+}
+
+/// Parses the decapsulated body of a response as Server-Sent Events, as
+/// specified by the HTML living standard. Events are yielded as soon as the
+/// blank line ending them has been decapsulated.
+pub struct SseStream {
+    response: OhttpResponse,
+    buffer: Vec<u8>,
+    ended: bool,
+    started: bool,
+    event: Option<String>,
+    data: String,
+    last_event_id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl SseStream {
+    pub fn new(response: OhttpResponse) -> SseStream {
+        SseStream {
+            response,
+            buffer: Vec::new(),
+            ended: false,
+            started: false,
+            event: None,
+            data: String::new(),
+            last_event_id: None,
+            retry: None,
+        }
+    }
+
+    /// The response whose body is parsed.
+    pub fn response(&self) -> &OhttpResponse {
+        &self.response
+    }
+
+    /// The last event ID received, to resume the stream from with a
+    /// `Last-Event-ID` header.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    /// The reconnection time last advised by the server with a `retry` field.
+    pub fn retry(&self) -> Option<Duration> {
+        self.retry
+    }
+
+    /// Takes the next complete line out of the buffer, without its end of
+    /// line. A trailing CR is only taken as the end of a line once the next
+    /// byte shows whether it is followed by LF.
+    fn next_line(&mut self) -> Option<Vec<u8>> {
+        let end = self
+            .buffer
+            .iter()
+            .position(|b| *b == b'\r' || *b == b'\n')?;
+        let eol = match (self.buffer[end], self.buffer.get(end + 1)) {
+            (b'\r', Some(b'\n')) => 2,
+            (b'\r', None) if !self.ended => return None,
+            _ => 1,
+        };
+        let line = self.buffer[..end].to_vec();
+        self.buffer.drain(..end + eol);
+        Some(line)
+    }
+
+    /// Processes a line, returning the event it dispatches, if any.
+    fn process_line(&mut self, line: &[u8]) -> Option<SseEvent> {
+        let line = String::from_utf8_lossy(line);
+        if line.is_empty() {
+            let event = self.event.take();
+            if self.data.is_empty() {
+                return None;
+            }
+            let mut data = std::mem::take(&mut self.data);
+            data.pop();
+            return Some(SseEvent {
+                event: event.unwrap_or_else(|| "message".to_string()),
+                data,
+                id: self.last_event_id.clone(),
+            });
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line.as_ref(), ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                self.retry = value.parse().ok().map(Duration::from_millis);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Returns the next event, or `None` at the end of the body. An event
+    /// that is not terminated by a blank line at the end of the body is
+    /// discarded.
+    pub async fn next(&mut self) -> Res<Option<SseEvent>> {
+        loop {
+            // Skip the byte order mark once enough of the body was read
+            if !self.started
+                && (self.ended || self.buffer.len() >= BOM.len() || !BOM.starts_with(&self.buffer))
+            {
+                self.started = true;
+                if self.buffer.starts_with(BOM) {
+                    self.buffer.drain(..BOM.len());
+                }
+            }
+            if self.started {
+                while let Some(line) = self.next_line() {
+                    if let Some(event) = self.process_line(&line) {
+                        return Ok(Some(event));
+                    }
+                }
+            }
+            if self.ended {
+                return Ok(None);
+            }
+            match self.response.chunk().await? {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                None => self.ended = true,
+            }
+        }
+    }
+
+    /// Turns the events into a stream.
+    pub fn into_stream(self) -> impl Stream<Item = Res<SseEvent>> {
+        unfold(Some(self), |events| async move {
+            let mut events = events?;
+            match events.next().await {
+                Ok(Some(event)) => Some((Ok(event), Some(events))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+}
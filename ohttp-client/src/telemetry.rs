@@ -0,0 +1,119 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    err::{OhttpClientError, Res},
+    response::RequestStats,
+};
+use ::metrics::{counter, histogram};
+use std::time::Duration;
+
+#[cfg(feature = "prometheus")]
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+#[cfg(feature = "prometheus")]
+use std::net::SocketAddr;
+
+/// Requests sent by the client, after any retries.
+const REQUESTS: &str = "ohttp_requests_total";
+/// Requests that failed, by error class.
+const REQUEST_ERRORS: &str = "ohttp_request_errors_total";
+/// Time from sending a request to reading the last byte of its response.
+const REQUEST_DURATION: &str = "ohttp_request_duration_seconds";
+/// Time from sending a request to receiving the response headers.
+const RESPONSE_HEADERS_DURATION: &str = "ohttp_response_headers_duration_seconds";
+/// Sizes of the encapsulated requests and responses.
+const REQUEST_SIZE: &str = "ohttp_encapsulated_request_bytes";
+const RESPONSE_SIZE: &str = "ohttp_encapsulated_response_bytes";
+/// Time to fetch the key configuration from a KMS, by outcome.
+const KMS_FETCH_DURATION: &str = "ohttp_kms_fetch_duration_seconds";
+
+/// A label for the kind of failure, with few enough values for a metric.
+fn error_class(e: &OhttpClientError) -> &'static str {
+    match e {
+        OhttpClientError::KmsError(_) => "kms",
+        OhttpClientError::ReceiptVerification(_) | OhttpClientError::KeyReceiptMismatch { .. } => {
+            "receipt"
+        }
+        OhttpClientError::Encapsulation(_) => "encapsulation",
+        OhttpClientError::RelayHttp { status, .. } if status.is_client_error() => "relay_4xx",
+        OhttpClientError::RelayHttp { .. } => "relay_5xx",
+        OhttpClientError::GatewayProblem { .. } => "gateway",
+        OhttpClientError::InnerStatus { .. } => "inner_status",
+        OhttpClientError::Bhttp(_) => "bhttp",
+        OhttpClientError::Io(_) | OhttpClientError::Http(_) => "transport",
+        OhttpClientError::Json(_) | OhttpClientError::HexError(_) => "decoding",
+        OhttpClientError::Attestation(_) => "attestation",
+        OhttpClientError::Discovery(_) => "discovery",
+        OhttpClientError::Config(_) => "config",
+        OhttpClientError::Timeout(_) => "timeout",
+        OhttpClientError::CircuitOpen { .. } => "circuit_open",
+        OhttpClientError::Auth(_) => "auth",
+        OhttpClientError::InvalidRequest(_) => "invalid_request",
+        OhttpClientError::Chat(_) => "chat",
+        OhttpClientError::Cancelled => "cancelled",
+    }
+}
+
+pub(crate) fn record_request<T>(result: &Res<T>) {
+    counter!(REQUESTS).increment(1);
+    if let Err(e) = result {
+        counter!(REQUEST_ERRORS, "class" => error_class(e)).increment(1);
+    }
+}
+
+pub(crate) fn record_request_size(size: usize) {
+    histogram!(REQUEST_SIZE).record(size as f64);
+}
+
+pub(crate) fn record_response_headers(elapsed: Duration) {
+    histogram!(RESPONSE_HEADERS_DURATION).record(elapsed.as_secs_f64());
+}
+
+/// Records the response once its body has been read completely.
+pub(crate) fn record_response(stats: &RequestStats) {
+    histogram!(RESPONSE_SIZE).record(stats.response_size as f64);
+    if let Some(total) = stats.total {
+        histogram!(REQUEST_DURATION).record(total.as_secs_f64());
+    }
+}
+
+pub(crate) fn record_kms_fetch<T>(elapsed: Duration, result: &Res<T>) {
+    let outcome = match result {
+        Ok(_) => "success",
+        Err(e) => error_class(e),
+    };
+    histogram!(KMS_FETCH_DURATION, "outcome" => outcome).record(elapsed.as_secs_f64());
+}
+
+/// Serves the metrics of the client for Prometheus to scrape at
+/// `http://<listen>/metrics`. Must be called once per process, since it
+/// installs the global metrics recorder.
+#[cfg(feature = "prometheus")]
+pub fn install_prometheus_exporter(listen: SocketAddr) -> Res<()> {
+    const SECONDS: &[f64] = &[
+        0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+    ];
+    const BYTES: &[f64] = &[
+        256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0,
+    ];
+    PrometheusBuilder::new()
+        .with_http_listener(listen)
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), SECONDS)
+        .and_then(|builder| {
+            builder.set_buckets_for_metric(Matcher::Suffix("_bytes".to_string()), BYTES)
+        })
+        .and_then(|builder| builder.install())
+        .map_err(|e| OhttpClientError::Config(format!("prometheus exporter: {e}")))
+}
+
+/// Pushes the metrics of the client to the statsd daemon at `host:port`
+/// over UDP, with names prefixed by `prefix` if given. Must be called once
+/// per process, since it installs the global metrics recorder.
+#[cfg(feature = "statsd")]
+pub fn install_statsd_exporter(host: &str, port: u16, prefix: Option<&str>) -> Res<()> {
+    let recorder = metrics_exporter_statsd::StatsdBuilder::from(host, port)
+        .build(prefix)
+        .map_err(|e| OhttpClientError::Config(format!("statsd exporter: {e}")))?;
+    ::metrics::set_global_recorder(recorder)
+        .map_err(|e| OhttpClientError::Config(format!("statsd exporter: {e}")))
+}
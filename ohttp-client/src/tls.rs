@@ -0,0 +1,277 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::err::{OhttpClientError, Res};
+use base64::{engine::general_purpose, Engine};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName,
+};
+use rustls_pemfile::Item;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    iter,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use tracing::{info, trace};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// A client certificate chain and private key used to authenticate outer
+/// TLS connections.
+#[derive(Clone)]
+pub struct ClientIdentity {
+    certs: Vec<Vec<u8>>,
+    key: Vec<u8>,
+    /// The chain and key as PEM, as expected by `reqwest::Identity`.
+    pem: Vec<u8>,
+}
+
+impl ClientIdentity {
+    /// Reads a PEM certificate chain and a PEM private key, which may both be
+    /// in the same buffer.
+    pub fn from_pem(cert_pem: &[u8], key_pem: &[u8]) -> Res<ClientIdentity> {
+        let mut certs = Vec::new();
+        let mut key = None;
+        // A combined buffer is read once, so that its certificates are not
+        // added to the chain twice
+        let pems = iter::once(cert_pem).chain((cert_pem != key_pem).then_some(key_pem));
+        for pem in pems {
+            for item in rustls_pemfile::read_all(&mut &pem[..])? {
+                match item {
+                    Item::X509Certificate(cert) => certs.push(cert),
+                    Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => {
+                        key.get_or_insert(der);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let key = key.ok_or_else(|| {
+            OhttpClientError::Config("no private key found for client identity".to_string())
+        })?;
+        if certs.is_empty() {
+            return Err(OhttpClientError::Config(
+                "no certificate found for client identity".to_string(),
+            ));
+        }
+
+        let mut pem = cert_pem.to_vec();
+        if cert_pem != key_pem {
+            pem.push(b'\n');
+            pem.extend_from_slice(key_pem);
+        }
+        Ok(ClientIdentity { certs, key, pem })
+    }
+
+    /// Reads a DER-encoded PKCS#12 archive holding a certificate chain and
+    /// private key.
+    pub fn from_pkcs12(der: &[u8], password: &str) -> Res<ClientIdentity> {
+        let invalid = |e: p12::ASN1Error| {
+            OhttpClientError::Config(format!("invalid PKCS#12 client identity: {e:?}"))
+        };
+        let pfx = p12::PFX::parse(der).map_err(invalid)?;
+        if !pfx.verify_mac(password) {
+            return Err(OhttpClientError::Config(
+                "wrong PKCS#12 client identity password".to_string(),
+            ));
+        }
+        let certs = pfx.cert_x509_bags(password).map_err(invalid)?;
+        let key = pfx
+            .key_bags(password)
+            .map_err(invalid)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                OhttpClientError::Config("no private key found for client identity".to_string())
+            })?;
+
+        let mut pem = String::new();
+        for cert in &certs {
+            pem += &to_pem("CERTIFICATE", cert);
+        }
+        pem += &to_pem("PRIVATE KEY", &key);
+        ClientIdentity::from_pem(pem.as_bytes(), pem.as_bytes())
+    }
+
+    /// Reads a client identity from a PEM certificate file and a PEM key
+    /// file, or from a PKCS#12 archive if no key file is given and the
+    /// certificate file is not PEM.
+    pub fn from_files(
+        cert: &Path,
+        key: Option<&Path>,
+        password: Option<&str>,
+    ) -> Res<ClientIdentity> {
+        let cert = fs::read(cert)?;
+        match key {
+            Some(key) => ClientIdentity::from_pem(&cert, &fs::read(key)?),
+            None if cert.starts_with(b"-----") => ClientIdentity::from_pem(&cert, &cert),
+            None => ClientIdentity::from_pkcs12(&cert, password.unwrap_or_default()),
+        }
+    }
+
+    pub(crate) fn reqwest_identity(&self) -> Res<reqwest::Identity> {
+        Ok(reqwest::Identity::from_pem(&self.pem)?)
+    }
+}
+
+/// Encodes a DER object as PEM with the given label.
+fn to_pem(label: &str, der: &[u8]) -> String {
+    let encoded = general_purpose::STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {label}-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        pem += std::str::from_utf8(line).unwrap_or_default();
+        pem.push('\n');
+    }
+    pem + &format!("-----END {label}-----\n")
+}
+
+/// Trust settings of the TLS connection to the relay.
+#[derive(Debug, Clone)]
+pub(crate) struct RelayTls {
+    /// PEM files with additional trusted root certificates.
+    pub(crate) ca_certs: Vec<PathBuf>,
+    /// Whether the built-in web PKI roots are trusted.
+    pub(crate) system_roots: bool,
+    /// Base64-encoded SHA-256 digests of the subject public key info of
+    /// which the relay certificate must carry one, optionally prefixed with
+    /// `sha256/`.
+    pub(crate) spki_pins: Vec<String>,
+}
+
+impl Default for RelayTls {
+    fn default() -> Self {
+        RelayTls {
+            ca_certs: Vec::new(),
+            system_roots: true,
+            spki_pins: Vec::new(),
+        }
+    }
+}
+
+impl RelayTls {
+    /// Builds the TLS configuration of the relay connection, or returns
+    /// `None` if the default configuration applies.
+    pub(crate) fn client_config(
+        &self,
+        identity: Option<&ClientIdentity>,
+    ) -> Res<Option<ClientConfig>> {
+        if self.ca_certs.is_empty() && self.system_roots && self.spki_pins.is_empty() {
+            return Ok(None);
+        }
+
+        let mut roots = RootCertStore::empty();
+        if self.system_roots {
+            roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    ta.subject,
+                    ta.spki,
+                    ta.name_constraints,
+                )
+            }));
+        }
+        for path in &self.ca_certs {
+            let mut reader = BufReader::new(File::open(path)?);
+            let certs = rustls_pemfile::certs(&mut reader)?;
+            if certs.is_empty() {
+                return Err(OhttpClientError::Config(format!(
+                    "no certificate found in {}",
+                    path.display()
+                )));
+            }
+            for cert in certs {
+                roots.add(&Certificate(cert)).map_err(|e| {
+                    OhttpClientError::Config(format!("invalid CA in {}: {e}", path.display()))
+                })?;
+            }
+            info!("Trusting relay root certificates from {}", path.display());
+        }
+        if roots.is_empty() {
+            return Err(OhttpClientError::Config(
+                "no trusted root certificate for the relay".to_string(),
+            ));
+        }
+
+        let builder = ClientConfig::builder().with_safe_defaults();
+        let builder = if self.spki_pins.is_empty() {
+            builder.with_root_certificates(roots)
+        } else {
+            let verifier = PinnedVerifier {
+                inner: WebPkiVerifier::new(roots, None),
+                pins: self.decoded_pins()?,
+            };
+            builder.with_custom_certificate_verifier(Arc::new(verifier))
+        };
+        let config = match identity {
+            Some(identity) => {
+                let certs = identity.certs.iter().cloned().map(Certificate).collect();
+                builder
+                    .with_client_auth_cert(certs, PrivateKey(identity.key.clone()))
+                    .map_err(|e| {
+                        OhttpClientError::Config(format!("invalid client identity: {e}"))
+                    })?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        Ok(Some(config))
+    }
+
+    fn decoded_pins(&self) -> Res<Vec<Vec<u8>>> {
+        self.spki_pins
+            .iter()
+            .map(|pin| {
+                let pin = pin.strip_prefix("sha256/").unwrap_or(pin);
+                general_purpose::STANDARD
+                    .decode(pin)
+                    .ok()
+                    .filter(|digest| digest.len() == 32)
+                    .ok_or_else(|| OhttpClientError::Config(format!("invalid SPKI pin {pin}")))
+            })
+            .collect()
+    }
+}
+
+/// Verifies the relay certificate chain as usual, then requires the subject
+/// public key of the relay certificate to match one of the pins.
+struct PinnedVerifier {
+    inner: WebPkiVerifier,
+    pins: Vec<Vec<u8>>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+
+        let (_, cert) = X509Certificate::from_der(&end_entity.0).map_err(|_| {
+            rustls::Error::InvalidCertificate(rustls::CertificateError::BadEncoding)
+        })?;
+        let digest = Sha256::digest(cert.public_key().raw);
+        if self.pins.iter().any(|pin| pin[..] == digest[..]) {
+            trace!("Relay certificate matches SPKI pin");
+            Ok(verified)
+        } else {
+            Err(rustls::Error::General(format!(
+                "relay public key sha256/{} is not pinned",
+                general_purpose::STANDARD.encode(digest)
+            )))
+        }
+    }
+}
@@ -0,0 +1,97 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    err::{OhttpClientError, Res},
+    http::HttpSettings,
+    progress::{self, ProgressCallback},
+    response::BodyStream,
+};
+use futures::{future::BoxFuture, StreamExt};
+use reqwest::{
+    header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE},
+    Client, StatusCode, Version,
+};
+
+/// An encapsulated request to be posted to the relay.
+pub struct OuterRequest {
+    pub url: String,
+    /// Content type of the encapsulated body, e.g. `message/ohttp-req`.
+    pub content_type: &'static str,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+/// The response of the relay, whose encapsulated body is streamed in the
+/// chunks in which it is received.
+pub struct OuterResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: BodyStream,
+}
+
+/// Carries encapsulated requests to the relay and their encapsulated
+/// responses back. The client encapsulates, verifies and decapsulates
+/// independently of the transport, which by default is reqwest over TCP and
+/// TLS. Other transports, e.g. over unix sockets or in memory for tests,
+/// are set with `OhttpClientBuilder::transport`.
+pub trait OuterTransport: Send + Sync {
+    /// Posts the request and returns the response once its headers are
+    /// received. Responses with an error status are returned as well.
+    fn send(&self, request: OuterRequest) -> BoxFuture<'_, Res<OuterResponse>>;
+}
+
+/// The default transport, which connects to the relay with the proxy, TLS
+/// and timeout settings of the client. The reqwest client is built once, so
+/// that its connections to the relay are reused across requests.
+pub(crate) struct ReqwestTransport {
+    client: Client,
+    version: Option<Version>,
+    upload_progress: Option<ProgressCallback>,
+}
+
+impl ReqwestTransport {
+    pub(crate) fn new(
+        http: &HttpSettings,
+        upload_progress: Option<ProgressCallback>,
+    ) -> Res<ReqwestTransport> {
+        Ok(ReqwestTransport {
+            client: http.relay_client()?,
+            version: http.version.request_version(),
+            upload_progress,
+        })
+    }
+}
+
+impl OuterTransport for ReqwestTransport {
+    fn send(&self, request: OuterRequest) -> BoxFuture<'_, Res<OuterResponse>> {
+        Box::pin(async move {
+            let builder = self
+                .client
+                .post(&request.url)
+                .header(CONTENT_TYPE, request.content_type)
+                .headers(request.headers);
+            let builder = match self.version {
+                Some(version) => builder.version(version),
+                None => builder,
+            };
+            let builder = match &self.upload_progress {
+                Some(progress) => builder
+                    .header(CONTENT_LENGTH, request.body.len())
+                    .body(progress::upload_body(request.body, progress.clone())),
+                None => builder.body(request.body),
+            };
+
+            let response = builder.send().await?;
+            Ok(OuterResponse {
+                status: response.status(),
+                headers: response.headers().clone(),
+                body: Box::pin(
+                    response
+                        .bytes_stream()
+                        .map(|chunk| chunk.map_err(OhttpClientError::from)),
+                ),
+            })
+        })
+    }
+}
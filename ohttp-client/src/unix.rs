@@ -0,0 +1,77 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::{
+    err::{OhttpClientError, Res},
+    transport::{OuterRequest, OuterResponse, OuterTransport},
+};
+use futures::{future::BoxFuture, StreamExt};
+use hyper::{
+    client::conn,
+    header::{CONTENT_TYPE, HOST},
+    Body, Request, Uri,
+};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use tokio::net::UnixStream;
+use tracing::{error, trace};
+
+fn connection_error(e: hyper::Error) -> OhttpClientError {
+    OhttpClientError::Io(io::Error::new(io::ErrorKind::Other, e))
+}
+
+/// Sends the encapsulated requests over a unix domain socket, e.g. to a relay
+/// running as a sidecar, without TCP or TLS. The requests are sent with
+/// HTTP/1.1, with the path and host of the relay URL, over a new connection
+/// for each request.
+#[derive(Debug, Clone)]
+pub struct UnixSocketTransport {
+    path: PathBuf,
+}
+
+impl UnixSocketTransport {
+    pub fn new(path: &Path) -> UnixSocketTransport {
+        UnixSocketTransport {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl OuterTransport for UnixSocketTransport {
+    fn send(&self, request: OuterRequest) -> BoxFuture<'_, Res<OuterResponse>> {
+        Box::pin(async move {
+            let url: Uri = request.url.parse().map_err(|_| {
+                OhttpClientError::InvalidRequest(format!("invalid relay URL {}", request.url))
+            })?;
+            let target = url.path_and_query().map_or("/", |target| target.as_str());
+            let host = url.authority().map_or("localhost", |host| host.as_str());
+
+            let stream = UnixStream::connect(&self.path).await?;
+            let (mut sender, connection) =
+                conn::handshake(stream).await.map_err(connection_error)?;
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    error!("{e}");
+                }
+            });
+            trace!("Connected to the relay at {}", self.path.display());
+
+            let mut outer = Request::post(target)
+                .header(HOST, host)
+                .header(CONTENT_TYPE, request.content_type)
+                .body(Body::from(request.body))
+                .map_err(|e| OhttpClientError::InvalidRequest(e.to_string()))?;
+            outer.headers_mut().extend(request.headers);
+
+            let response = sender.send_request(outer).await.map_err(connection_error)?;
+            let (parts, body) = response.into_parts();
+            Ok(OuterResponse {
+                status: parts.status,
+                headers: parts.headers,
+                body: Box::pin(body.map(|chunk| chunk.map_err(connection_error))),
+            })
+        })
+    }
+}
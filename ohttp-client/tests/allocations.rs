@@ -0,0 +1,79 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Counts the bytes allocated while building inner requests with large
+//! bodies, which are shared when a builder is cloned and copied once into
+//! the bhttp message.
+
+use ohttp_client::InnerRequestBuilder;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+const BODY_SIZE: usize = 8 << 20;
+
+/// Allowance for the headers, the target and other small allocations.
+const OVERHEAD: usize = 64 << 10;
+
+/// Counts the bytes allocated by each thread, since tests run in parallel.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count(size: usize) {
+    let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + size));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Runs `f` and returns its result with the bytes it allocated.
+fn allocated_by<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATED.with(Cell::get);
+    let result = f();
+    (result, ALLOCATED.with(Cell::get) - before)
+}
+
+fn request() -> InnerRequestBuilder {
+    InnerRequestBuilder::new()
+        .path("/upload")
+        .typed_body(vec![0x5a; BODY_SIZE], "application/octet-stream")
+}
+
+#[test]
+fn cloning_a_builder_shares_the_body() {
+    let request = request();
+    let (clone, allocated) = allocated_by(|| request.clone());
+    drop(clone);
+    assert!(allocated < OVERHEAD, "cloning allocated {allocated} bytes");
+}
+
+#[test]
+fn building_copies_the_body_once() {
+    let request = request();
+    let (bhttp, allocated) = allocated_by(|| request.build().unwrap());
+    assert!(bhttp.len() > BODY_SIZE);
+    assert!(
+        allocated < BODY_SIZE + OVERHEAD,
+        "building allocated {allocated} bytes for a body of {BODY_SIZE} bytes"
+    );
+}
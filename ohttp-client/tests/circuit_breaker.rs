@@ -0,0 +1,60 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Attempts cut short by the total timeout count as failures of the circuit
+//! breaker. Run with `cargo test -p ohttp-client --features test-util`.
+
+use futures::future::{self, BoxFuture};
+use ohttp_client::{
+    CircuitBreakerPolicy, InnerRequestBuilder, MockGateway, OhttpClientBuilder, OhttpClientError,
+    OuterRequest, OuterResponse, OuterTransport, Res, Timeouts,
+};
+use reqwest::header::HeaderMap;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// A relay that never answers, counting the requests sent to it.
+#[derive(Default)]
+struct HangingTransport {
+    sent: AtomicUsize,
+}
+
+impl OuterTransport for HangingTransport {
+    fn send(&self, _: OuterRequest) -> BoxFuture<'_, Res<OuterResponse>> {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+        Box::pin(future::pending())
+    }
+}
+
+#[tokio::test]
+async fn total_timeout_opens_the_circuit() {
+    let gateway = MockGateway::new().unwrap();
+    let transport = Arc::new(HangingTransport::default());
+    let client = OhttpClientBuilder::new()
+        .config(&Some(gateway.config()))
+        .transport(transport.clone())
+        .timeouts(&Timeouts {
+            total: Some(Duration::from_millis(50)),
+            ..Timeouts::default()
+        })
+        .circuit_breaker(&Some(CircuitBreakerPolicy {
+            failure_threshold: 1,
+            open_duration: Duration::from_secs(60),
+        }))
+        .build()
+        .await
+        .unwrap();
+
+    let url = "https://relay.invalid/score".to_string();
+    let request = InnerRequestBuilder::new();
+    let first = client.post(&url, &request, &HeaderMap::new()).await;
+    assert!(matches!(first, Err(OhttpClientError::Timeout(_))));
+    let second = client.post(&url, &request, &HeaderMap::new()).await;
+    assert!(matches!(second, Err(OhttpClientError::CircuitOpen { .. })));
+    assert_eq!(transport.sent.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,44 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A request of the pool gives up its turn once its response body has been
+//! read, while the response itself is still held. Run with
+//! `cargo test -p ohttp-client --features test-util`.
+
+use ohttp_client::{
+    InnerRequestBuilder, MockGateway, MockResponse, OhttpClientBuilder, OhttpClientPool,
+    OhttpProtocol,
+};
+use reqwest::{header::HeaderMap, StatusCode};
+
+#[tokio::test]
+async fn reading_the_body_releases_the_permit() {
+    let gateway = MockGateway::new()
+        .unwrap()
+        .respond_with(|_| MockResponse::new(StatusCode::OK).body(vec![0x5a; 64 << 10]));
+    let client = gateway
+        .client(OhttpClientBuilder::new().protocol(OhttpProtocol::Chunked))
+        .await
+        .unwrap();
+    let pool = OhttpClientPool::new(client, 1);
+
+    let mut response = pool
+        .spawn_post(
+            "https://relay.invalid/score",
+            InnerRequestBuilder::new(),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(pool.available(), 0);
+
+    let mut read = 0;
+    while let Some(chunk) = response.chunk().await.unwrap() {
+        read += chunk.len();
+    }
+    assert_eq!(read, 64 << 10);
+    assert_eq!(pool.available(), 1);
+    drop(response);
+    assert_eq!(pool.available(), 1);
+}
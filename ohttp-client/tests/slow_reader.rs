@@ -0,0 +1,86 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A chunked response is decapsulated as it is read, so a slow reader
+//! throttles how much of the outer body is pulled from the transport, and
+//! the client holds a bounded part of the response however large it is.
+//! Run with `cargo test -p ohttp-client --features test-util`.
+
+use futures::{future::BoxFuture, StreamExt};
+use ohttp_client::{
+    InnerRequestBuilder, MockGateway, MockResponse, OhttpClientBuilder, OhttpProtocol,
+    OuterRequest, OuterResponse, OuterTransport, Res,
+};
+use reqwest::{header::HeaderMap, StatusCode};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+const BODY_SIZE: usize = 8 << 20;
+
+/// Bound on the encapsulated bytes pulled ahead of the decapsulated bytes
+/// read, a few chunks of the gateway and their overhead.
+const MAX_AHEAD: usize = 64 << 10;
+
+/// Counts the bytes of the outer response bodies pulled from the gateway.
+struct CountingTransport {
+    gateway: MockGateway,
+    pulled: Arc<AtomicUsize>,
+}
+
+impl OuterTransport for CountingTransport {
+    fn send(&self, request: OuterRequest) -> BoxFuture<'_, Res<OuterResponse>> {
+        Box::pin(async move {
+            let mut response = self.gateway.send(request).await?;
+            let pulled = self.pulled.clone();
+            response.body = Box::pin(response.body.inspect(move |chunk| {
+                if let Ok(chunk) = chunk {
+                    pulled.fetch_add(chunk.len(), Ordering::SeqCst);
+                }
+            }));
+            Ok(response)
+        })
+    }
+}
+
+#[tokio::test]
+async fn slow_reader_bounds_the_buffered_response() {
+    let gateway = MockGateway::new()
+        .unwrap()
+        .respond_with(|_| MockResponse::new(StatusCode::OK).body(vec![0x5a; BODY_SIZE]));
+    let pulled = Arc::new(AtomicUsize::new(0));
+    let transport = CountingTransport {
+        gateway: gateway.clone(),
+        pulled: pulled.clone(),
+    };
+    let client = OhttpClientBuilder::new()
+        .protocol(OhttpProtocol::Chunked)
+        .config(&Some(gateway.config()))
+        .transport(Arc::new(transport))
+        .build()
+        .await
+        .unwrap();
+
+    let url = "https://relay.invalid/score".to_string();
+    let mut response = client
+        .post(&url, &InnerRequestBuilder::new(), &HeaderMap::new())
+        .await
+        .unwrap();
+    let mut read = 0;
+    let mut max_ahead = 0;
+    while let Some(chunk) = response.chunk().await.unwrap() {
+        read += chunk.len();
+        max_ahead = max_ahead.max(pulled.load(Ordering::SeqCst) - read);
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    assert_eq!(read, BODY_SIZE);
+    assert!(
+        max_ahead < MAX_AHEAD,
+        "{max_ahead} bytes were pulled ahead of the reader"
+    );
+}
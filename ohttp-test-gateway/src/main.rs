@@ -0,0 +1,174 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A minimal OHTTP gateway for end-to-end tests of the client, the CLI and
+//! the bindings. It terminates standard and chunked OHTTP with a fresh key,
+//! answers every inner request with a JSON echo of it, and publishes its key
+//! configuration in the shape of the KMS `/listpubkeys` response. The receipt
+//! of that key is empty, since receipts can only be issued by the KMS, so
+//! clients are given the key configuration with `--config` instead.
+
+use clap::Parser;
+use hyper::{
+    header::{HeaderValue, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use ohttp_client::{
+    init_logging, LogFormat, MockGateway, MockRequest, MockResponse, OuterRequest, OuterTransport,
+};
+use serde_json::{json, Map, Value};
+use std::{convert::Infallible, fs, net::SocketAddr, path::PathBuf, sync::Arc};
+use tracing::{error, info};
+
+type Res<T> = Result<T, Box<dyn std::error::Error>>;
+
+const OHTTP_REQUEST_CONTENT_TYPE: &str = "message/ohttp-req";
+const OHTTP_CHUNKED_REQUEST_CONTENT_TYPE: &str = "message/ohttp-chunked-req";
+
+#[derive(Parser, Debug)]
+#[command(about = "OHTTP gateway echoing inner requests, for tests")]
+struct Args {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:9443")]
+    listen: SocketAddr,
+
+    /// Attestation token returned in x-attestation-token with every response
+    #[arg(long)]
+    attestation_token: Option<String>,
+
+    /// Write the hex key configuration list to this file, for use with --config
+    #[arg(long)]
+    config_out: Option<PathBuf>,
+
+    /// Format of the log events enabled with RUST_LOG: text or json
+    #[arg(long, default_value = "text")]
+    log_format: LogFormat,
+}
+
+/// Answers an inner request with its method, path, headers and body.
+fn echo(request: &MockRequest) -> MockResponse {
+    let headers: Map<String, Value> = request
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            (name.to_string(), Value::String(value))
+        })
+        .collect();
+    let echo = json!({
+        "method": request.method.as_str(),
+        "path": request.path,
+        "headers": headers,
+        "body": String::from_utf8_lossy(&request.body),
+    });
+    MockResponse::new(StatusCode::OK)
+        .header("content-type", "application/json")
+        .body(echo.to_string())
+}
+
+fn plain(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
+    let mut response = Response::new(body.into());
+    *response.status_mut() = status;
+    response
+}
+
+/// The key configuration in the shape of the KMS `/listpubkeys` response.
+fn list_public_keys(config: &str) -> Response<Body> {
+    let keys = json!([{ "publicKey": config, "receipt": "" }]);
+    let mut response = plain(StatusCode::OK, keys.to_string());
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+async fn decapsulate(gateway: &MockGateway, request: Request<Body>) -> Response<Body> {
+    let content_type = match request.headers().get(CONTENT_TYPE) {
+        Some(value) if value == OHTTP_REQUEST_CONTENT_TYPE => OHTTP_REQUEST_CONTENT_TYPE,
+        Some(value) if value == OHTTP_CHUNKED_REQUEST_CONTENT_TYPE => {
+            OHTTP_CHUNKED_REQUEST_CONTENT_TYPE
+        }
+        _ => {
+            return plain(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "expected an OHTTP request",
+            )
+        }
+    };
+    let url = request.uri().to_string();
+    let (parts, body) = request.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => return plain(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+
+    let outer = OuterRequest {
+        url,
+        content_type,
+        headers: parts.headers,
+        body: body.to_vec(),
+    };
+    match gateway.send(outer).await {
+        Ok(outer) => {
+            let mut response = plain(outer.status, Body::wrap_stream(outer.body));
+            response.headers_mut().extend(outer.headers);
+            response
+        }
+        Err(e) => {
+            error!("{e}");
+            plain(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        }
+    }
+}
+
+async fn handle(
+    gateway: Arc<MockGateway>,
+    config: Arc<String>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    info!("{} {}", request.method(), request.uri());
+    let method = request.method().clone();
+    let list_keys = request.uri().path() == "/listpubkeys";
+    let response = match method {
+        Method::GET if list_keys => list_public_keys(&config),
+        Method::POST => decapsulate(&gateway, request).await,
+        _ => plain(StatusCode::NOT_FOUND, "not found"),
+    };
+    Ok(response)
+}
+
+#[tokio::main]
+async fn main() -> Res<()> {
+    let args = Args::parse();
+    init_logging(args.log_format)?;
+
+    let mut gateway = MockGateway::new()?.respond_with(echo);
+    if let Some(token) = &args.attestation_token {
+        gateway = gateway.attestation_token(token);
+    }
+    let config = hex::encode(&*gateway.config());
+    let builder = Server::try_bind(&args.listen)?;
+
+    // The key configuration is written once the gateway accepts connections
+    if let Some(path) = &args.config_out {
+        fs::write(path, &config)?;
+    }
+    println!("{config}");
+
+    let gateway = Arc::new(gateway);
+    let config = Arc::new(config);
+    let make_service = make_service_fn(move |_| {
+        let gateway = gateway.clone();
+        let config = config.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle(gateway.clone(), config.clone(), request)
+            }))
+        }
+    });
+    let server = builder.serve(make_service);
+    info!("Listening on {}", args.listen);
+    server.await?;
+    Ok(())
+}
@@ -2,69 +2,539 @@
 // Licensed under the MIT License.
 
 use core::str;
-use ohttp_client::OhttpClientBuilder;
-use pyo3::prelude::*;
-use reqwest::Response;
-use std::{collections::HashMap, path::PathBuf, string::String, sync::Arc};
+use ohttp_client::{
+    AttestationClaims, AttestationConfig, CancellationToken, ChatMessage, ChatRequest, HexArg,
+    InnerRequestBuilder, OhttpClientBuilder, OhttpClientError, RelayRetryPolicy, Timeouts,
+};
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyStopAsyncIteration},
+    prelude::*,
+    types::{PyBytes, PyCFunction, PyDict, PyTuple},
+};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Method,
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::PathBuf,
+    string::String,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::Mutex;
 
+mod logging;
+
+/// Turns `future` into a Python awaitable. Cancelling the awaitable, e.g. by
+/// `asyncio.wait_for` or by cancelling its task, cancels `cancellation`,
+/// which aborts the request to the relay and frees its connection.
+fn cancellable_future_into_py<'py, F, T>(
+    py: Python<'py>,
+    cancellation: CancellationToken,
+    future: F,
+) -> PyResult<&'py PyAny>
+where
+    F: Future<Output = PyResult<T>> + Send + 'static,
+    T: IntoPy<PyObject>,
+{
+    // Also cancel if the future is dropped before it completes
+    let guard = cancellation.clone().drop_guard();
+    let awaitable = pyo3_asyncio::tokio::future_into_py(py, async move {
+        let result = future.await;
+        guard.disarm();
+        result
+    })?;
+
+    let on_done = PyCFunction::new_closure(
+        py,
+        None,
+        None,
+        move |args: &PyTuple, _kwargs: Option<&PyDict>| -> PyResult<()> {
+            if args.get_item(0)?.call_method0("cancelled")?.is_true()? {
+                cancellation.cancel();
+            }
+            Ok(())
+        },
+    )?;
+    awaitable.call_method1("add_done_callback", (on_done,))?;
+    Ok(awaitable)
+}
+
 #[pyclass]
 struct OhttpResponse {
-    response: Arc<Mutex<Response>>,
+    status: u16,
+    headers: HashMap<String, String>,
+    attestation: Option<AttestationClaims>,
+    response: Arc<Mutex<ohttp_client::OhttpResponse>>,
+    cancellation: CancellationToken,
+}
+
+impl OhttpResponse {
+    /// Wraps a response, keeping its status and headers so that they can be
+    /// read without waiting for the body.
+    fn new(response: ohttp_client::OhttpResponse, cancellation: CancellationToken) -> Self {
+        OhttpResponse {
+            status: response.status().as_u16(),
+            headers: header_dict(response.headers()),
+            attestation: response.attestation().cloned(),
+            response: Arc::new(Mutex::new(response)),
+            cancellation,
+        }
+    }
 }
 
 #[pymethods]
 impl OhttpResponse {
     fn status(&self) -> u16 {
-        let response = Arc::clone(&self.response);
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        let f = async move { response.lock().await.status().as_u16() };
-        rt.block_on(f)
+        self.status
     }
 
     fn headers(&self) -> HashMap<String, String> {
-        let response = Arc::clone(&self.response);
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        let f = async move {
-            response
-                .lock()
-                .await
-                .headers()
-                .iter()
-                .filter_map(|(key, value)| {
-                    value
-                        .to_str()
-                        .ok()
-                        .map(|value_str| (key.as_str().to_string(), value_str.to_string()))
-                })
-                .collect::<HashMap<String, String>>()
-        };
-        rt.block_on(f)
+        self.headers.clone()
+    }
+
+    /// The attestation token returned by the gateway, if any.
+    fn attestation_token(&self) -> Option<String> {
+        self.attestation
+            .as_ref()
+            .map(|attestation| attestation.token().to_string())
     }
 
+    /// The claims of the attestation token as a dictionary, if any.
+    fn attestation_claims(&self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        self.attestation
+            .as_ref()
+            .map(|attestation| claims_dict(py, attestation))
+            .transpose()
+    }
+
+    /// Whether the attestation token was verified against the issuer given
+    /// to the client.
+    fn attestation_verified(&self) -> bool {
+        self.attestation
+            .as_ref()
+            .is_some_and(|attestation| attestation.verified())
+    }
+
+    /// Returns the next chunk of the body, or `None` at the end of the body.
+    /// Failures to read or check the body are raised rather than ending it.
     fn chunk<'py>(&mut self, py: Python<'py>) -> PyResult<&'py PyAny> {
         let response = Arc::clone(&self.response);
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let chunk = match response.lock().await.chunk().await {
-                Ok(Some(chunk)) => Some(chunk.to_vec()),
-                _ => None,
-            };
-            Ok(chunk)
+        cancellable_future_into_py(py, self.cancellation.clone(), async move {
+            let chunk = response.lock().await.chunk().await.map_err(py_error)?;
+            Ok(chunk.map(|chunk| chunk.to_vec()))
+        })
+    }
+
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Returns the next chunk of the body as bytes, ending the iteration at
+    /// the end of the body.
+    fn __anext__<'py>(&mut self, py: Python<'py>) -> PyResult<Option<&'py PyAny>> {
+        let response = Arc::clone(&self.response);
+        cancellable_future_into_py(py, self.cancellation.clone(), async move {
+            match response.lock().await.chunk().await.map_err(py_error)? {
+                Some(chunk) => Ok(Python::with_gil(|py| {
+                    PyBytes::new(py, &chunk).to_object(py)
+                })),
+                None => Err(PyStopAsyncIteration::new_err(())),
+            }
+        })
+        .map(Some)
+    }
+
+    /// Reads the remainder of the body as bytes.
+    fn read<'py>(&mut self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let response = Arc::clone(&self.response);
+        cancellable_future_into_py(py, self.cancellation.clone(), async move {
+            let body = read_body(response).await?;
+            Ok(Python::with_gil(|py| PyBytes::new(py, &body).to_object(py)))
+        })
+    }
+
+    /// Reads the remainder of the body as text. Invalid UTF-8 sequences are
+    /// replaced.
+    fn text<'py>(&mut self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let response = Arc::clone(&self.response);
+        cancellable_future_into_py(py, self.cancellation.clone(), async move {
+            let body = read_body(response).await?;
+            Ok(String::from_utf8_lossy(&body).into_owned())
+        })
+    }
+
+    /// Reads the remainder of the body and decodes it from JSON.
+    fn json<'py>(&mut self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let response = Arc::clone(&self.response);
+        cancellable_future_into_py(py, self.cancellation.clone(), async move {
+            let body = read_body(response).await?;
+            Python::with_gil(|py| {
+                let value = py
+                    .import("json")?
+                    .call_method1("loads", (PyBytes::new(py, &body),))?;
+                Ok(value.to_object(py))
+            })
+        })
+    }
+}
+
+/// Reads the remainder of the body of a response.
+async fn read_body(response: Arc<Mutex<ohttp_client::OhttpResponse>>) -> PyResult<Vec<u8>> {
+    let mut response = response.lock().await;
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(py_error)? {
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+create_exception!(
+    pyohttp,
+    OhttpError,
+    PyException,
+    "An oblivious request failed."
+);
+create_exception!(
+    pyohttp,
+    KmsError,
+    OhttpError,
+    "The key configuration could not be obtained or verified."
+);
+create_exception!(
+    pyohttp,
+    AttestationError,
+    OhttpError,
+    "The attestation token was missing, invalid or violated the policy."
+);
+create_exception!(
+    pyohttp,
+    RelayError,
+    OhttpError,
+    "The relay could not be reached, timed out or returned an error."
+);
+create_exception!(
+    pyohttp,
+    EncapsulationError,
+    OhttpError,
+    "The request could not be encapsulated or the response decapsulated."
+);
+
+/// Raises an error of the client as the Python exception of its class.
+fn py_error(e: OhttpClientError) -> PyErr {
+    let message = e.to_string();
+    match e {
+        OhttpClientError::KmsError(_)
+        | OhttpClientError::ReceiptVerification(_)
+        | OhttpClientError::KeyReceiptMismatch { .. }
+        | OhttpClientError::Discovery(_) => KmsError::new_err(message),
+        OhttpClientError::Attestation(_) => AttestationError::new_err(message),
+        OhttpClientError::RelayHttp { .. }
+        | OhttpClientError::GatewayProblem { .. }
+        | OhttpClientError::Http(_)
+        | OhttpClientError::Timeout(_)
+        | OhttpClientError::CircuitOpen { .. }
+        | OhttpClientError::Auth(_) => RelayError::new_err(message),
+        OhttpClientError::Encapsulation(_) | OhttpClientError::Bhttp(_) => {
+            EncapsulationError::new_err(message)
+        }
+        _ => OhttpError::new_err(message),
+    }
+}
+
+#[pyclass]
+struct ChatStream {
+    stream: Arc<Mutex<ohttp_client::ChatStream>>,
+    cancellation: CancellationToken,
+}
+
+#[pymethods]
+impl ChatStream {
+    /// Returns the content added by the next chunk of the completion, or
+    /// None at its end.
+    fn delta<'py>(&mut self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let stream = Arc::clone(&self.stream);
+        cancellable_future_into_py(py, self.cancellation.clone(), async move {
+            let mut stream = stream.lock().await;
+            loop {
+                let chunk = stream.next().await.map_err(py_error)?;
+                match chunk {
+                    Some(chunk) => {
+                        if let Some(content) = chunk.content() {
+                            return Ok(Some(content.to_string()));
+                        }
+                    }
+                    None => return Ok(None),
+                }
+            }
+        })
+    }
+}
+
+/// Converts headers into a dictionary for Python, skipping values that are
+/// not visible ASCII.
+fn header_dict(headers: &HeaderMap) -> HashMap<String, String> {
+    headers
+        .iter()
+        .filter_map(|(key, value)| {
+            value
+                .to_str()
+                .ok()
+                .map(|value_str| (key.as_str().to_string(), value_str.to_string()))
+        })
+        .collect()
+}
+
+/// Converts the claims of an attestation token into a dictionary.
+fn claims_dict(py: Python<'_>, attestation: &AttestationClaims) -> PyResult<PyObject> {
+    let claims = serde_json::to_string(attestation.claims())
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{}", e)))?;
+    let claims = py.import("json")?.call_method1("loads", (claims,))?;
+    Ok(claims.to_object(py))
+}
+
+/// Converts headers passed from Python into a header map.
+fn header_map(headers: &HashMap<String, String>) -> PyResult<HeaderMap> {
+    let mut map = HeaderMap::new();
+    for (key, value) in headers {
+        let name = HeaderName::from_bytes(key.as_bytes()).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{}: {}", key, e))
+        })?;
+        let value = HeaderValue::from_str(value).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{}: {}", key, e))
+        })?;
+        map.append(name, value);
+    }
+    Ok(map)
+}
+
+/// Builds the inner request sent by `post`.
+fn inner_request(
+    headers: &HashMap<String, String>,
+    form_fields: &HashMap<String, String>,
+    target_path: &str,
+    method: &str,
+    files: &HashMap<String, PathBuf>,
+    body: Option<&[u8]>,
+) -> PyResult<InnerRequestBuilder> {
+    if body.is_some() && !(form_fields.is_empty() && files.is_empty()) {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "body cannot be combined with form_fields or files",
+        ));
+    }
+    let method = Method::from_bytes(method.as_bytes()).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{}: {}", method, e))
+    })?;
+    let mut request = InnerRequestBuilder::new()
+        .method(method)
+        .path(target_path)
+        .headers(&header_map(headers)?);
+    for (key, value) in form_fields.iter() {
+        // If the value starts with '@', it is treated as a file path.
+        request = match value.strip_prefix('@') {
+            Some(path) => request.file_part(key, path),
+            None => request.form_field(key, value),
+        };
+    }
+    for (key, path) in files.iter() {
+        request = request.file_part(key, path);
+    }
+    if let Some(body) = body {
+        request = request.body(body.to_vec());
+    }
+    Ok(request)
+}
+
+/// Options of the Python clients, given as keyword arguments.
+struct ClientOptions {
+    kms_url: Option<String>,
+    kms_cert: Option<PathBuf>,
+    key_ttl: Option<f64>,
+    config: Option<String>,
+    config_file: Option<PathBuf>,
+    discover_dns: Option<String>,
+    attestation_issuer: Option<String>,
+    attestation_audience: Option<String>,
+    connect_timeout: Option<f64>,
+    total_timeout: Option<f64>,
+    max_retries: Option<u32>,
+    retry_backoff: Option<f64>,
+}
+
+/// The Rust client of a Python client, built on first use so that the key
+/// configuration is obtained and verified once rather than for every request.
+struct ClientCache {
+    kms_url: Option<String>,
+    kms_cert: Option<PathBuf>,
+    config: Option<HexArg>,
+    config_file: Option<PathBuf>,
+    discover_dns: Option<String>,
+    attestation: Option<AttestationConfig>,
+    timeouts: Timeouts,
+    relay_retry: Option<RelayRetryPolicy>,
+    key_ttl: Option<Duration>,
+    client: Mutex<Option<(ohttp_client::OhttpClient, Instant)>>,
+}
+
+impl ClientCache {
+    /// The key configuration is obtained from the KMS at `kms_url` and
+    /// verified with `kms_cert`, or given as hex by `config`, or loaded from
+    /// a `config_file` saved by the CLI with --save-config, whose receipt is
+    /// verified again with `kms_cert`, which is then required, or discovered from the HTTPS
+    /// records of the gateway name `discover_dns`. `key_ttl` is the number of
+    /// seconds after which the key configuration is obtained again, never by
+    /// default. The attestation token of the gateway is requested and
+    /// verified against `attestation_issuer` if given.
+    ///
+    /// `connect_timeout` bounds the time to connect to the relay and the KMS
+    /// and `total_timeout` the time of a whole request, in seconds. Requests
+    /// failing because of a transient relay error are retried up to
+    /// `max_retries` times, waiting `retry_backoff` seconds before the first
+    /// retry and twice as long before each further one, if either is given.
+    fn new(options: ClientOptions) -> PyResult<ClientCache> {
+        let value_error = |e: String| PyErr::new::<pyo3::exceptions::PyValueError, _>(e);
+        let seconds = |name: &str, seconds: Option<f64>| {
+            seconds
+                .map(Duration::try_from_secs_f64)
+                .transpose()
+                .map_err(|e| value_error(format!("{}: {}", name, e)))
+        };
+        let key_ttl = seconds("key_ttl", options.key_ttl)?;
+        let timeouts = Timeouts {
+            connect: seconds("connect_timeout", options.connect_timeout)?,
+            total: seconds("total_timeout", options.total_timeout)?,
+            ..Timeouts::default()
+        };
+        let retry_backoff = seconds("retry_backoff", options.retry_backoff)?;
+        let relay_retry = (options.max_retries.is_some() || retry_backoff.is_some()).then(|| {
+            let default = RelayRetryPolicy::default();
+            RelayRetryPolicy {
+                max_retries: options.max_retries.unwrap_or(default.max_retries),
+                base_delay: retry_backoff.unwrap_or(default.base_delay),
+                ..default
+            }
+        });
+        let config = options
+            .config
+            .map(|config| config.parse::<HexArg>())
+            .transpose()
+            .map_err(|e| value_error(format!("config: {}", e)))?;
+        if options.kms_url.is_none()
+            && config.is_none()
+            && options.config_file.is_none()
+            && options.discover_dns.is_none()
+        {
+            return Err(value_error(
+                "one of kms_url, config, config_file or discover_dns is required".to_string(),
+            ));
+        }
+        let attestation = options.attestation_issuer.map(|issuer| {
+            let config = AttestationConfig::new(&issuer);
+            match &options.attestation_audience {
+                Some(audience) => config.audience(audience),
+                None => config,
+            }
+        });
+        Ok(ClientCache {
+            kms_url: options.kms_url,
+            kms_cert: options.kms_cert,
+            config,
+            config_file: options.config_file,
+            discover_dns: options.discover_dns,
+            attestation,
+            timeouts,
+            relay_retry,
+            key_ttl,
+            client: Mutex::new(None),
         })
     }
+
+    /// Returns the cached client, building it again once it is older than the
+    /// key TTL. The client also fetches new keys by itself when the gateway
+    /// reports that they were rotated.
+    async fn client(&self) -> PyResult<ohttp_client::OhttpClient> {
+        let mut cached = self.client.lock().await;
+        if let Some((client, built)) = cached.as_ref() {
+            if self.key_ttl.map_or(true, |ttl| built.elapsed() < ttl) {
+                return Ok(client.clone());
+            }
+        }
+
+        let client = OhttpClientBuilder::new()
+            .kms_url(&self.kms_url)
+            .kms_cert(&self.kms_cert)
+            .config(&self.config)
+            .saved_config(&self.config_file)
+            .discover_dns(&self.discover_dns)
+            .attestation(&self.attestation)
+            .timeouts(&self.timeouts)
+            .relay_retry(&self.relay_retry)
+            .build()
+            .await
+            .map_err(py_error)?;
+        *cached = Some((client.clone(), Instant::now()));
+        Ok(client)
+    }
 }
 
 #[pyclass]
 struct OhttpClient {
-    kms_url: String,
-    kms_cert: PathBuf,
+    cache: Arc<ClientCache>,
 }
 
 #[pymethods]
 impl OhttpClient {
+    /// See `ClientCache::new` for how the key configuration is obtained.
     #[new]
-    fn new(kms_url: String, kms_cert: PathBuf) -> Self {
-        OhttpClient { kms_url, kms_cert }
+    #[pyo3(signature = (
+        kms_url=None,
+        kms_cert=None,
+        key_ttl=None,
+        config=None,
+        config_file=None,
+        discover_dns=None,
+        attestation_issuer=None,
+        attestation_audience=None,
+        connect_timeout=None,
+        total_timeout=None,
+        max_retries=None,
+        retry_backoff=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        kms_url: Option<String>,
+        kms_cert: Option<PathBuf>,
+        key_ttl: Option<f64>,
+        config: Option<String>,
+        config_file: Option<PathBuf>,
+        discover_dns: Option<String>,
+        attestation_issuer: Option<String>,
+        attestation_audience: Option<String>,
+        connect_timeout: Option<f64>,
+        total_timeout: Option<f64>,
+        max_retries: Option<u32>,
+        retry_backoff: Option<f64>,
+    ) -> PyResult<Self> {
+        let cache = ClientCache::new(ClientOptions {
+            kms_url,
+            kms_cert,
+            key_ttl,
+            config,
+            config_file,
+            discover_dns,
+            attestation_issuer,
+            attestation_audience,
+            connect_timeout,
+            total_timeout,
+            max_retries,
+            retry_backoff,
+        })?;
+        Ok(OhttpClient {
+            cache: Arc::new(cache),
+        })
     }
 
     pub fn post_raw<'py>(
@@ -74,86 +544,344 @@ impl OhttpClient {
         http_request: Vec<u8>,
         py: Python<'py>,
     ) -> PyResult<&'py PyAny> {
-        let kms_url = self.kms_url.clone();
-        let kms_cert = self.kms_cert.clone();
-        let outer_headers = outer_headers
-            .iter()
-            .map(|(key, value)| format!("{}: {}", key, value))
-            .collect();
-
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let client = OhttpClientBuilder::new()
-                .kms_url(&Some(kms_url.clone()))
-                .kms_cert(&Some(kms_cert.clone()))
-                .build()
-                .await
-                .map_err(|e: Box<dyn std::error::Error>| {
-                    PyErr::new::<pyo3::exceptions::PyException, _>(format!("{}", e))
-                })?;
+        let cache = Arc::clone(&self.cache);
+        let outer_headers = header_map(&outer_headers)?;
+        let cancellation = CancellationToken::new();
+
+        cancellable_future_into_py(py, cancellation.clone(), async move {
+            let client = cache.client().await?.with_cancellation(&cancellation);
 
             let response = client
                 .post_raw(&url, &outer_headers, &http_request)
                 .await
-                .map_err(|e: Box<dyn std::error::Error>| {
-                    PyErr::new::<pyo3::exceptions::PyException, _>(format!("{}", e))
-                })?;
+                .map_err(py_error)?;
 
-            Ok(OhttpResponse {
-                response: Arc::new(Mutex::new(response)),
-            })
+            Ok(OhttpResponse::new(response, cancellation))
         })
     }
 
+    /// Sends a request with the given form fields to `target_path` of the
+    /// gateway, with POST unless another `method` is given. `files` maps
+    /// field names to paths of files, which are read into memory in full
+    /// when the request is built, since the request is encapsulated at once.
+    /// Alternatively, `body` is sent as is, with the content type given in
+    /// `headers`.
+    #[pyo3(signature = (
+        url,
+        headers,
+        form_fields,
+        outer_headers,
+        target_path="/",
+        method="POST",
+        files=HashMap::new(),
+        body=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
     pub fn post<'py>(
         &self,
         url: String,
         headers: HashMap<String, String>,
         form_fields: HashMap<String, String>,
         outer_headers: HashMap<String, String>,
+        target_path: &str,
+        method: &str,
+        files: HashMap<String, PathBuf>,
+        body: Option<&PyBytes>,
         py: Python<'py>,
     ) -> PyResult<&'py PyAny> {
-        let kms_url = self.kms_url.clone();
-        let kms_cert = self.kms_cert.clone();
-        let headers = headers
-            .iter()
-            .map(|(key, value)| format!("{}: {}", key, value))
-            .collect();
-        let form_fields = form_fields
-            .iter()
-            .map(|(key, value)| format!("{}={}", key, value))
-            .collect();
-        let outer_headers = outer_headers
-            .iter()
-            .map(|(key, value)| format!("{}: {}", key, value))
-            .collect();
-
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let client = OhttpClientBuilder::new()
-                .kms_url(&Some(kms_url.clone()))
-                .kms_cert(&Some(kms_cert.clone()))
-                .build()
-                .await
-                .map_err(|e: Box<dyn std::error::Error>| {
-                    PyErr::new::<pyo3::exceptions::PyException, _>(format!("{}", e))
-                })?;
+        let cache = Arc::clone(&self.cache);
+        let request = inner_request(
+            &headers,
+            &form_fields,
+            target_path,
+            method,
+            &files,
+            body.map(PyBytes::as_bytes),
+        )?;
+        let outer_headers = header_map(&outer_headers)?;
+        let cancellation = CancellationToken::new();
+
+        cancellable_future_into_py(py, cancellation.clone(), async move {
+            let client = cache.client().await?.with_cancellation(&cancellation);
 
             let response = client
-                .post(&url, "/", &headers, &form_fields, &outer_headers)
+                .post(&url, &request, &outer_headers)
+                .await
+                .map_err(py_error)?;
+
+            Ok(OhttpResponse::new(response, cancellation))
+        })
+    }
+
+    /// Streams a completion from an OpenAI-compatible chat completions
+    /// endpoint. `messages` are dictionaries with a role and a content.
+    #[pyo3(signature = (url, messages, model=None, outer_headers=HashMap::new()))]
+    pub fn chat<'py>(
+        &self,
+        url: String,
+        messages: Vec<HashMap<String, String>>,
+        model: Option<String>,
+        outer_headers: HashMap<String, String>,
+        py: Python<'py>,
+    ) -> PyResult<&'py PyAny> {
+        let cache = Arc::clone(&self.cache);
+        let mut request = ChatRequest::new();
+        if let Some(model) = &model {
+            request = request.model(model);
+        }
+        for message in &messages {
+            let field = |name: &str| {
+                message.get(name).ok_or_else(|| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "message without {}",
+                        name
+                    ))
+                })
+            };
+            request = request.message(ChatMessage::new(field("role")?, field("content")?));
+        }
+        let outer_headers = header_map(&outer_headers)?;
+        let cancellation = CancellationToken::new();
+
+        cancellable_future_into_py(py, cancellation.clone(), async move {
+            let client = cache.client().await?.with_cancellation(&cancellation);
+
+            let stream = client
+                .chat_stream(&url, &request, &outer_headers)
                 .await
-                .map_err(|e: Box<dyn std::error::Error>| {
-                    PyErr::new::<pyo3::exceptions::PyException, _>(format!("{}", e))
-                })?;
+                .map_err(py_error)?;
 
-            Ok(OhttpResponse {
-                response: Arc::new(Mutex::new(response)),
+            Ok(ChatStream {
+                stream: Arc::new(Mutex::new(stream)),
+                cancellation,
             })
         })
     }
 }
 
+/// A response read completely by a `SyncOhttpClient`.
+#[pyclass]
+struct SyncOhttpResponse {
+    status: u16,
+    headers: HashMap<String, String>,
+    attestation: Option<AttestationClaims>,
+    body: Vec<u8>,
+}
+
+impl SyncOhttpResponse {
+    /// Reads the remainder of the body of `response`.
+    async fn read_from(mut response: ohttp_client::OhttpResponse) -> PyResult<SyncOhttpResponse> {
+        let status = response.status().as_u16();
+        let headers = header_dict(response.headers());
+        let attestation = response.attestation().cloned();
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(py_error)? {
+            body.extend_from_slice(&chunk);
+        }
+        Ok(SyncOhttpResponse {
+            status,
+            headers,
+            attestation,
+            body,
+        })
+    }
+}
+
+#[pymethods]
+impl SyncOhttpResponse {
+    fn status(&self) -> u16 {
+        self.status
+    }
+
+    fn headers(&self) -> HashMap<String, String> {
+        self.headers.clone()
+    }
+
+    /// The attestation token returned by the gateway, if any.
+    fn attestation_token(&self) -> Option<String> {
+        self.attestation
+            .as_ref()
+            .map(|attestation| attestation.token().to_string())
+    }
+
+    /// The claims of the attestation token as a dictionary, if any.
+    fn attestation_claims(&self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        self.attestation
+            .as_ref()
+            .map(|attestation| claims_dict(py, attestation))
+            .transpose()
+    }
+
+    /// Whether the attestation token was verified against the issuer given
+    /// to the client.
+    fn attestation_verified(&self) -> bool {
+        self.attestation
+            .as_ref()
+            .is_some_and(|attestation| attestation.verified())
+    }
+
+    /// The body as bytes.
+    fn read<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, &self.body)
+    }
+
+    /// The body as text. Invalid UTF-8 sequences are replaced.
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// The body decoded from JSON.
+    fn json(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let value = py
+            .import("json")?
+            .call_method1("loads", (PyBytes::new(py, &self.body),))?;
+        Ok(value.to_object(py))
+    }
+}
+
+/// Runs `future` to completion on the runtime shared with the asynchronous
+/// client, releasing the GIL meanwhile.
+fn block_on<F, T>(py: Python<'_>, future: F) -> PyResult<T>
+where
+    F: Future<Output = PyResult<T>> + Send,
+    T: Send,
+{
+    py.allow_threads(|| pyo3_asyncio::tokio::get_runtime().block_on(future))
+}
+
+/// A client whose methods block until the response has been read
+/// completely, for callers that do not use asyncio.
+#[pyclass]
+struct SyncOhttpClient {
+    cache: Arc<ClientCache>,
+}
+
+#[pymethods]
+impl SyncOhttpClient {
+    /// See `ClientCache::new` for how the key configuration is obtained.
+    #[new]
+    #[pyo3(signature = (
+        kms_url=None,
+        kms_cert=None,
+        key_ttl=None,
+        config=None,
+        config_file=None,
+        discover_dns=None,
+        attestation_issuer=None,
+        attestation_audience=None,
+        connect_timeout=None,
+        total_timeout=None,
+        max_retries=None,
+        retry_backoff=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        kms_url: Option<String>,
+        kms_cert: Option<PathBuf>,
+        key_ttl: Option<f64>,
+        config: Option<String>,
+        config_file: Option<PathBuf>,
+        discover_dns: Option<String>,
+        attestation_issuer: Option<String>,
+        attestation_audience: Option<String>,
+        connect_timeout: Option<f64>,
+        total_timeout: Option<f64>,
+        max_retries: Option<u32>,
+        retry_backoff: Option<f64>,
+    ) -> PyResult<Self> {
+        let cache = ClientCache::new(ClientOptions {
+            kms_url,
+            kms_cert,
+            key_ttl,
+            config,
+            config_file,
+            discover_dns,
+            attestation_issuer,
+            attestation_audience,
+            connect_timeout,
+            total_timeout,
+            max_retries,
+            retry_backoff,
+        })?;
+        Ok(SyncOhttpClient {
+            cache: Arc::new(cache),
+        })
+    }
+
+    pub fn post_raw(
+        &self,
+        url: String,
+        outer_headers: HashMap<String, String>,
+        http_request: Vec<u8>,
+        py: Python<'_>,
+    ) -> PyResult<SyncOhttpResponse> {
+        let outer_headers = header_map(&outer_headers)?;
+        block_on(py, async {
+            let client = self.cache.client().await?;
+            let response = client
+                .post_raw(&url, &outer_headers, &http_request)
+                .await
+                .map_err(py_error)?;
+            SyncOhttpResponse::read_from(response).await
+        })
+    }
+
+    /// Same as `OhttpClient.post`, but returns the response once read.
+    #[pyo3(signature = (
+        url,
+        headers,
+        form_fields,
+        outer_headers,
+        target_path="/",
+        method="POST",
+        files=HashMap::new(),
+        body=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn post(
+        &self,
+        url: String,
+        headers: HashMap<String, String>,
+        form_fields: HashMap<String, String>,
+        outer_headers: HashMap<String, String>,
+        target_path: &str,
+        method: &str,
+        files: HashMap<String, PathBuf>,
+        body: Option<&PyBytes>,
+        py: Python<'_>,
+    ) -> PyResult<SyncOhttpResponse> {
+        let request = inner_request(
+            &headers,
+            &form_fields,
+            target_path,
+            method,
+            &files,
+            body.map(PyBytes::as_bytes),
+        )?;
+        let outer_headers = header_map(&outer_headers)?;
+        block_on(py, async {
+            let client = self.cache.client().await?;
+            let response = client
+                .post(&url, &request, &outer_headers)
+                .await
+                .map_err(py_error)?;
+            SyncOhttpResponse::read_from(response).await
+        })
+    }
+}
+
 #[pymodule]
-fn pyohttp(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+fn pyohttp(py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<OhttpResponse>()?;
     m.add_class::<OhttpClient>()?;
+    m.add_class::<ChatStream>()?;
+    m.add_class::<SyncOhttpClient>()?;
+    m.add_class::<SyncOhttpResponse>()?;
+    m.add_function(wrap_pyfunction!(logging::init_logging, m)?)?;
+    m.add("OhttpError", py.get_type::<OhttpError>())?;
+    m.add("KmsError", py.get_type::<KmsError>())?;
+    m.add("AttestationError", py.get_type::<AttestationError>())?;
+    m.add("RelayError", py.get_type::<RelayError>())?;
+    m.add("EncapsulationError", py.get_type::<EncapsulationError>())?;
     Ok(())
 }
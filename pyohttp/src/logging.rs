@@ -0,0 +1,99 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+};
+use std::fmt::{self, Write};
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    layer::{Context, Layer, SubscriberExt},
+    Registry,
+};
+
+/// Message and fields of a tracing event, formatted as `message key=value`.
+#[derive(Default)]
+struct EventMessage(String);
+
+impl Visit for EventMessage {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        let _ = if field.name() == "message" {
+            write!(self.0, "{value:?}")
+        } else {
+            write!(self.0, "{}={value:?}", field.name())
+        };
+    }
+}
+
+/// Forwards tracing events as `log` records to the logger installed by
+/// pyo3-log, which hands them to the Python logger named after the module
+/// that emitted them, e.g. `ohttp_client.kms`.
+struct PythonLogLayer;
+
+impl<S: Subscriber> Layer<S> for PythonLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let level = match *metadata.level() {
+            Level::ERROR => log::Level::Error,
+            Level::WARN => log::Level::Warn,
+            Level::INFO => log::Level::Info,
+            Level::DEBUG => log::Level::Debug,
+            Level::TRACE => log::Level::Trace,
+        };
+        let mut message = EventMessage::default();
+        event.record(&mut message);
+        log::logger().log(
+            &log::Record::builder()
+                .level(level)
+                .target(metadata.target())
+                .module_path(metadata.module_path())
+                .file(metadata.file())
+                .line(metadata.line())
+                .args(format_args!("{}", message.0))
+                .build(),
+        );
+    }
+}
+
+/// The error raised when a logger or subscriber was already installed.
+fn already_initialised<E>(_: E) -> PyErr {
+    PyRuntimeError::new_err("logging is already initialised")
+}
+
+/// Parses the name of a Python logging level, e.g. `DEBUG` or `warning`.
+fn level_filter(level: &str) -> PyResult<(LevelFilter, log::LevelFilter)> {
+    match level.to_ascii_uppercase().as_str() {
+        "CRITICAL" | "ERROR" => Ok((LevelFilter::ERROR, log::LevelFilter::Error)),
+        "WARNING" | "WARN" => Ok((LevelFilter::WARN, log::LevelFilter::Warn)),
+        "INFO" => Ok((LevelFilter::INFO, log::LevelFilter::Info)),
+        "DEBUG" => Ok((LevelFilter::DEBUG, log::LevelFilter::Debug)),
+        "TRACE" | "NOTSET" => Ok((LevelFilter::TRACE, log::LevelFilter::Trace)),
+        _ => Err(PyValueError::new_err(format!(
+            "unknown logging level: {level}"
+        ))),
+    }
+}
+
+/// Forwards the diagnostics of the client at `level` or above to the Python
+/// `logging` module, where the level of each module logger still applies.
+/// Logging can only be initialised once per process.
+#[pyfunction]
+#[pyo3(signature = (level="INFO"))]
+pub(crate) fn init_logging(level: &str) -> PyResult<()> {
+    let (filter, log_filter) = level_filter(level)?;
+    pyo3_log::Logger::default()
+        .filter(log_filter)
+        .install()
+        .map_err(already_initialised)?;
+    let subscriber = Registry::default().with(filter).with(PythonLogLayer);
+    tracing::subscriber::set_global_default(subscriber).map_err(already_initialised)?;
+    Ok(())
+}
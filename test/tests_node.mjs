@@ -0,0 +1,65 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Run with TARGET_URI, API_KEY and AUDIO_FILE set, after building the module
+// with `npm run build` in ohttp-client-node.
+
+import assert from 'node:assert/strict'
+import { writeFile } from 'node:fs/promises'
+import { createRequire } from 'node:module'
+import { before, test } from 'node:test'
+
+const require = createRequire(import.meta.url)
+const { OhttpClient } = require('../ohttp-client-node')
+
+const kmsUrl = 'https://accconfinferenceprod.confidential-ledger.azure.com'
+const kmsCert = '/tmp/service_cert.pem'
+const { TARGET_URI: targetUri, API_KEY: apiKey, AUDIO_FILE: audioFile } = process.env
+
+let client
+
+before(async () => {
+  const network = await (await fetch(kmsUrl + '/node/network')).json()
+  await writeFile(kmsCert, network.service_certificate)
+  client = new OhttpClient({ kmsUrl, kmsCert })
+})
+
+test('post', async () => {
+  const response = await client.post(targetUri, {
+    formFields: { file: '@' + audioFile, response_format: 'json' },
+    outerHeaders: { 'api-key': apiKey },
+  })
+  assert.equal(response.status, 200)
+  assert.ok('text' in (await response.json()))
+})
+
+test('async iteration', async () => {
+  const response = await client.post(targetUri, {
+    formFields: { response_format: 'json' },
+    files: { file: audioFile },
+    outerHeaders: { 'api-key': apiKey },
+  })
+  const chunks = []
+  for await (const chunk of response) {
+    chunks.push(chunk)
+  }
+  assert.ok('text' in JSON.parse(Buffer.concat(chunks).toString()))
+})
+
+test('attestation claims', async () => {
+  const response = await client.post(targetUri, {
+    formFields: { file: '@' + audioFile, response_format: 'json' },
+    outerHeaders: { 'api-key': apiKey, 'x-attestation-token': 'true' },
+  })
+  assert.equal(response.status, 200)
+  assert.equal(response.attestationToken, response.headers['x-attestation-token'])
+  assert.ok('iss' in response.attestationClaims)
+  assert.equal(response.attestationVerified, false)
+})
+
+test('invalid api key', async () => {
+  await assert.rejects(client.post(targetUri, {
+    formFields: { file: '@' + audioFile, response_format: 'json' },
+    outerHeaders: { 'api-key': 'invalid_key' },
+  }))
+})
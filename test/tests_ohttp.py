@@ -53,13 +53,101 @@ async def test_attestation_token(ohttp_client, target_uri, api_key, audio_file):
   assert status == 200
 
 
+@pytest.mark.asyncio
+async def test_attestation_claims(ohttp_client, target_uri, api_key, audio_file):
+  form_fields = {"file": "@" + audio_file, "response_format": "json" }
+  outer_headers = { "api-key": api_key, "x-attestation-token": "true" }
+  headers = {}
+  response = await ohttp_client.post(target_uri, headers, form_fields, outer_headers)
+  assert response.status() == 200
+  assert response.attestation_token() == response.headers()["x-attestation-token"]
+  claims = response.attestation_claims()
+  assert isinstance(claims, dict)
+  assert "iss" in claims
+  assert not response.attestation_verified()
+
+
 @pytest.mark.asyncio
 async def test_invalid_api_key(ohttp_client, target_uri, audio_file):
   form_fields = {"file": "@" + audio_file, "response_format": "json" }
   outer_headers = { "api-key": "invalid_key" }
   headers = {}
+  with pytest.raises(pyohttp.RelayError, match="401"):
+    await ohttp_client.post(target_uri, headers, form_fields, outer_headers)
+
+
+@pytest.mark.asyncio
+async def test_cancellation(ohttp_client, target_uri, api_key, audio_file):
+  form_fields = {"file": "@" + audio_file, "response_format": "json" }
+  outer_headers = { "api-key": api_key }
+  headers = {}
+  with pytest.raises(asyncio.TimeoutError):
+    await asyncio.wait_for(ohttp_client.post(target_uri, headers, form_fields, outer_headers), 0.001)
+
+
+@pytest.mark.asyncio
+async def test_async_iteration(ohttp_client, target_uri, api_key, audio_file):
+  form_fields = {"file": "@" + audio_file, "response_format": "json" }
+  outer_headers = { "api-key": api_key }
+  headers = {}
   response = await ohttp_client.post(target_uri, headers, form_fields, outer_headers)
-  status = response.status()
-  for key, value in response.headers().items():
-    print(f"{key}: {value}")
-  assert status == 401
+  body = b""
+  async for chunk in response:
+    body += chunk
+  assert "text" in json.loads(body)
+
+
+@pytest.mark.asyncio
+async def test_files(ohttp_client, target_uri, api_key, audio_file):
+  form_fields = {"response_format": "json" }
+  files = {"file": audio_file}
+  outer_headers = { "api-key": api_key }
+  headers = {}
+  response = await ohttp_client.post(target_uri, headers, form_fields, outer_headers, files=files)
+  assert response.status() == 200
+
+
+@pytest.fixture(scope="module", params=["https://accconfinferenceprod.confidential-ledger.azure.com"])
+def sync_ohttp_client(request):
+  output_file = "/tmp/service_cert.pem"
+  download_kms_certificate(kms_url=request.param, output_file=output_file)
+  return pyohttp.SyncOhttpClient(request.param, output_file)
+
+
+def test_sync_client(sync_ohttp_client, target_uri, api_key, audio_file):
+  form_fields = {"file": "@" + audio_file, "response_format": "json" }
+  outer_headers = { "api-key": api_key }
+  headers = {}
+  response = sync_ohttp_client.post(target_uri, headers, form_fields, outer_headers)
+  assert response.status() == 200
+  assert "text" in response.json()
+
+
+def test_logging(caplog, target_uri, api_key, audio_file):
+  kms_url = "https://accconfinferenceprod.confidential-ledger.azure.com"
+  output_file = "/tmp/service_cert.pem"
+  download_kms_certificate(kms_url=kms_url, output_file=output_file)
+  pyohttp.init_logging("DEBUG")
+  caplog.set_level("DEBUG")
+  client = pyohttp.SyncOhttpClient(kms_url, output_file)
+  form_fields = {"file": "@" + audio_file, "response_format": "json" }
+  outer_headers = { "api-key": api_key }
+  response = client.post(target_uri, {}, form_fields, outer_headers)
+  assert response.status() == 200
+  assert any(record.name.startswith("ohttp_client") for record in caplog.records)
+  with pytest.raises(RuntimeError):
+    pyohttp.init_logging()
+
+
+def test_timeouts(target_uri, api_key, audio_file):
+  kms_url = "https://accconfinferenceprod.confidential-ledger.azure.com"
+  output_file = "/tmp/service_cert.pem"
+  download_kms_certificate(kms_url=kms_url, output_file=output_file)
+  client = pyohttp.SyncOhttpClient(kms_url, output_file, connect_timeout=10, max_retries=2, retry_backoff=0.1)
+  form_fields = {"file": "@" + audio_file, "response_format": "json" }
+  outer_headers = { "api-key": api_key }
+  response = client.post(target_uri, {}, form_fields, outer_headers)
+  assert response.status() == 200
+  client = pyohttp.SyncOhttpClient(kms_url, output_file, total_timeout=0.001)
+  with pytest.raises(pyohttp.OhttpError):
+    client.post(target_uri, {}, form_fields, outer_headers)
@@ -17,6 +17,10 @@ pub enum Error {
     HexError(#[from] FromHexError),
     #[error("base64 decode error: {0}")]
     DecodeError(#[from] DecodeError),
+    #[error("invalid receipt: {0}")]
+    InvalidReceipt(String),
+    #[error("attestation token error: {0}")]
+    Token(String),
 }
 
 pub type Res<T> = Result<T, Error>;
@@ -9,7 +9,11 @@ use openssl::{
 };
 use serde::Deserialize;
 mod err;
-pub use crate::err::{Error, Res};
+mod token;
+pub use crate::{
+    err::{Error, Res},
+    token::{decode_claims, verify_token, Claims, TokenValidation},
+};
 use colored::*;
 use tracing::info;
 
@@ -30,10 +34,25 @@ struct LeafComponents {
 struct Receipt {
     signature: String,
     cert: String,
+    #[serde(default)]
+    node_id: Option<String>,
     leaf_components: LeafComponents,
     proof: Vec<ProofElement>,
 }
 
+/// Claims of a receipt that was successfully verified.
+#[derive(Debug, Clone)]
+pub struct ReceiptClaims {
+    /// Ledger transaction ID (`view.seqno`) in which the key was generated.
+    pub transaction_id: String,
+    /// Hex-encoded digest of the application claims bound to the transaction.
+    pub claims_digest: String,
+    /// Identity of the node that signed the receipt, if reported.
+    pub node_id: Option<String>,
+    /// PEM certificate of the node that signed the receipt.
+    pub node_cert: String,
+}
+
 fn check_certificate(cert: &str, service_cert_pem: &str) -> Res<bool> {
     // Load the endorser certificate from PEM
     let service_cert = X509::from_pem(service_cert_pem.as_bytes())?;
@@ -55,6 +74,17 @@ fn check_certificate(cert: &str, service_cert_pem: &str) -> Res<bool> {
     Ok(result)
 }
 
+/// Extracts the transaction ID from commit evidence of the form
+/// `ce:{view}.{seqno}:{nonce}`.
+fn transaction_id(commit_evidence: &str) -> Res<String> {
+    match commit_evidence.split(':').collect::<Vec<_>>()[..] {
+        ["ce", txid, _] => Ok(txid.to_string()),
+        _ => Err(Error::InvalidReceipt(
+            "malformed commit evidence".to_string(),
+        )),
+    }
+}
+
 fn compute_leaf(leaf_components: LeafComponents) -> Res<Vec<u8>> {
     // Digest commit evidence
     let mut hasher = Hasher::new(MessageDigest::sha256())?;
@@ -131,10 +161,26 @@ fn check_signature(signing_cert: &str, signature: &str, root: &[u8]) -> Res<bool
 
 /// Verify receipt from KMS
 pub fn verify(receipt_str: &str, service_cert: &str) -> Res<bool> {
+    match verify_receipt(receipt_str, service_cert) {
+        Ok(_) => Ok(true),
+        Err(Error::InvalidReceipt(_)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Verify receipt from KMS and return the claims it attests to
+pub fn verify_receipt(receipt_str: &str, service_cert: &str) -> Res<ReceiptClaims> {
     let receipt: Receipt = serde_json::from_str(receipt_str)?;
 
     // Check that the certificate used to sign the receipt is endorsed by the KMS
-    let _ = check_certificate(&receipt.cert, service_cert)?;
+    if !check_certificate(&receipt.cert, service_cert)? {
+        return Err(Error::InvalidReceipt(
+            "receipt signing certificate is not endorsed by the service".to_string(),
+        ));
+    }
+
+    let transaction_id = transaction_id(&receipt.leaf_components.commit_evidence)?;
+    let claims_digest = receipt.leaf_components.claims_digest.clone();
 
     // Compute leaf
     let leaf = compute_leaf(receipt.leaf_components)?;
@@ -147,6 +193,16 @@ pub fn verify(receipt_str: &str, service_cert: &str) -> Res<bool> {
     info!("  {} {}", "root: ".yellow(), hex::encode(&root));
 
     // Check signature over the root
-    let result = check_signature(&receipt.cert, &receipt.signature, &root)?;
-    Ok(result)
+    if !check_signature(&receipt.cert, &receipt.signature, &root)? {
+        return Err(Error::InvalidReceipt(
+            "invalid receipt signature".to_string(),
+        ));
+    }
+
+    Ok(ReceiptClaims {
+        transaction_id,
+        claims_digest,
+        node_id: receipt.node_id,
+        node_cert: receipt.cert,
+    })
 }
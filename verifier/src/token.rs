@@ -0,0 +1,148 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use crate::err::{Error, Res};
+use base64::{engine::general_purpose, Engine};
+use openssl::{
+    bn::BigNum,
+    hash::MessageDigest,
+    pkey::{PKey, Public},
+    rsa::Rsa,
+    sign::Verifier,
+    x509::X509,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tracing::info;
+
+/// Claims of an attestation token, keyed by claim name.
+pub type Claims = Map<String, Value>;
+
+#[derive(Deserialize)]
+struct TokenHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kid: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    x5c: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+/// Expected properties of an attestation token.
+pub struct TokenValidation<'a> {
+    /// Issuer the token must have been issued by.
+    pub issuer: &'a str,
+    /// Audience the token must have been issued for, if any.
+    pub audience: Option<&'a str>,
+    /// Current time in seconds since the Unix epoch.
+    pub now: u64,
+}
+
+/// Splits a compact JWS into its decoded header, decoded claims, signing
+/// input and signature.
+fn split_token(token: &str) -> Res<(TokenHeader, Claims, &str, Vec<u8>)> {
+    let mut parts = token.split('.');
+    let (header, payload, signature) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(header), Some(payload), Some(signature)) if parts.next().is_none() => {
+            (header, payload, signature)
+        }
+        _ => return Err(Error::Token("malformed token".to_string())),
+    };
+
+    let signing_input = &token[..header.len() + 1 + payload.len()];
+    let header: TokenHeader =
+        serde_json::from_slice(&general_purpose::URL_SAFE_NO_PAD.decode(header)?)?;
+    let claims: Claims =
+        serde_json::from_slice(&general_purpose::URL_SAFE_NO_PAD.decode(payload)?)?;
+    let signature = general_purpose::URL_SAFE_NO_PAD.decode(signature)?;
+    Ok((header, claims, signing_input, signature))
+}
+
+/// Decodes the claims of a token without verifying it.
+pub fn decode_claims(token: &str) -> Res<Claims> {
+    let (_, claims, _, _) = split_token(token)?;
+    Ok(claims)
+}
+
+/// Extracts the RSA public key of a JSON web key, either from its modulus and
+/// exponent or from the first certificate of its chain.
+fn public_key(jwk: &Jwk) -> Res<PKey<Public>> {
+    if let (Some(n), Some(e)) = (&jwk.n, &jwk.e) {
+        let n = BigNum::from_slice(&general_purpose::URL_SAFE_NO_PAD.decode(n)?)?;
+        let e = BigNum::from_slice(&general_purpose::URL_SAFE_NO_PAD.decode(e)?)?;
+        return Ok(PKey::from_rsa(Rsa::from_public_components(n, e)?)?);
+    }
+
+    match jwk.x5c.as_ref().and_then(|chain| chain.first()) {
+        Some(cert) => {
+            let cert = X509::from_der(&general_purpose::STANDARD.decode(cert)?)?;
+            Ok(cert.public_key()?)
+        }
+        None => Err(Error::Token("signing key has no public key".to_string())),
+    }
+}
+
+/// Verifies the signature of an RS256 attestation token against the issuer's
+/// JSON web key set, checks its issuer, expiry and audience, and returns the
+/// token claims.
+pub fn verify_token(token: &str, jwks: &str, validation: &TokenValidation) -> Res<Claims> {
+    let (header, claims, signing_input, signature) = split_token(token)?;
+    if header.alg != "RS256" {
+        return Err(Error::Token(format!(
+            "unsupported algorithm {}",
+            header.alg
+        )));
+    }
+
+    // Find the signing key
+    let jwks: JwkSet = serde_json::from_str(jwks)?;
+    let jwk = jwks
+        .keys
+        .iter()
+        .find(|jwk| header.kid.is_none() || jwk.kid == header.kid)
+        .ok_or_else(|| Error::Token("signing key not found".to_string()))?;
+
+    // Check the signature over the header and payload
+    let key = public_key(jwk)?;
+    let mut verifier = Verifier::new(MessageDigest::sha256(), &key)?;
+    verifier.update(signing_input.as_bytes())?;
+    if !verifier.verify(&signature)? {
+        return Err(Error::Token("invalid token signature".to_string()));
+    }
+
+    // Check issuer, expiry and audience
+    if claims.get("iss").and_then(Value::as_str) != Some(validation.issuer) {
+        return Err(Error::Token("unexpected token issuer".to_string()));
+    }
+    match claims.get("exp").and_then(Value::as_u64) {
+        Some(exp) if exp > validation.now => {}
+        _ => return Err(Error::Token("token expired".to_string())),
+    }
+    if let Some(nbf) = claims.get("nbf").and_then(Value::as_u64) {
+        if nbf > validation.now {
+            return Err(Error::Token("token not yet valid".to_string()));
+        }
+    }
+    if let Some(audience) = validation.audience {
+        let matches = match claims.get("aud") {
+            Some(Value::String(aud)) => aud == audience,
+            Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+            _ => false,
+        };
+        if !matches {
+            return Err(Error::Token("unexpected token audience".to_string()));
+        }
+    }
+
+    info!("Attestation token signature and claims are valid.");
+    Ok(claims)
+}